@@ -85,6 +85,7 @@ class _Collection:
         log: bool = False,
     ) -> None:
         self.backend = backend
+        backend.col = self
         self._debugLog = log
         self.db = db
         self.path = db._path
@@ -227,6 +228,21 @@ crt=?, mod=?, scm=?, dty=?, usn=?, ls=?, conf=?""",
         self._markOp(name)
         self._lastSave = time.time()
 
+    def beforeBackendCommand(self) -> None:
+        "Commit changes and release the write lock, so the backend sees them."
+        self.models.flush()
+        self.decks.flush()
+        self.tags.flush()
+        if self.db.mod:
+            self.flush()
+            self.db.mod = False
+        self.db.commit()
+
+    def afterBackendCommand(self) -> None:
+        "Reload what the backend may have changed, and take the lock again."
+        self.load()
+        self.lock()
+
     def autosave(self) -> Optional[bool]:
         "Save if 5 minutes has passed since last save. True if saved."
         if time.time() - self._lastSave > 300:
@@ -111,6 +111,7 @@ threadLocal = threading.local()
 # global defaults
 currentLang: Any = None
 currentTranslation: Any = None
+currentLocaleDir = ""
 
 
 def localTranslation() -> Any:
@@ -135,10 +136,12 @@ def setLang(lang: str, locale_dir: str, local: bool = True) -> None:
     if local:
         threadLocal.currentLang = lang
         threadLocal.currentTranslation = trans
+        threadLocal.currentLocaleDir = locale_dir
     else:
-        global currentLang, currentTranslation
+        global currentLang, currentTranslation, currentLocaleDir
         currentLang = lang
         currentTranslation = trans
+        currentLocaleDir = locale_dir
 
 
 def getLang() -> str:
@@ -149,6 +152,14 @@ def getLang() -> str:
         return currentLang
 
 
+def getLocaleDir() -> str:
+    "Return the folder the language local to this thread was loaded from."
+    if getattr(threadLocal, "currentLang", None):
+        return threadLocal.currentLocaleDir
+    else:
+        return currentLocaleDir
+
+
 def noHint(str) -> str:
     "Remove translation hint from end of string."
     return re.sub(r"(^.*?)( ?\(.+?\))?$", "\\1", str)
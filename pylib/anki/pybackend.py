@@ -7,7 +7,7 @@ A Python implementation of some backend commands.
 Unimplemented commands will be forwarded on to the Rust backend.
 """
 
-from typing import Any, Dict, Tuple
+from typing import Any, Dict
 
 import anki  # pylint: disable=unused-import
 import anki.backend_pb2 as pb
@@ -30,8 +30,8 @@ class PythonBackend:
         kind = input.WhichOneof("value")
         handler = getattr(self, kind, None)
         # run the equivalent of the following, based on available method names
-        # if kind == "deck_tree":
-        #     return pb.BackendOutput(deck_tree=self.deck_tree(input.deck_tree))
+        # if kind == "find_cards":
+        #     return pb.BackendOutput(find_cards=self.find_cards(input.find_cards))
         if handler is not None:
             input_variant = getattr(input, kind)
             output_variant = handler(input_variant)
@@ -42,10 +42,6 @@ class PythonBackend:
             # forward any unknown commands onto the Rust backend
             return self.col.backend._run_command(input)
 
-    def deck_tree(self, _input: pb.Empty) -> pb.DeckTreeOut:
-        native = self.col.sched.deckDueTree()
-        return native_deck_tree_to_proto(native)
-
     def find_cards(self, input: pb.FindCardsIn) -> pb.FindCardsOut:
         cids = self.col.findCards(input.search)
         return pb.FindCardsOut(card_ids=cids)
@@ -59,24 +55,3 @@ class PythonBackend:
                 )
             )
         return pb.BrowserRowsOut(sort_fields=sort_fields)
-
-
-def native_deck_tree_to_proto(native):
-    top = pb.DeckTreeNode(children=[native_deck_node_to_proto(c) for c in native])
-    out = pb.DeckTreeOut(top=top)
-    return out
-
-
-def native_deck_node_to_proto(native: Tuple) -> pb.DeckTreeNode:
-    return pb.DeckTreeNode(
-        # fixme: need to decide whether full list
-        # should be included or just tail element
-        names=[native[0]],
-        deck_id=native[1],
-        review_count=native[2],
-        learn_count=native[3],
-        new_count=native[4],
-        children=[native_deck_node_to_proto(c) for c in native[5]],
-        # fixme: currently hard-coded
-        collapsed=False,
-    )
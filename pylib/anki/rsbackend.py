@@ -2,12 +2,15 @@
 # License: GNU AGPL, version 3 or later; http://www.gnu.org/licenses/agpl.html
 # pylint: skip-file
 
-from typing import Dict, List
+import json
+import locale
+from typing import Any, Dict, List, Optional
 
 import ankirspy  # pytype: disable=import-error
 
 import anki.backend_pb2 as pb
 import anki.buildinfo
+import anki.lang
 
 from .types import AllTemplateReqs
 
@@ -15,6 +18,14 @@ assert ankirspy.buildhash() == anki.buildinfo.buildhash
 
 SchedTimingToday = pb.SchedTimingTodayOut
 
+# commands that don't read or change the collection
+COLLECTION_FREE_COMMANDS = {
+    "plus_one",
+    "template_requirements",
+    "sched_timing_today",
+    "format_timespan",
+}
+
 
 class BackendException(Exception):
     def __str__(self) -> str:
@@ -24,6 +35,12 @@ class BackendException(Exception):
             return f"invalid input: {err.invalid_input.info}"
         elif kind == "template_parse":
             return f"template parse: {err.template_parse.info}"
+        elif kind == "db_error":
+            return f"database error: {err.db_error.info}"
+        elif kind == "not_found":
+            return "not found"
+        elif kind == "search_error":
+            return f"invalid search: {err.search_error.info}"
         else:
             return f"unhandled error: {err}"
 
@@ -46,13 +63,35 @@ def proto_template_reqs_to_legacy(
     return legacy_reqs
 
 
+# the backend translates with the same catalog and decimal point as we do
+def current_translation() -> pb.Translation:
+    return pb.Translation(
+        locale=anki.lang.getLang() or "",
+        locale_dir=anki.lang.getLocaleDir(),
+        decimal_point=locale.localeconv()["decimal_point"],
+    )
+
+
 class RustBackend:
     def __init__(self, path: str):
         self._backend = ankirspy.Backend(path)
+        # the backend uses its own connection to the collection file, so the
+        # collection's changes are committed before each command, and its
+        # state is reloaded after it
+        self.col: Optional["anki.storage._Collection"] = None
 
     def _run_command(self, input: pb.BackendInput) -> pb.BackendOutput:
         input_bytes = input.SerializeToString()
-        output_bytes = self._backend.command(input_bytes)
+        col = self.col
+        kind = input.WhichOneof("value")
+        if not (col and col.db) or kind in COLLECTION_FREE_COMMANDS:
+            output_bytes = self._backend.command(input_bytes)
+        else:
+            col.beforeBackendCommand()
+            try:
+                output_bytes = self._backend.command(input_bytes)
+            finally:
+                col.afterBackendCommand()
         output = pb.BackendOutput()
         output.ParseFromString(output_bytes)
         kind = output.WhichOneof("value")
@@ -88,3 +127,279 @@ class RustBackend:
                 )
             )
         ).sched_timing_today
+
+    def answer_card(
+        self, card_id: int, ease: int, milliseconds_taken: int
+    ) -> pb.AnswerCardOut:
+        return self._run_command(
+            pb.BackendInput(
+                answer_card=pb.AnswerCardIn(
+                    card_id=card_id,
+                    ease=ease,
+                    milliseconds_taken=milliseconds_taken,
+                )
+            )
+        ).answer_card
+
+    def get_queued_cards(self, fetch_limit: int) -> pb.GetQueuedCardsOut:
+        return self._run_command(
+            pb.BackendInput(
+                get_queued_cards=pb.GetQueuedCardsIn(fetch_limit=fetch_limit)
+            )
+        ).get_queued_cards
+
+    def deck_tree(self) -> pb.DeckTreeNode:
+        return self._run_command(pb.BackendInput(deck_tree=pb.Empty())).deck_tree.top
+
+    def due_forecast(self, days: int) -> List[pb.DeckForecast]:
+        return list(
+            self._run_command(
+                pb.BackendInput(due_forecast=pb.DueForecastIn(days=days))
+            ).due_forecast.decks
+        )
+
+    def rebuild_filtered_deck(self, deck_id: int) -> int:
+        return self._run_command(
+            pb.BackendInput(rebuild_filtered_deck=deck_id)
+        ).rebuild_filtered_deck
+
+    def empty_filtered_deck(self, deck_id: int) -> None:
+        self._run_command(pb.BackendInput(empty_filtered_deck=deck_id))
+
+    def simulate_workload(
+        self,
+        deck_id: int,
+        days: int,
+        *,
+        config: Optional[Dict[str, Any]] = None,
+        synthetic_new_cards: int = 0,
+        retention_at_due: float = 0.9,
+        learning_recall: float = 0.9,
+        review_seconds: int = 10,
+        learning_seconds: int = 20,
+        seed: int = 0,
+    ) -> List[pb.SimulatedDay]:
+        return list(
+            self._run_command(
+                pb.BackendInput(
+                    simulate_workload=pb.SimulateWorkloadIn(
+                        deck_id=deck_id,
+                        config_json=json.dumps(config) if config else "",
+                        days=days,
+                        synthetic_new_cards=synthetic_new_cards,
+                        retention_at_due=retention_at_due,
+                        learning_recall=learning_recall,
+                        review_seconds=review_seconds,
+                        learning_seconds=learning_seconds,
+                        seed=seed,
+                    )
+                )
+            ).simulate_workload.days
+        )
+
+    def optimize_deck_options(
+        self, deck_id: int, target_retention: float
+    ) -> pb.OptimizeDeckOptionsOut:
+        return self._run_command(
+            pb.BackendInput(
+                optimize_deck_options=pb.OptimizeDeckOptionsIn(
+                    deck_id=deck_id, target_retention=target_retention
+                )
+            )
+        ).optimize_deck_options
+
+    def card_retrievability(
+        self, card_ids: List[int], target_retention: float = 0.9
+    ) -> List[pb.CardRetrievability]:
+        return list(
+            self._run_command(
+                pb.BackendInput(
+                    card_retrievability=pb.CardRetrievabilityIn(
+                        card_ids=card_ids, target_retention=target_retention
+                    )
+                )
+            ).card_retrievability.cards
+        )
+
+    def leech_candidates(self, search: str, margin: int) -> List[pb.LeechCandidate]:
+        return list(
+            self._run_command(
+                pb.BackendInput(
+                    leech_candidates=pb.LeechCandidatesIn(search=search, margin=margin)
+                )
+            ).leech_candidates.candidates
+        )
+
+    def unbury_cards_in_current_deck(
+        self, mode: int = pb.UnburyCardsInCurrentDeckIn.ALL
+    ) -> int:
+        return self._run_command(
+            pb.BackendInput(
+                unbury_cards_in_current_deck=pb.UnburyCardsInCurrentDeckIn(mode=mode)
+            )
+        ).unbury_cards_in_current_deck
+
+    def suspend_cards(self, card_ids: List[int]) -> int:
+        return self._run_command(
+            pb.BackendInput(suspend_cards=pb.CardIds(ids=card_ids))
+        ).suspend_cards
+
+    def unsuspend_cards(self, card_ids: List[int]) -> int:
+        return self._run_command(
+            pb.BackendInput(unsuspend_cards=pb.CardIds(ids=card_ids))
+        ).unsuspend_cards
+
+    def bury_cards(self, card_ids: List[int], manual: bool = True) -> int:
+        return self._run_command(
+            pb.BackendInput(
+                bury_cards=pb.BuryCardsIn(card_ids=card_ids, manual=manual)
+            )
+        ).bury_cards
+
+    def bury_notes(self, note_ids: List[int]) -> int:
+        return self._run_command(
+            pb.BackendInput(bury_notes=pb.NoteIds(ids=note_ids))
+        ).bury_notes
+
+    def unbury_cards(self, card_ids: List[int]) -> int:
+        return self._run_command(
+            pb.BackendInput(unbury_cards=pb.CardIds(ids=card_ids))
+        ).unbury_cards
+
+    def forget_cards(self, card_ids: List[int]) -> int:
+        return self._run_command(
+            pb.BackendInput(forget_cards=pb.CardIds(ids=card_ids))
+        ).forget_cards
+
+    def reschedule_cards(
+        self, card_ids: List[int], min_days: int, max_days: int
+    ) -> int:
+        return self._run_command(
+            pb.BackendInput(
+                reschedule_cards=pb.RescheduleCardsIn(
+                    card_ids=card_ids, min_days=min_days, max_days=max_days
+                )
+            )
+        ).reschedule_cards
+
+    def reset_cards(self, card_ids: List[int]) -> int:
+        return self._run_command(
+            pb.BackendInput(reset_cards=pb.CardIds(ids=card_ids))
+        ).reset_cards
+
+    def set_due_date(self, card_ids: List[int], days: str) -> int:
+        return self._run_command(
+            pb.BackendInput(set_due_date=pb.SetDueDateIn(card_ids=card_ids, days=days))
+        ).set_due_date
+
+    def sort_cards(
+        self,
+        card_ids: List[int],
+        starting_from: int = 1,
+        step_size: int = 1,
+        randomize: bool = False,
+        shift_existing: bool = False,
+    ) -> pb.PositionRange:
+        return self._run_command(
+            pb.BackendInput(
+                sort_cards=pb.SortCardsIn(
+                    card_ids=card_ids,
+                    starting_from=starting_from,
+                    step_size=step_size,
+                    randomize=randomize,
+                    shift_existing=shift_existing,
+                )
+            )
+        ).sort_cards
+
+    def sort_deck(self, deck_id: int, randomize: bool = False) -> pb.PositionRange:
+        return self._run_command(
+            pb.BackendInput(
+                sort_deck=pb.SortDeckIn(deck_id=deck_id, randomize=randomize)
+            )
+        ).sort_deck
+
+    def change_scheduler_version(
+        self, version: int, dry_run: bool = False
+    ) -> pb.ChangeSchedulerVersionOut:
+        return self._run_command(
+            pb.BackendInput(
+                change_scheduler_version=pb.ChangeSchedulerVersionIn(
+                    version=version, dry_run=dry_run
+                )
+            )
+        ).change_scheduler_version
+
+    def undo_status(self) -> pb.UndoStatus:
+        return self._run_command(pb.BackendInput(undo_status=pb.Empty())).undo_status
+
+    def undo(self) -> str:
+        return self._run_command(pb.BackendInput(undo=pb.Empty())).undo
+
+    def redo(self) -> str:
+        return self._run_command(pb.BackendInput(redo=pb.Empty())).redo
+
+    def format_timespan(
+        self, seconds: float, style: int = pb.FormatTimespanIn.LONG, precision: int = 0
+    ) -> str:
+        return self._run_command(
+            pb.BackendInput(
+                format_timespan=pb.FormatTimespanIn(
+                    seconds=seconds,
+                    style=style,
+                    precision=precision,
+                    translation=current_translation(),
+                )
+            )
+        ).format_timespan
+
+    def answer_button_labels(self, card_id: int) -> List[str]:
+        return list(
+            self._run_command(
+                pb.BackendInput(
+                    answer_button_labels=pb.AnswerButtonLabelsIn(
+                        card_id=card_id, translation=current_translation()
+                    )
+                )
+            ).answer_button_labels.labels
+        )
+
+    def start_study_session(self) -> None:
+        self._run_command(pb.BackendInput(start_study_session=pb.Empty()))
+
+    def end_study_session(self) -> pb.StudySession:
+        return self._run_command(
+            pb.BackendInput(end_study_session=pb.Empty())
+        ).end_study_session
+
+    def study_today(self) -> pb.StudyToday:
+        return self._run_command(pb.BackendInput(study_today=pb.Empty())).study_today
+
+    def study_sessions(self, limit: int) -> List[pb.StudySession]:
+        return list(
+            self._run_command(
+                pb.BackendInput(study_sessions=limit)
+            ).study_sessions.sessions
+        )
+
+    def study_streak(self) -> int:
+        return self._run_command(pb.BackendInput(study_streak=pb.Empty())).study_streak
+
+    def congrats_info(self) -> pb.CongratsInfo:
+        return self._run_command(
+            pb.BackendInput(congrats_info=pb.Empty())
+        ).congrats_info
+
+    def today_stats(self, whole_collection: bool = False) -> pb.TodayStats:
+        return self._run_command(
+            pb.BackendInput(today_stats=whole_collection)
+        ).today_stats
+
+    def collection_stats(
+        self, deck_id: int = 0, period: int = pb.CollectionStatsIn.MONTH
+    ) -> pb.CollectionStats:
+        return self._run_command(
+            pb.BackendInput(
+                collection_stats=pb.CollectionStatsIn(deck_id=deck_id, period=period)
+            )
+        ).collection_stats
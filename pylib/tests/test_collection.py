@@ -1,5 +1,6 @@
 # coding: utf-8
 
+import json
 import os
 import tempfile
 
@@ -147,3 +148,23 @@ def test_furigana():
     m["tmpls"][0]["qfmt"] = "{{kana:}}"
     mm.save(m)
     c.q(reload=True)
+
+
+def test_backend_shares_changes():
+    deck = getEmptyCol()
+    # the backend sees changes that haven't been saved yet
+    n = deck.newNote()
+    n["Front"] = "one"
+    deck.addNote(n)
+    c = n.cards()[0]
+    assert deck.backend.deck_tree().children[0].new_count == 1
+    # and the collection sees the backend's changes
+    deck.backend.answer_card(c.id, 3, 1000)
+    c.load()
+    assert c.queue == 1
+    assert deck.decks.get(1)["newToday"][1] == 1
+    # which saving the collection doesn't overwrite
+    deck.decks.save(deck.decks.get(1))
+    deck.save()
+    decks = json.loads(deck.db.scalar("select decks from col"))
+    assert decks["1"]["newToday"][1] == 1
@@ -1057,6 +1057,30 @@ def test_deckTree():
     assert "new" not in names
 
 
+def test_deckTreeBackend():
+    d = getEmptyCol()
+    # decks and cards that haven't been saved yet
+    f = d.newNote()
+    f["Front"] = "one"
+    f.model()["did"] = d.decks.id("foo::bar")
+    d.addNote(f)
+    d.decks.id("foo::baz")
+    d.reset()
+
+    def native(node):
+        return (
+            node.names[-1],
+            node.deck_id,
+            node.review_count,
+            node.learn_count,
+            node.new_count,
+            tuple(native(c) for c in node.children),
+        )
+
+    tree = tuple(native(c) for c in d.backend.deck_tree().children)
+    assert tree == d.sched.deckDueTree()
+
+
 def test_deckFlow():
     d = getEmptyCol()
     # add a note with default deck
@@ -1,17 +1,34 @@
 use crate::backend_proto as pt;
 use crate::backend_proto::backend_input::Value;
+use crate::collection::{open_collection, Collection};
 use crate::err::{AnkiError, Result};
-use crate::sched::sched_timing_today;
+use crate::i18n::I18n;
+use crate::sched::deck_tree::DeckTreeNode;
+use crate::sched::queue::QueueKind;
+use crate::sched::reposition::PositionRange;
+use crate::sched::session::{DeckStudy, StudySession};
+use crate::sched::siblings::UnburyKind;
+use crate::sched::simulator::{RecallModel, SimulatorOptions};
+use crate::sched::timespan::{TimespanFormat, TimespanStyle};
+use crate::sched::{sched_timing_today, Answer};
+use crate::stats::graphs::{CollectionStats, StatsPeriod};
 use crate::template::{
     without_legacy_template_directives, FieldMap, FieldRequirements, ParsedTemplate,
 };
+use crate::undo::UndoableOp;
 use prost::Message;
-use std::collections::HashSet;
-use std::path::PathBuf;
+use std::collections::{HashMap, HashSet};
+use std::convert::TryFrom;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
 pub struct Backend {
-    #[allow(dead_code)]
     path: PathBuf,
+    /// Opened on first use.
+    col: Option<Collection>,
+    /// The translation the latest request asked for, kept so its catalog is
+    /// only read again when another one is asked for.
+    i18n: Option<(pt::Translation, Arc<I18n>)>,
 }
 
 /// Convert an Anki error to a protobuf error.
@@ -23,6 +40,9 @@ impl std::convert::From<AnkiError> for pt::BackendError {
             AnkiError::TemplateParseError { info } => {
                 V::TemplateParse(pt::TemplateParseError { info })
             }
+            AnkiError::DBError { info } => V::DbError(pt::DbError { info }),
+            AnkiError::NotFound => V::NotFound(pt::Empty {}),
+            AnkiError::SearchError { info } => V::SearchError(pt::SearchError { info }),
         };
 
         pt::BackendError { value: Some(value) }
@@ -38,7 +58,11 @@ impl std::convert::From<AnkiError> for pt::backend_output::Value {
 
 impl Backend {
     pub fn new<P: Into<PathBuf>>(path: P) -> Backend {
-        Backend { path: path.into() }
+        Backend {
+            path: path.into(),
+            col: None,
+            i18n: None,
+        }
     }
 
     /// Decode a request, process it, and return the encoded result.
@@ -63,7 +87,7 @@ impl Backend {
         buf
     }
 
-    fn run_command(&self, input: pt::BackendInput) -> pt::BackendOutput {
+    fn run_command(&mut self, input: pt::BackendInput) -> pt::BackendOutput {
         let oval = if let Some(ival) = input.value {
             match self.run_command_inner(ival) {
                 Ok(output) => output,
@@ -77,7 +101,7 @@ impl Backend {
     }
 
     fn run_command_inner(
-        &self,
+        &mut self,
         ival: pt::backend_input::Value,
     ) -> Result<pt::backend_output::Value> {
         use pt::backend_output::Value as OValue;
@@ -89,12 +113,169 @@ impl Backend {
             Value::SchedTimingToday(input) => {
                 OValue::SchedTimingToday(self.sched_timing_today(input))
             }
-            Value::DeckTree(_) => todo!(),
+            Value::DeckTree(_) => OValue::DeckTree(self.deck_tree()?),
             Value::FindCards(_) => todo!(),
             Value::BrowserRows(_) => todo!(),
+            Value::AnswerCard(input) => OValue::AnswerCard(self.answer_card(input)?),
+            Value::GetQueuedCards(input) => OValue::GetQueuedCards(self.get_queued_cards(input)?),
+            Value::DueForecast(input) => OValue::DueForecast(self.due_forecast(input)?),
+            Value::RebuildFilteredDeck(did) => {
+                OValue::RebuildFilteredDeck(self.col()?.rebuild_filtered_deck(did)?)
+            }
+            Value::EmptyFilteredDeck(did) => {
+                self.col()?.empty_filtered_deck(did)?;
+                OValue::EmptyFilteredDeck(pt::Empty {})
+            }
+            Value::SimulateWorkload(input) => {
+                OValue::SimulateWorkload(self.simulate_workload(input)?)
+            }
+            Value::OptimizeDeckOptions(input) => {
+                OValue::OptimizeDeckOptions(self.optimize_deck_options(input)?)
+            }
+            Value::CardRetrievability(input) => {
+                OValue::CardRetrievability(self.card_retrievability(input)?)
+            }
+            Value::LeechCandidates(input) => OValue::LeechCandidates(self.leech_candidates(input)?),
+            Value::UnburyCardsInCurrentDeck(input) => {
+                OValue::UnburyCardsInCurrentDeck(self.unbury_cards_in_current_deck(input)?)
+            }
+            Value::SuspendCards(input) => {
+                OValue::SuspendCards(self.col()?.suspend_cards(&input.ids)?)
+            }
+            Value::UnsuspendCards(input) => {
+                OValue::UnsuspendCards(self.col()?.unsuspend_cards(&input.ids)?)
+            }
+            Value::BuryCards(input) => {
+                OValue::BuryCards(self.col()?.bury_cards(&input.card_ids, input.manual)?)
+            }
+            Value::BuryNotes(input) => OValue::BuryNotes(self.col()?.bury_notes(&input.ids)?),
+            Value::UnburyCards(input) => OValue::UnburyCards(self.col()?.unbury_cards(&input.ids)?),
+            Value::ForgetCards(input) => OValue::ForgetCards(self.col()?.forget_cards(&input.ids)?),
+            Value::RescheduleCards(input) => OValue::RescheduleCards(
+                self.col()?
+                    .reschedule_cards(&input.card_ids, input.min_days, input.max_days)?,
+            ),
+            Value::ResetCards(input) => OValue::ResetCards(self.col()?.reset_cards(&input.ids)?),
+            Value::SetDueDate(input) => {
+                OValue::SetDueDate(self.col()?.set_due_date(&input.card_ids, &input.days)?)
+            }
+            Value::SortCards(input) => OValue::SortCards(position_range(self.col()?.sort_cards(
+                &input.card_ids,
+                input.starting_from,
+                input.step_size,
+                input.randomize,
+                input.shift_existing,
+            )?)),
+            Value::SortDeck(input) => OValue::SortDeck(position_range(
+                self.col()?.sort_deck(input.deck_id, input.randomize)?,
+            )),
+            Value::ChangeSchedulerVersion(input) => {
+                OValue::ChangeSchedulerVersion(self.change_scheduler_version(input)?)
+            }
+            Value::UndoStatus(_) => {
+                let status = self.col()?.undo_status();
+                OValue::UndoStatus(pt::UndoStatus {
+                    undo: op_name(status.undo),
+                    redo: op_name(status.redo),
+                })
+            }
+            Value::Undo(_) => OValue::Undo(op_name(self.col()?.undo()?)),
+            Value::Redo(_) => OValue::Redo(op_name(self.col()?.redo()?)),
+            Value::FormatTimespan(input) => {
+                let i18n = self.i18n(input.translation.clone())?;
+                OValue::FormatTimespan(format_timespan(input, &i18n)?)
+            }
+            Value::StartStudySession(_) => {
+                self.col()?.start_study_session()?;
+                OValue::StartStudySession(pt::Empty {})
+            }
+            Value::EndStudySession(_) => OValue::EndStudySession(
+                self.col()?
+                    .end_study_session()
+                    .map(study_session_to_proto)
+                    .unwrap_or_default(),
+            ),
+            Value::StudyToday(_) => {
+                let today = self.col()?.study_today()?;
+                OValue::StudyToday(pt::StudyToday {
+                    sessions: today.sessions,
+                    reps: today.reps,
+                    milliseconds: today.millis,
+                    decks: deck_study_to_proto(today.decks),
+                })
+            }
+            Value::StudySessions(limit) => OValue::StudySessions(pt::StudySessionsOut {
+                sessions: self
+                    .col()?
+                    .study_sessions(limit)?
+                    .into_iter()
+                    .map(study_session_to_proto)
+                    .collect(),
+            }),
+            Value::StudyStreak(_) => OValue::StudyStreak(self.col()?.study_streak()?),
+            Value::CongratsInfo(_) => {
+                let info = self.col()?.congrats_info()?;
+                OValue::CongratsInfo(pt::CongratsInfo {
+                    deck_id: info.deck_id,
+                    is_filtered_deck: info.is_filtered_deck,
+                    next_learning_due: info.next_learning_due.unwrap_or_default(),
+                    reviews_over_limit: info.reviews_over_limit,
+                    new_over_limit: info.new_over_limit,
+                    have_sibling_buried: info.have_sibling_buried,
+                    have_user_buried: info.have_user_buried,
+                    have_suspended: info.have_suspended,
+                })
+            }
+            Value::TodayStats(whole_collection) => {
+                let stats = self.col()?.today_stats(whole_collection)?;
+                OValue::TodayStats(pt::TodayStats {
+                    answer_count: stats.answer_count,
+                    answer_milliseconds: stats.answer_millis,
+                    again_count: stats.again_count,
+                    learn_count: stats.learn_count,
+                    review_count: stats.review_count,
+                    relearn_count: stats.relearn_count,
+                    filtered_count: stats.filtered_count,
+                    mature_count: stats.mature_count,
+                    mature_correct: stats.mature_correct,
+                })
+            }
+            Value::CollectionStats(input) => OValue::CollectionStats(self.collection_stats(input)?),
+            Value::AnswerButtonLabels(input) => {
+                let i18n = self.i18n(input.translation)?;
+                OValue::AnswerButtonLabels(pt::AnswerButtonLabelsOut {
+                    labels: self.col()?.answer_button_labels(input.card_id, &i18n)?,
+                })
+            }
         })
     }
 
+    /// The translation for `translation`, reading its catalog unless it is
+    /// the one used last.
+    fn i18n(&mut self, translation: Option<pt::Translation>) -> Result<Arc<I18n>> {
+        let translation = translation.unwrap_or_default();
+        if let Some((last, i18n)) = &self.i18n {
+            if *last == translation {
+                return Ok(i18n.clone());
+            }
+        }
+        let i18n = Arc::new(I18n::load(
+            Path::new(&translation.locale_dir),
+            &translation.locale,
+            &translation.decimal_point,
+        )?);
+        self.i18n = Some((translation, i18n.clone()));
+        Ok(i18n)
+    }
+
+    /// The collection, opening it if necessary.
+    fn col(&mut self) -> Result<&mut Collection> {
+        if self.col.is_none() {
+            self.col = Some(open_collection(&self.path, false)?);
+        }
+        Ok(self.col.as_mut().unwrap())
+    }
+
     fn plus_one(&self, input: pt::PlusOneIn) -> Result<pt::PlusOneOut> {
         let num = input.num + 1;
         Ok(pt::PlusOneOut { num })
@@ -153,6 +334,350 @@ impl Backend {
             next_day_at: today.next_day_at,
         }
     }
+
+    fn answer_card(&mut self, input: pt::AnswerCardIn) -> Result<pt::AnswerCardOut> {
+        let answer = Answer::from_ease(input.ease as u8)
+            .ok_or_else(|| AnkiError::invalid_input("invalid ease"))?;
+        let outcome = self
+            .col()?
+            .answer_card(input.card_id, answer, input.milliseconds_taken)?;
+        let fuzzed = outcome.fuzzed_interval.unwrap_or_default();
+        let timebox = outcome.timebox_reached;
+        Ok(pt::AnswerCardOut {
+            leech: outcome.leech,
+            buried_sibling_ids: outcome.buried_sibling_ids,
+            interval: fuzzed.interval,
+            fuzz_min: fuzzed.min,
+            fuzz_max: fuzzed.max,
+            timebox_reached: timebox.is_some(),
+            timebox_reps: timebox.map(|t| t.reps).unwrap_or_default(),
+            timebox_secs: timebox.map(|t| t.secs).unwrap_or_default(),
+        })
+    }
+
+    fn get_queued_cards(&mut self, input: pt::GetQueuedCardsIn) -> Result<pt::GetQueuedCardsOut> {
+        use pt::queued_card::Kind;
+        let queued = self.col()?.get_queued_cards(input.fetch_limit as usize)?;
+        let cards = queued
+            .cards
+            .into_iter()
+            .map(|card| {
+                let kind = match card.kind {
+                    QueueKind::New => Kind::New,
+                    QueueKind::Learning => Kind::Learning,
+                    QueueKind::Review => Kind::Review,
+                };
+                pt::QueuedCard {
+                    card_id: card.id,
+                    kind: kind as i32,
+                }
+            })
+            .collect();
+        Ok(pt::GetQueuedCardsOut {
+            cards,
+            new_count: queued.new_count,
+            learning_count: queued.learning_count,
+            review_count: queued.review_count,
+        })
+    }
+
+    fn due_forecast(&mut self, input: pt::DueForecastIn) -> Result<pt::DueForecastOut> {
+        let forecast = self.col()?.due_forecast(input.days)?;
+        let decks = forecast
+            .into_iter()
+            .map(|deck| pt::DeckForecast {
+                deck_id: deck.deck_id,
+                days: deck
+                    .days
+                    .into_iter()
+                    .map(|day| pt::ForecastDay {
+                        review_count: day.review_count,
+                        learn_count: day.learn_count,
+                        new_count: day.new_count,
+                    })
+                    .collect(),
+            })
+            .collect();
+        Ok(pt::DueForecastOut { decks })
+    }
+
+    fn simulate_workload(
+        &mut self,
+        input: pt::SimulateWorkloadIn,
+    ) -> Result<pt::SimulateWorkloadOut> {
+        use pt::simulate_workload_in::RecallModel as PRecallModel;
+        let conf = if input.config_json.is_empty() {
+            None
+        } else {
+            Some(
+                serde_json::from_str(&input.config_json)
+                    .map_err(|e| AnkiError::invalid_input(format!("invalid config: {}", e)))?,
+            )
+        };
+        let recall = match input.recall_model {
+            Some(PRecallModel::ConstantRecall(prob)) => RecallModel::Constant(prob),
+            Some(PRecallModel::RetentionAtDue(retention_at_due)) => {
+                RecallModel::Exponential { retention_at_due }
+            }
+            None => return Err(AnkiError::invalid_input("no recall model")),
+        };
+        let opts = SimulatorOptions {
+            days: input.days,
+            recall,
+            learning_recall: input.learning_recall,
+            review_secs: input.review_seconds,
+            learning_secs: input.learning_seconds,
+            seed: input.seed,
+        };
+        let days = self
+            .col()?
+            .simulate_workload(input.deck_id, conf, input.synthetic_new_cards, &opts)?
+            .into_iter()
+            .map(|day| pt::SimulatedDay {
+                review_count: day.review_count,
+                learn_count: day.learn_count,
+                new_count: day.new_count,
+                seconds: day.seconds,
+                expected_retention: day.expected_retention,
+            })
+            .collect();
+        Ok(pt::SimulateWorkloadOut { days })
+    }
+
+    fn optimize_deck_options(
+        &mut self,
+        input: pt::OptimizeDeckOptionsIn,
+    ) -> Result<pt::OptimizeDeckOptionsOut> {
+        let opts = self
+            .col()?
+            .optimize_deck_options(input.deck_id, input.target_retention)?;
+        Ok(pt::OptimizeDeckOptionsOut {
+            review_count: opts.review_count,
+            current_interval_modifier: opts.current_interval_modifier,
+            interval_modifier: opts.interval_modifier,
+            current_starting_ease: u32::from(opts.current_starting_ease),
+            starting_ease: u32::from(opts.starting_ease),
+            retention_before: opts.retention_before,
+            retention_after: opts.retention_after,
+        })
+    }
+
+    fn card_retrievability(
+        &mut self,
+        input: pt::CardRetrievabilityIn,
+    ) -> Result<pt::CardRetrievabilityOut> {
+        let cards = self
+            .col()?
+            .card_retrievability(&input.card_ids, input.target_retention)?
+            .into_iter()
+            .map(|card| pt::CardRetrievability {
+                card_id: card.card_id,
+                recall: card.recall,
+                below_target_day: card.below_target_day,
+            })
+            .collect();
+        Ok(pt::CardRetrievabilityOut { cards })
+    }
+
+    fn leech_candidates(&mut self, input: pt::LeechCandidatesIn) -> Result<pt::LeechCandidatesOut> {
+        let candidates = self
+            .col()?
+            .leech_candidates(&input.search, input.margin)?
+            .into_iter()
+            .map(|candidate| pt::LeechCandidate {
+                card_id: candidate.card_id,
+                note_id: candidate.note_id,
+                lapses: candidate.lapses,
+                lapses_until_leech: candidate.lapses_until_leech,
+                lapse_times: candidate.lapse_times,
+            })
+            .collect();
+        Ok(pt::LeechCandidatesOut { candidates })
+    }
+
+    fn change_scheduler_version(
+        &mut self,
+        input: pt::ChangeSchedulerVersionIn,
+    ) -> Result<pt::ChangeSchedulerVersionOut> {
+        let version = u8::try_from(input.version)
+            .map_err(|_| AnkiError::invalid_input("unsupported scheduler version"))?;
+        let change = self
+            .col()?
+            .change_scheduler_version(version, input.dry_run)?;
+        Ok(pt::ChangeSchedulerVersionOut {
+            filtered_cards_returned: change.filtered_cards_returned,
+            relearning_cards_restored: change.relearning_cards_restored,
+            learning_cards_forgotten: change.learning_cards_forgotten,
+            suspended_learning_reset: change.suspended_learning_reset,
+            buried_cards_moved: change.buried_cards_moved,
+            revlog_entries_remapped: change.revlog_entries_remapped,
+        })
+    }
+
+    fn unbury_cards_in_current_deck(
+        &mut self,
+        input: pt::UnburyCardsInCurrentDeckIn,
+    ) -> Result<u32> {
+        use pt::unbury_cards_in_current_deck_in::Mode;
+        let kind = match Mode::from_i32(input.mode) {
+            Some(Mode::All) => UnburyKind::All,
+            Some(Mode::UserOnly) => UnburyKind::User,
+            Some(Mode::SiblingsOnly) => UnburyKind::Siblings,
+            None => return Err(AnkiError::invalid_input("unknown unbury mode")),
+        };
+        self.col()?.unbury_cards_in_current_deck(kind)
+    }
+
+    fn collection_stats(&mut self, input: pt::CollectionStatsIn) -> Result<pt::CollectionStats> {
+        use pt::collection_stats_in::Period;
+        let period = match Period::from_i32(input.period) {
+            Some(Period::Month) => StatsPeriod::Month,
+            Some(Period::Year) => StatsPeriod::Year,
+            Some(Period::Life) => StatsPeriod::Life,
+            None => return Err(AnkiError::invalid_input("unknown stats period")),
+        };
+        let deck_id = if input.deck_id == 0 {
+            None
+        } else {
+            Some(input.deck_id)
+        };
+        let stats = self.col()?.collection_stats(deck_id, period)?;
+        Ok(collection_stats_to_proto(stats))
+    }
+
+    fn deck_tree(&mut self) -> Result<pt::DeckTreeOut> {
+        let top = self.col()?.deck_tree()?;
+        Ok(pt::DeckTreeOut {
+            top: Some(deck_tree_node_to_proto(top)),
+        })
+    }
+}
+
+fn deck_tree_node_to_proto(node: DeckTreeNode) -> pt::DeckTreeNode {
+    pt::DeckTreeNode {
+        names: node.names,
+        deck_id: node.deck_id,
+        review_count: node.review_count,
+        learn_count: node.learn_count,
+        new_count: node.new_count,
+        children: node
+            .children
+            .into_iter()
+            .map(deck_tree_node_to_proto)
+            .collect(),
+        collapsed: node.collapsed,
+    }
+}
+
+fn position_range(range: Option<PositionRange>) -> pt::PositionRange {
+    let range = range.unwrap_or(PositionRange { first: 0, last: 0 });
+    pt::PositionRange {
+        first: range.first,
+        last: range.last,
+    }
+}
+
+fn format_timespan(input: pt::FormatTimespanIn, i18n: &I18n) -> Result<String> {
+    use pt::format_timespan_in::Style;
+    let style = match Style::from_i32(input.style) {
+        Some(Style::Short) => TimespanStyle::Short,
+        Some(Style::Long) => TimespanStyle::Long,
+        Some(Style::FromNow) => TimespanStyle::FromNow,
+        None => return Err(AnkiError::invalid_input("unknown timespan style")),
+    };
+    let format = TimespanFormat {
+        style,
+        precision: input.precision,
+        ..Default::default()
+    };
+    Ok(format.format_secs(input.seconds, i18n))
+}
+
+fn deck_study_to_proto(decks: HashMap<i64, DeckStudy>) -> Vec<pt::DeckStudy> {
+    let mut decks: Vec<_> = decks
+        .into_iter()
+        .map(|(deck_id, study)| pt::DeckStudy {
+            deck_id,
+            reps: study.reps,
+            milliseconds: study.millis,
+        })
+        .collect();
+    decks.sort_unstable_by_key(|deck| deck.deck_id);
+    decks
+}
+
+fn study_session_to_proto(session: StudySession) -> pt::StudySession {
+    pt::StudySession {
+        started_at: session.started_at,
+        last_answer_at: session.last_answer_at,
+        day: session.day,
+        reps: session.reps,
+        milliseconds: session.millis,
+        decks: deck_study_to_proto(session.decks),
+    }
+}
+
+fn collection_stats_to_proto(stats: CollectionStats) -> pt::CollectionStats {
+    let review = stats.review_cards;
+    pt::CollectionStats {
+        bucket_days: stats.bucket_days,
+        reviews: stats
+            .reviews
+            .iter()
+            .map(|bucket| pt::ReviewBucket {
+                learn_count: bucket.learn_count,
+                young_count: bucket.young_count,
+                mature_count: bucket.mature_count,
+                relearn_count: bucket.relearn_count,
+                filtered_count: bucket.filtered_count,
+                learn_milliseconds: bucket.learn_millis,
+                young_milliseconds: bucket.young_millis,
+                mature_milliseconds: bucket.mature_millis,
+                relearn_milliseconds: bucket.relearn_millis,
+                filtered_milliseconds: bucket.filtered_millis,
+            })
+            .collect(),
+        added: stats.added,
+        due: stats
+            .due
+            .iter()
+            .map(|bucket| pt::DueBucket {
+                young: bucket.young,
+                mature: bucket.mature,
+            })
+            .collect(),
+        due_tomorrow: stats.due_tomorrow,
+        intervals: stats.intervals,
+        buttons: Some(pt::ButtonCounts {
+            learning: stats.buttons.learning.to_vec(),
+            young: stats.buttons.young.to_vec(),
+            mature: stats.buttons.mature.to_vec(),
+        }),
+        hours: stats
+            .hours
+            .iter()
+            .map(|hour| pt::HourBucket {
+                count: hour.count,
+                correct: hour.correct,
+            })
+            .collect(),
+        cards: Some(pt::CardCounts {
+            new: stats.cards.new,
+            young_or_learning: stats.cards.young_or_learning,
+            mature: stats.cards.mature,
+            suspended_or_buried: stats.cards.suspended_or_buried,
+        }),
+        review_count: review.count,
+        average_interval: review.average_interval,
+        max_interval: review.max_interval,
+        min_factor: review.min_factor,
+        average_factor: review.average_factor,
+        max_factor: review.max_factor,
+    }
+}
+
+fn op_name(op: Option<UndoableOp>) -> String {
+    op.map(|op| op.name().to_string()).unwrap_or_default()
 }
 
 fn ords_hash_to_set(ords: HashSet<u16>) -> Vec<u32> {
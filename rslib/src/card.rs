@@ -0,0 +1,176 @@
+use serde_json::{Map, Value};
+
+/// The key the scheduling algorithm's state is stored under in `data`.
+const MEMORY_STATE_KEY: &str = "mem";
+
+/// The type of a card, stored in the `type` column.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum CardType {
+    New = 0,
+    Learn = 1,
+    Review = 2,
+    Relearn = 3,
+}
+
+/// The queue a card is in, stored in the `queue` column.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(i8)]
+pub enum CardQueue {
+    New = 0,
+    /// Learning cards due later today; due is a timestamp.
+    Learn = 1,
+    Review = 2,
+    /// Learning cards due on a later day; due is a day number.
+    DayLearn = 3,
+    /// Cards being previewed in a filtered deck; due is a timestamp.
+    PreviewRepeat = 4,
+    Suspended = -1,
+    SiblingBuried = -2,
+    UserBuried = -3,
+}
+
+/// A row from the cards table.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Card {
+    pub id: i64,
+    pub nid: i64,
+    pub did: i64,
+    pub ord: u16,
+    pub mtime: i64,
+    pub usn: i32,
+    pub ctype: CardType,
+    pub queue: CardQueue,
+    /// A position for new cards, a timestamp for intraday learning and
+    /// previews, or a day number otherwise.
+    pub due: i32,
+    /// The interval in days.
+    pub ivl: u32,
+    /// The ease factor, in permille.
+    pub factor: u16,
+    pub reps: u32,
+    pub lapses: u32,
+    /// Remaining learning steps, encoded as today * 1000 + total.
+    pub left: i32,
+    /// The original due of a card in a filtered deck.
+    pub odue: i32,
+    /// The original deck of a card in a filtered deck.
+    pub odid: i64,
+    pub flags: u8,
+    pub data: String,
+}
+
+impl Default for Card {
+    fn default() -> Self {
+        Self {
+            id: 0,
+            nid: 0,
+            did: 1,
+            ord: 0,
+            mtime: 0,
+            usn: 0,
+            ctype: CardType::New,
+            queue: CardQueue::New,
+            due: 0,
+            ivl: 0,
+            factor: 0,
+            reps: 0,
+            lapses: 0,
+            left: 0,
+            odue: 0,
+            odid: 0,
+            flags: 0,
+            data: "".to_string(),
+        }
+    }
+}
+
+impl Card {
+    /// Extra state kept by the card's scheduling algorithm, stored under
+    /// the `mem` key of the JSON object in the data column.
+    pub fn memory_state(&self) -> Option<Value> {
+        serde_json::from_str::<Map<String, Value>>(&self.data)
+            .ok()?
+            .remove(MEMORY_STATE_KEY)
+    }
+
+    /// Store or clear the scheduling algorithm's state, keeping anything
+    /// else in the data column.
+    pub fn set_memory_state(&mut self, state: Option<Value>) {
+        let mut data = match serde_json::from_str::<Map<String, Value>>(&self.data) {
+            Ok(data) => data,
+            Err(_) if state.is_none() => return,
+            Err(_) => Map::new(),
+        };
+        match state {
+            Some(state) => data.insert(MEMORY_STATE_KEY.to_string(), state),
+            None => data.remove(MEMORY_STATE_KEY),
+        };
+        self.data = if data.is_empty() {
+            String::new()
+        } else {
+            Value::Object(data).to_string()
+        };
+    }
+
+    /// The deck the card belongs to outside of filtered decks.
+    pub fn home_deck_id(&self) -> i64 {
+        if self.odid != 0 {
+            self.odid
+        } else {
+            self.did
+        }
+    }
+
+    /// Move the card back into the queue its type calls for, as when it is
+    /// unsuspended or unburied. Learning cards may be due on a timestamp or
+    /// a day number, depending on their delay.
+    pub fn restore_queue(&mut self) {
+        self.queue = match self.ctype {
+            CardType::New => CardQueue::New,
+            CardType::Review => CardQueue::Review,
+            CardType::Learn | CardType::Relearn => {
+                let due = if self.odue != 0 { self.odue } else { self.due };
+                if due > 1_000_000_000 {
+                    CardQueue::Learn
+                } else {
+                    CardQueue::DayLearn
+                }
+            }
+        };
+    }
+
+    /// Return a card in a filtered deck to its home deck. The due number
+    /// is left as is.
+    pub fn remove_from_filtered_deck(&mut self) {
+        if self.odid != 0 {
+            self.did = self.odid;
+            self.odid = 0;
+            self.odue = 0;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Card;
+    use serde_json::json;
+
+    #[test]
+    fn test_memory_state() {
+        let mut card = Card::default();
+        assert_eq!(card.memory_state(), None);
+        card.set_memory_state(None);
+        assert_eq!(card.data, "");
+
+        card.set_memory_state(Some(json!({"stability": 2.5})));
+        assert_eq!(card.memory_state(), Some(json!({"stability": 2.5})));
+        assert_eq!(card.data, r#"{"mem":{"stability":2.5}}"#);
+
+        // other keys are preserved
+        card.data = r#"{"other":1}"#.into();
+        card.set_memory_state(Some(json!(1)));
+        card.set_memory_state(None);
+        assert_eq!(card.data, r#"{"other":1}"#);
+    }
+}
@@ -0,0 +1,112 @@
+use crate::config::Config;
+use crate::err::Result;
+use crate::sched::cutoff::{
+    fixed_offset_from_minutes, sched_timing_today_legacy, utc_minus_local_mins,
+};
+use crate::sched::limits::StudyDay;
+use crate::sched::session::ActiveSession;
+use crate::sched::{sched_timing_today, SchedTimingToday};
+use crate::storage::SqliteStorage;
+use crate::time::{i64_unix_millis, i64_unix_secs};
+use crate::undo::{UndoManager, UndoableOp};
+use chrono::{Datelike, TimeZone};
+use std::path::Path;
+
+pub struct Collection {
+    pub(crate) storage: SqliteStorage,
+    /// True when running on the sync server, which has no local timezone
+    /// and does not mark changes as pending sync.
+    pub(crate) server: bool,
+    pub(crate) undo: UndoManager,
+    pub(crate) session: Option<ActiveSession>,
+}
+
+pub fn open_collection<P: AsRef<Path>>(path: P, server: bool) -> Result<Collection> {
+    let storage = SqliteStorage::open_or_create(path.as_ref())?;
+    storage.begin_undo_tracking()?;
+    Ok(Collection {
+        storage,
+        server,
+        undo: UndoManager::default(),
+        session: None,
+    })
+}
+
+#[cfg(test)]
+pub(crate) fn open_test_collection() -> Collection {
+    open_collection(":memory:", false).unwrap()
+}
+
+impl Collection {
+    /// Run the provided closure in a transaction, committing and bumping the
+    /// collection's modification time if it succeeds, and rolling back if
+    /// it fails. The changes are recorded so `op` can be undone; if `op` is
+    /// None, the operation can't be undone and the undo history is cleared.
+    pub(crate) fn transact<F, R>(&mut self, op: Option<UndoableOp>, func: F) -> Result<R>
+    where
+        F: FnOnce(&mut Collection) -> Result<R>,
+    {
+        self.storage.begin()?;
+        // changes not made in an operation can't be undone
+        let res = self
+            .storage
+            .take_undo_log()
+            .and_then(|_| func(self))
+            .and_then(|output| {
+                let changes = self.storage.take_undo_log()?;
+                self.storage.mark_modified(i64_unix_millis())?;
+                self.storage.commit()?;
+                self.undo.record(op, changes);
+                Ok(output)
+            });
+        if res.is_err() {
+            self.storage.rollback()?;
+        }
+        res
+    }
+
+    /// The usn to record changes with.
+    pub(crate) fn usn(&self) -> Result<i32> {
+        if self.server {
+            self.storage.server_usn()
+        } else {
+            Ok(-1)
+        }
+    }
+
+    /// The scheduler's current day and the time of the next rollover.
+    pub(crate) fn timing_today(&self) -> Result<SchedTimingToday> {
+        let crt = self.storage.creation_stamp()?;
+        let config = self.storage.get_config()?;
+        let now = i64_unix_secs();
+        Ok(if config.new_timezone {
+            sched_timing_today(crt, now, self.minutes_west(&config), config.rollover_hour())
+        } else {
+            sched_timing_today_legacy(crt, now, config.rollover_hour())
+        })
+    }
+
+    /// The current scheduler day, with the local weekday and date it
+    /// started on.
+    pub(crate) fn study_day(&self) -> Result<StudyDay> {
+        let config = self.storage.get_config()?;
+        let timing = self.timing_today()?;
+        let day_start = timing.next_day_at - 86_400;
+        let local = fixed_offset_from_minutes(self.minutes_west(&config)).timestamp(day_start, 0);
+        Ok(StudyDay {
+            day: timing.days_elapsed,
+            weekday: local.weekday().num_days_from_monday() as u8,
+            date: local.date().naive_local(),
+        })
+    }
+
+    /// The offset of the local timezone from UTC, in minutes. The sync
+    /// server uses the offset the client last reported.
+    fn minutes_west(&self, config: &Config) -> i32 {
+        if self.server {
+            config.local_offset.unwrap_or(0)
+        } else {
+            utc_minus_local_mins()
+        }
+    }
+}
@@ -0,0 +1,94 @@
+use crate::sched::session::StudyStreak;
+use serde_derive::{Deserialize, Serialize};
+use serde_json::Value;
+use serde_repr::{Deserialize_repr, Serialize_repr};
+use std::collections::HashMap;
+
+/// Collection-wide settings, as stored in the `conf` column of the col table.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Config {
+    /// The selected deck and its children.
+    #[serde(rename = "activeDecks", default)]
+    pub active_decks: Vec<i64>,
+    /// The selected deck.
+    #[serde(rename = "curDeck", default = "default_deck")]
+    pub current_deck: i64,
+    /// How new cards are mixed in with reviews.
+    #[serde(rename = "newSpread", default)]
+    pub new_spread: NewCardSpread,
+    /// Learning cards due within this many seconds are shown early if
+    /// there is nothing else to study.
+    #[serde(rename = "collapseTime", default = "default_collapse_time")]
+    pub collapse_time: u32,
+    /// Show interday learning cards before reviews.
+    #[serde(rename = "dayLearnFirst", default)]
+    pub day_learn_first: bool,
+    /// The hour of the day the scheduler rolls over to the next day.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rollover: Option<i8>,
+    /// Whether the day cutoff is calculated by the backend.
+    #[serde(rename = "newTimezone", default)]
+    pub new_timezone: bool,
+    /// Minutes west of UTC, recorded for the sync server.
+    #[serde(
+        rename = "localOffset",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub local_offset: Option<i32>,
+    #[serde(rename = "schedVer", default = "default_sched_ver")]
+    pub sched_ver: u8,
+    /// When a review interval is fuzzed, pick the day in the fuzz range
+    /// with the fewest reviews due instead of a random one.
+    #[serde(rename = "loadBalance", default)]
+    pub load_balance: bool,
+    /// Weekdays that load balancing avoids where possible, from 0 for
+    /// Monday to 6 for Sunday.
+    #[serde(rename = "lightDays", default)]
+    pub light_days: Vec<u8>,
+    /// The length of the timebox, in seconds, or 0 for no timebox.
+    #[serde(rename = "timeLim", default)]
+    pub time_limit_secs: u32,
+    /// The latest run of consecutive days with a study session.
+    #[serde(
+        rename = "studyStreak",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub study_streak: Option<StudyStreak>,
+    #[serde(flatten)]
+    pub other: HashMap<String, Value>,
+}
+
+/// Where new cards are placed relative to reviews.
+#[derive(Serialize_repr, Deserialize_repr, Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum NewCardSpread {
+    Distribute = 0,
+    Last = 1,
+    First = 2,
+}
+
+impl Default for NewCardSpread {
+    fn default() -> Self {
+        NewCardSpread::Distribute
+    }
+}
+
+fn default_deck() -> i64 {
+    1
+}
+
+fn default_collapse_time() -> u32 {
+    1200
+}
+
+fn default_sched_ver() -> u8 {
+    1
+}
+
+impl Config {
+    pub fn rollover_hour(&self) -> i8 {
+        self.rollover.unwrap_or(4)
+    }
+}
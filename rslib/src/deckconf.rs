@@ -0,0 +1,365 @@
+use crate::decks::Deck;
+use crate::sched::limits::StudyDay;
+use chrono::{Duration, NaiveDate};
+use serde_derive::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+
+/// A deck options group, as stored in the `dconf` column of the col table.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct DeckConf {
+    pub id: i64,
+    pub name: String,
+    #[serde(rename = "mod")]
+    pub mtime: i64,
+    pub usn: i32,
+    pub new: NewConf,
+    pub lapse: LapseConf,
+    pub rev: RevConf,
+    /// The cap on the time recorded for a single answer, in seconds.
+    #[serde(rename = "maxTaken", default = "default_max_taken")]
+    pub max_taken: u32,
+    /// The scheduling algorithm review cards are answered with. Empty for
+    /// the default.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub algorithm: String,
+    /// Whether interday learning siblings are buried when a card is
+    /// answered.
+    #[serde(rename = "buryInterdayLearning", default)]
+    pub bury_interday_learning: bool,
+    /// Whether burying is limited to siblings in the same home deck.
+    #[serde(rename = "burySameDeckOnly", default)]
+    pub bury_same_deck_only: bool,
+    /// Ranges of days with no new cards or reviews.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub vacations: Vec<Vacation>,
+    #[serde(flatten)]
+    pub other: HashMap<String, Value>,
+}
+
+/// Options for new cards, from the `new` section of a deck config.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct NewConf {
+    /// Learning steps, in minutes.
+    pub delays: Vec<f32>,
+    /// Graduating intervals in days: good, easy and an unused third value.
+    pub ints: Vec<u16>,
+    /// Ease factor assigned on graduation, in permille.
+    #[serde(rename = "initialFactor")]
+    pub initial_factor: u16,
+    #[serde(rename = "perDay")]
+    pub per_day: u32,
+    /// Limits that replace `per_day` on each weekday from Monday, or null
+    /// to keep it.
+    #[serde(rename = "perWeekday", default, skip_serializing_if = "Vec::is_empty")]
+    pub per_weekday: Vec<Option<u32>>,
+    /// Whether new siblings are buried when a card is answered.
+    #[serde(default = "default_true")]
+    pub bury: bool,
+    #[serde(flatten)]
+    pub other: HashMap<String, Value>,
+}
+
+/// Options for lapsed reviews, from the `lapse` section of a deck config.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct LapseConf {
+    /// Relearning steps, in minutes.
+    pub delays: Vec<f32>,
+    /// Multiplier applied to the interval on lapse.
+    pub mult: f64,
+    /// Minimum interval after a lapse, in days.
+    #[serde(rename = "minInt")]
+    pub min_int: u32,
+    #[serde(rename = "leechFails")]
+    pub leech_fails: u32,
+    /// 0 to suspend, 1 to tag only, 2 to move to `leech_deck`, 3 to reset
+    /// the ease factor.
+    #[serde(rename = "leechAction")]
+    pub leech_action: u8,
+    /// The name of the deck leeches are moved to.
+    #[serde(
+        rename = "leechDeck",
+        default,
+        skip_serializing_if = "String::is_empty"
+    )]
+    pub leech_deck: String,
+    #[serde(flatten)]
+    pub other: HashMap<String, Value>,
+}
+
+/// Options for reviews, from the `rev` section of a deck config.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RevConf {
+    #[serde(rename = "perDay")]
+    pub per_day: u32,
+    /// Limits that replace `per_day` on each weekday from Monday, or null
+    /// to keep it.
+    #[serde(rename = "perWeekday", default, skip_serializing_if = "Vec::is_empty")]
+    pub per_weekday: Vec<Option<u32>>,
+    /// Extra multiplier applied when Easy is pressed.
+    pub ease4: f64,
+    /// Multiplier applied to all review intervals.
+    #[serde(rename = "ivlFct", default = "default_one")]
+    pub ivl_fct: f64,
+    /// Maximum review interval, in days.
+    #[serde(rename = "maxIvl")]
+    pub max_ivl: u32,
+    /// Whether review siblings are buried when a card is answered.
+    #[serde(default = "default_true")]
+    pub bury: bool,
+    /// Multiplier applied to the interval when Hard is pressed.
+    #[serde(rename = "hardFactor", default = "default_hard_factor")]
+    pub hard_factor: f64,
+    #[serde(flatten)]
+    pub other: HashMap<String, Value>,
+}
+
+/// A range of local dates, like "2020-12-24" to "2020-12-31", including
+/// both ends.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Vacation {
+    pub start: String,
+    pub end: String,
+}
+
+impl Vacation {
+    /// The first and last date, or None if either can't be parsed or the
+    /// range is empty.
+    fn dates(&self) -> Option<(NaiveDate, NaiveDate)> {
+        let start = NaiveDate::parse_from_str(&self.start, "%Y-%m-%d").ok()?;
+        let end = NaiveDate::parse_from_str(&self.end, "%Y-%m-%d").ok()?;
+        if start <= end {
+            Some((start, end))
+        } else {
+            None
+        }
+    }
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_one() -> f64 {
+    1.0
+}
+
+fn default_hard_factor() -> f64 {
+    1.2
+}
+
+fn default_max_taken() -> u32 {
+    60
+}
+
+impl Default for DeckConf {
+    fn default() -> Self {
+        DeckConf {
+            id: 1,
+            name: "Default".to_string(),
+            mtime: 0,
+            usn: 0,
+            new: NewConf::default(),
+            lapse: LapseConf::default(),
+            rev: RevConf::default(),
+            max_taken: default_max_taken(),
+            algorithm: String::new(),
+            bury_interday_learning: false,
+            bury_same_deck_only: false,
+            vacations: vec![],
+            other: HashMap::new(),
+        }
+    }
+}
+
+impl Default for NewConf {
+    fn default() -> Self {
+        NewConf {
+            delays: vec![1.0, 10.0],
+            ints: vec![1, 4, 7],
+            initial_factor: 2500,
+            per_day: 20,
+            per_weekday: vec![],
+            bury: false,
+            other: HashMap::new(),
+        }
+    }
+}
+
+impl Default for LapseConf {
+    fn default() -> Self {
+        LapseConf {
+            delays: vec![10.0],
+            mult: 0.0,
+            min_int: 1,
+            leech_fails: 8,
+            leech_action: 0,
+            leech_deck: String::new(),
+            other: HashMap::new(),
+        }
+    }
+}
+
+impl Default for RevConf {
+    fn default() -> Self {
+        RevConf {
+            per_day: 200,
+            per_weekday: vec![],
+            ease4: 1.3,
+            ivl_fct: 1.0,
+            max_ivl: 36500,
+            bury: false,
+            hard_factor: 1.2,
+            other: HashMap::new(),
+        }
+    }
+}
+
+/// The limit for `weekday` in a list of weekday overrides, or `per_day`.
+fn weekday_limit(per_weekday: &[Option<u32>], per_day: u32, weekday: u8) -> u32 {
+    per_weekday
+        .get(weekday as usize)
+        .cloned()
+        .flatten()
+        .unwrap_or(per_day)
+}
+
+/// Where a day falls relative to the vacations of an options group.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum VacationDay {
+    Vacation,
+    /// One of the days after a vacation that make up for it, with the
+    /// number of make-up days left, including this one.
+    MakingUp(u32),
+    Normal,
+}
+
+impl DeckConf {
+    /// Whether `day` falls in a vacation, or in the days after one that
+    /// make up for it. As many days are made up as the vacation lasted.
+    fn vacation_on(&self, day: StudyDay) -> VacationDay {
+        let mut making_up = VacationDay::Normal;
+        for (start, end) in self.vacations.iter().filter_map(Vacation::dates) {
+            if start <= day.date && day.date <= end {
+                return VacationDay::Vacation;
+            }
+            let last = end + (end.signed_duration_since(start) + Duration::days(1));
+            if end < day.date && day.date <= last {
+                let left = last.signed_duration_since(day.date).num_days() as u32 + 1;
+                if let VacationDay::MakingUp(other) = making_up {
+                    making_up = VacationDay::MakingUp(other.max(left));
+                } else {
+                    making_up = VacationDay::MakingUp(left);
+                }
+            }
+        }
+        making_up
+    }
+
+    /// The number of new cards to introduce on `day`. New cards are paused
+    /// during vacations.
+    pub(crate) fn new_per_day(&self, day: StudyDay) -> u32 {
+        if self.vacation_on(day) == VacationDay::Vacation {
+            0
+        } else {
+            weekday_limit(&self.new.per_weekday, self.new.per_day, day.weekday)
+        }
+    }
+
+    /// The number of reviews to show on `day`. Reviews are paused during
+    /// vacations. On the days after one, `backlog`, the reviews that were
+    /// overdue when the day started, is spread evenly over the make-up
+    /// days left, on top of the usual limit.
+    pub(crate) fn review_per_day(&self, day: StudyDay, backlog: u32) -> u32 {
+        let per_day = weekday_limit(&self.rev.per_weekday, self.rev.per_day, day.weekday);
+        match self.vacation_on(day) {
+            VacationDay::Vacation => 0,
+            VacationDay::MakingUp(days_left) => {
+                per_day.saturating_add((f64::from(backlog) / f64::from(days_left)).ceil() as u32)
+            }
+            VacationDay::Normal => per_day,
+        }
+    }
+}
+
+/// The options group of a normal deck, falling back on the default group
+/// if it is missing.
+pub(crate) fn conf_for_deck<'a>(
+    deck: &Deck,
+    confs: &'a HashMap<i64, DeckConf>,
+) -> Option<&'a DeckConf> {
+    deck.conf
+        .and_then(|id| confs.get(&id))
+        .or_else(|| confs.get(&1))
+}
+
+/// The options that apply to a card.
+///
+/// Cards in a filtered deck use the options of their home deck, and the
+/// filtered deck decides whether they are rescheduled or just previewed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CardConf {
+    pub conf: DeckConf,
+    /// Set when the card is in a filtered deck that does not reschedule,
+    /// to the delay in seconds before a card is shown again.
+    pub preview_delay_secs: Option<u32>,
+}
+
+impl CardConf {
+    /// Resolve the options for a card in deck `did` with original deck
+    /// `odid`. Missing decks and options groups fall back on the defaults.
+    pub(crate) fn for_card(
+        did: i64,
+        odid: i64,
+        decks: &HashMap<i64, Deck>,
+        confs: &HashMap<i64, DeckConf>,
+    ) -> CardConf {
+        let home_did = if odid != 0 { odid } else { did };
+        let conf = decks
+            .get(&home_did)
+            .and_then(|deck| conf_for_deck(deck, confs))
+            .or_else(|| confs.get(&1))
+            .cloned()
+            .unwrap_or_default();
+
+        let preview_delay_secs = match decks.get(&did) {
+            Some(deck) if deck.is_filtered() && !deck.resched.unwrap_or(true) => {
+                Some(deck.preview_delay.unwrap_or(10) * 60)
+            }
+            _ => None,
+        };
+
+        CardConf {
+            conf,
+            preview_delay_secs,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::DeckConf;
+
+    #[test]
+    fn test_conf_json() {
+        let json = r#"{"name": "Default", "new": {"delays": [1, 10], "ints": [1, 4, 7],
+            "initialFactor": 2500, "separate": true, "order": 1, "perDay": 20, "bury": false},
+            "lapse": {"delays": [10], "mult": 0, "minInt": 1, "leechFails": 8,
+            "leechAction": 0}, "rev": {"perDay": 200, "ease4": 1.3, "fuzz": 0.05,
+            "minSpace": 1, "ivlFct": 1, "maxIvl": 36500, "bury": false, "hardFactor": 1.2},
+            "maxTaken": 60, "timer": 0, "autoplay": true, "replayq": true, "mod": 0,
+            "usn": 0, "id": 1}"#;
+        let conf: DeckConf = serde_json::from_str(json).unwrap();
+        assert_eq!(conf.new.delays, vec![1.0, 10.0]);
+        assert_eq!(conf.rev.hard_factor, 1.2);
+        assert_eq!(conf.max_taken, 60);
+        assert_eq!(conf.other.get("timer"), Some(&serde_json::json!(0)));
+
+        // bury defaults to on when missing
+        let mut value = serde_json::to_value(&conf).unwrap();
+        value["new"].as_object_mut().unwrap().remove("bury");
+        let conf: DeckConf = serde_json::from_value(value).unwrap();
+        assert!(conf.new.bury);
+        assert_eq!(conf.new.other.get("order"), Some(&serde_json::json!(1)));
+    }
+}
@@ -0,0 +1,182 @@
+use serde_derive::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+
+/// A deck, as stored in the `decks` column of the col table.
+///
+/// Only the keys the backend needs are broken out; everything else is kept
+/// in `other` so it survives a round trip.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Deck {
+    pub id: i64,
+    pub name: String,
+    #[serde(rename = "mod")]
+    pub mtime: i64,
+    pub usn: i32,
+    /// 1 for filtered decks.
+    #[serde(rename = "dyn")]
+    pub dynamic: u8,
+    #[serde(rename = "newToday")]
+    pub new_today: (u32, i32),
+    #[serde(rename = "revToday")]
+    pub rev_today: (u32, i32),
+    #[serde(rename = "lrnToday")]
+    pub lrn_today: (u32, i32),
+    /// Milliseconds spent studying.
+    #[serde(rename = "timeToday")]
+    pub time_today: (u32, i32),
+    /// The options group of a normal deck.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub conf: Option<i64>,
+    /// Whether a filtered deck reschedules cards based on answers.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub resched: Option<bool>,
+    /// The delay in minutes before a previewed card is shown again.
+    #[serde(
+        rename = "previewDelay",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub preview_delay: Option<u32>,
+    /// The searches of a filtered deck, as (search, limit, order).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub terms: Option<Vec<(String, u32, u8)>>,
+    /// Whether the deck's children are hidden in the deck list.
+    #[serde(default)]
+    pub collapsed: bool,
+    #[serde(flatten)]
+    pub other: HashMap<String, Value>,
+}
+
+/// A daily counter on a deck.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TodayCounter {
+    New,
+    Review,
+    Learn,
+    Time,
+}
+
+impl Deck {
+    pub fn is_filtered(&self) -> bool {
+        self.dynamic != 0
+    }
+
+    /// Add to one of the daily counters, resetting it first if it was last
+    /// touched on a previous day.
+    pub fn bump_today(&mut self, counter: TodayCounter, today: u32, amount: i32) {
+        let entry = match counter {
+            TodayCounter::New => &mut self.new_today,
+            TodayCounter::Review => &mut self.rev_today,
+            TodayCounter::Learn => &mut self.lrn_today,
+            TodayCounter::Time => &mut self.time_today,
+        };
+        if entry.0 != today {
+            *entry = (today, 0);
+        }
+        entry.1 += amount;
+    }
+}
+
+/// The ids of a deck and its parents, starting with the deck itself.
+/// Parents are matched by name, ignoring case.
+pub(crate) fn deck_and_parent_ids(decks: &HashMap<i64, Deck>, did: i64) -> Vec<i64> {
+    let deck = match decks.get(&did) {
+        Some(deck) => deck,
+        None => return vec![],
+    };
+    let mut ids = vec![did];
+    let components: Vec<_> = deck.name.split("::").collect();
+    for len in (1..components.len()).rev() {
+        let parent_name = components[..len].join("::").to_lowercase();
+        if let Some(parent) = decks
+            .values()
+            .find(|d| d.name.to_lowercase() == parent_name)
+        {
+            ids.push(parent.id);
+        }
+    }
+    ids
+}
+
+/// Decks in the order they appear in the deck list, so that each deck
+/// comes before its children.
+pub(crate) fn decks_in_tree_order(decks: &HashMap<i64, Deck>) -> Vec<&Deck> {
+    let mut sorted: Vec<&Deck> = decks.values().collect();
+    sorted.sort_by(|a, b| a.name.split("::").cmp(b.name.split("::")));
+    sorted
+}
+
+/// The ids of a deck and all of its children, starting with the deck
+/// itself. Children are matched by name, ignoring case.
+pub(crate) fn deck_and_child_ids(decks: &HashMap<i64, Deck>, did: i64) -> Vec<i64> {
+    let deck = match decks.get(&did) {
+        Some(deck) => deck,
+        None => return vec![],
+    };
+    let prefix = format!("{}::", deck.name.to_lowercase());
+    let mut ids = vec![did];
+    ids.extend(
+        decks
+            .values()
+            .filter(|d| d.name.to_lowercase().starts_with(&prefix))
+            .map(|d| d.id),
+    );
+    ids
+}
+
+#[cfg(test)]
+mod test {
+    use super::{deck_and_child_ids, deck_and_parent_ids, Deck, TodayCounter};
+    use std::collections::HashMap;
+
+    fn deck(id: i64, name: &str) -> Deck {
+        let json = format!(
+            r#"{{"id": {}, "name": "{}", "mod": 0, "usn": 0, "dyn": 0,
+            "newToday": [0, 0], "revToday": [0, 0], "lrnToday": [0, 0],
+            "timeToday": [0, 0], "conf": 1, "desc": "", "collapsed": false}}"#,
+            id, name
+        );
+        serde_json::from_str(&json).unwrap()
+    }
+
+    #[test]
+    fn test_deck_json() {
+        let mut d = deck(5, "foo");
+        assert_eq!(d.conf, Some(1));
+        assert_eq!(d.other.get("desc"), Some(&serde_json::json!("")));
+
+        d.bump_today(TodayCounter::New, 3, 1);
+        d.bump_today(TodayCounter::New, 3, 1);
+        assert_eq!(d.new_today, (3, 2));
+        d.bump_today(TodayCounter::New, 4, 1);
+        assert_eq!(d.new_today, (4, 1));
+
+        let value = serde_json::to_value(&d).unwrap();
+        assert_eq!(value["newToday"], serde_json::json!([4, 1]));
+        assert!(!d.collapsed);
+        assert_eq!(value["collapsed"], serde_json::json!(false));
+        assert!(value.get("resched").is_none());
+    }
+
+    #[test]
+    fn test_parents() {
+        let decks: HashMap<_, _> = vec![
+            deck(1, "Default"),
+            deck(2, "Foo"),
+            deck(3, "foo::Bar"),
+            deck(4, "Foo::Bar::Baz"),
+        ]
+        .into_iter()
+        .map(|d| (d.id, d))
+        .collect();
+        assert_eq!(deck_and_parent_ids(&decks, 4), vec![4, 3, 2]);
+        assert_eq!(deck_and_parent_ids(&decks, 1), vec![1]);
+        assert!(deck_and_parent_ids(&decks, 10).is_empty());
+
+        let mut children = deck_and_child_ids(&decks, 2);
+        children[1..].sort();
+        assert_eq!(children, vec![2, 3, 4]);
+        assert_eq!(deck_and_child_ids(&decks, 4), vec![4]);
+    }
+}
@@ -9,6 +9,15 @@ pub enum AnkiError {
 
     #[fail(display = "invalid card template: {}", info)]
     TemplateParseError { info: String },
+
+    #[fail(display = "database error: {}", info)]
+    DBError { info: String },
+
+    #[fail(display = "not found")]
+    NotFound,
+
+    #[fail(display = "invalid search: {}", info)]
+    SearchError { info: String },
 }
 
 // error helpers
@@ -20,4 +29,28 @@ impl AnkiError {
     pub(crate) fn invalid_input<S: Into<String>>(s: S) -> AnkiError {
         AnkiError::InvalidInput { info: s.into() }
     }
+
+    pub(crate) fn search_error<S: Into<String>>(s: S) -> AnkiError {
+        AnkiError::SearchError { info: s.into() }
+    }
+
+    pub(crate) fn db_error<S: Into<String>>(s: S) -> AnkiError {
+        AnkiError::DBError { info: s.into() }
+    }
+}
+
+impl From<rusqlite::Error> for AnkiError {
+    fn from(err: rusqlite::Error) -> Self {
+        AnkiError::DBError {
+            info: format!("{:?}", err),
+        }
+    }
+}
+
+impl From<serde_json::Error> for AnkiError {
+    fn from(err: serde_json::Error) -> Self {
+        AnkiError::DBError {
+            info: format!("invalid json: {}", err),
+        }
+    }
 }
@@ -0,0 +1,417 @@
+//! Translations of the text the backend produces.
+//!
+//! Text is looked up in the same compiled gettext catalogs the Python code
+//! translates its strings with, and plural forms are picked with the
+//! catalog's own rule, so the two produce the same text.
+
+use crate::err::{AnkiError, Result};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+/// The messages of a translation catalog, and how numbers are written.
+/// Messages missing from the catalog are shown in English.
+#[derive(Debug, Clone, PartialEq)]
+pub struct I18n {
+    /// The translated forms of each message, by its English singular form.
+    messages: HashMap<String, Vec<String>>,
+    /// Which plural form to use for a number, or None for English rules.
+    plural: Option<PluralExpr>,
+    decimal_point: String,
+}
+
+impl Default for I18n {
+    fn default() -> Self {
+        I18n::english()
+    }
+}
+
+impl I18n {
+    pub fn english() -> I18n {
+        I18n {
+            messages: HashMap::new(),
+            plural: None,
+            decimal_point: ".".to_string(),
+        }
+    }
+
+    /// Load the catalog for `locale`, like `de_DE`, from
+    /// `<locale_dir>/<locale>/LC_MESSAGES/anki.mo`, falling back on the
+    /// language without its region as gettext does. English is used if
+    /// there is no catalog. Numbers are written with `decimal_point`, or
+    /// "." if it is empty.
+    pub fn load(locale_dir: &Path, locale: &str, decimal_point: &str) -> Result<I18n> {
+        let locale = locale.replace('-', "_");
+        let mut candidates = vec![locale.as_str()];
+        if let Some(lang) = locale.split('_').next() {
+            candidates.push(lang);
+        }
+        let mut i18n = I18n::english();
+        for candidate in candidates.into_iter().filter(|c| !c.is_empty()) {
+            let path = locale_dir
+                .join(candidate)
+                .join("LC_MESSAGES")
+                .join("anki.mo");
+            if let Ok(data) = fs::read(&path) {
+                i18n = I18n::from_mo(&data)?;
+                break;
+            }
+        }
+        Ok(i18n.with_decimal_point(decimal_point))
+    }
+
+    /// Write numbers with `decimal_point`, or "." if it is empty.
+    pub(crate) fn with_decimal_point(mut self, decimal_point: &str) -> I18n {
+        self.decimal_point = if decimal_point.is_empty() {
+            ".".to_string()
+        } else {
+            decimal_point.to_string()
+        };
+        self
+    }
+
+    /// Read a compiled gettext catalog.
+    pub(crate) fn from_mo(data: &[u8]) -> Result<I18n> {
+        let invalid = || AnkiError::invalid_input("invalid translation catalog");
+        let word_le = |pos: usize| -> Option<u32> {
+            let bytes = data.get(pos..pos + 4)?;
+            Some(
+                u32::from(bytes[0])
+                    | u32::from(bytes[1]) << 8
+                    | u32::from(bytes[2]) << 16
+                    | u32::from(bytes[3]) << 24,
+            )
+        };
+        let big_endian = match word_le(0) {
+            Some(0x9504_12de) => false,
+            Some(0xde12_0495) => true,
+            _ => return Err(invalid()),
+        };
+        let word = |pos: usize| -> Result<usize> {
+            let word = word_le(pos).ok_or_else(invalid)?;
+            Ok(if big_endian { word.swap_bytes() } else { word } as usize)
+        };
+        let string = |table: usize, idx: usize| -> Result<&str> {
+            let len = word(table + idx * 8)?;
+            let start = word(table + idx * 8 + 4)?;
+            let bytes = data.get(start..start + len).ok_or_else(invalid)?;
+            std::str::from_utf8(bytes).map_err(|_| invalid())
+        };
+
+        let count = word(8)?;
+        let (originals, translations) = (word(12)?, word(16)?);
+        let mut i18n = I18n::english();
+        for idx in 0..count {
+            let msgid = string(originals, idx)?;
+            let forms: Vec<String> = string(translations, idx)?
+                .split('\0')
+                .map(Into::into)
+                .collect();
+            if msgid.is_empty() {
+                i18n.plural = forms
+                    .first()
+                    .and_then(|header| plural_rule_in_header(header));
+            } else {
+                // the plural English form follows the singular
+                let singular = msgid.split('\0').next().unwrap_or_default();
+                i18n.messages.insert(singular.to_string(), forms);
+            }
+        }
+        Ok(i18n)
+    }
+
+    /// The translation of `msgid`.
+    pub(crate) fn tr<'a>(&'a self, msgid: &'a str) -> &'a str {
+        self.messages
+            .get(msgid)
+            .and_then(|forms| forms.first())
+            .map(String::as_str)
+            .unwrap_or(msgid)
+    }
+
+    /// The translation of a message with singular form `one` and plural
+    /// form `other`, in the form that suits `count`.
+    pub(crate) fn ntr<'a>(&'a self, one: &'a str, other: &'a str, count: u64) -> &'a str {
+        let form = match (&self.plural, self.messages.get(one)) {
+            (Some(plural), Some(forms)) => forms.get(plural.eval(count) as usize),
+            _ => None,
+        };
+        match form {
+            Some(form) => form,
+            None if count == 1 => one,
+            None => other,
+        }
+    }
+
+    /// The character or characters placed between the whole and fractional
+    /// parts of a number.
+    pub(crate) fn decimal_point(&self) -> &str {
+        &self.decimal_point
+    }
+}
+
+/// The plural rule in a catalog header, like `plural=(n != 1);` in its
+/// `Plural-Forms` line.
+fn plural_rule_in_header(header: &str) -> Option<PluralExpr> {
+    let line = header
+        .lines()
+        .find(|line| line.to_lowercase().starts_with("plural-forms:"))?;
+    let start = line.find("plural=")? + "plural=".len();
+    let rule = line[start..].split(';').next()?;
+    PluralExpr::parse(rule)
+}
+
+/// A plural rule, written as a C expression of `n`.
+#[derive(Debug, Clone, PartialEq)]
+enum PluralExpr {
+    N,
+    Number(u64),
+    Not(Box<PluralExpr>),
+    Binary(Box<PluralExpr>, BinaryOp, Box<PluralExpr>),
+    Choice(Box<PluralExpr>, Box<PluralExpr>, Box<PluralExpr>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BinaryOp {
+    Or,
+    And,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+}
+
+/// Operators from the loosest to the tightest binding, after `?:`.
+const BINARY_LEVELS: &[&[(&str, BinaryOp)]] = &[
+    &[("||", BinaryOp::Or)],
+    &[("&&", BinaryOp::And)],
+    &[("==", BinaryOp::Eq), ("!=", BinaryOp::Ne)],
+    &[
+        ("<=", BinaryOp::Le),
+        (">=", BinaryOp::Ge),
+        ("<", BinaryOp::Lt),
+        (">", BinaryOp::Gt),
+    ],
+    &[("+", BinaryOp::Add), ("-", BinaryOp::Sub)],
+    &[
+        ("*", BinaryOp::Mul),
+        ("/", BinaryOp::Div),
+        ("%", BinaryOp::Rem),
+    ],
+];
+
+impl PluralExpr {
+    fn parse(text: &str) -> Option<PluralExpr> {
+        let text: String = text.chars().filter(|c| !c.is_whitespace()).collect();
+        let mut rest = text.as_str();
+        let expr = parse_choice(&mut rest)?;
+        if rest.is_empty() {
+            Some(expr)
+        } else {
+            None
+        }
+    }
+
+    fn eval(&self, n: u64) -> u64 {
+        match self {
+            PluralExpr::N => n,
+            PluralExpr::Number(num) => *num,
+            PluralExpr::Not(expr) => (expr.eval(n) == 0) as u64,
+            PluralExpr::Choice(cond, yes, no) => {
+                if cond.eval(n) != 0 {
+                    yes.eval(n)
+                } else {
+                    no.eval(n)
+                }
+            }
+            PluralExpr::Binary(left, op, right) => {
+                let (a, b) = (left.eval(n), right.eval(n));
+                match op {
+                    BinaryOp::Or => (a != 0 || b != 0) as u64,
+                    BinaryOp::And => (a != 0 && b != 0) as u64,
+                    BinaryOp::Eq => (a == b) as u64,
+                    BinaryOp::Ne => (a != b) as u64,
+                    BinaryOp::Lt => (a < b) as u64,
+                    BinaryOp::Le => (a <= b) as u64,
+                    BinaryOp::Gt => (a > b) as u64,
+                    BinaryOp::Ge => (a >= b) as u64,
+                    BinaryOp::Add => a.wrapping_add(b),
+                    BinaryOp::Sub => a.wrapping_sub(b),
+                    BinaryOp::Mul => a.wrapping_mul(b),
+                    BinaryOp::Div => a.checked_div(b).unwrap_or(0),
+                    BinaryOp::Rem => a.checked_rem(b).unwrap_or(0),
+                }
+            }
+        }
+    }
+}
+
+fn parse_choice(rest: &mut &str) -> Option<PluralExpr> {
+    let cond = parse_binary(rest, 0)?;
+    if !rest.starts_with('?') {
+        return Some(cond);
+    }
+    *rest = &rest[1..];
+    let yes = parse_choice(rest)?;
+    if !rest.starts_with(':') {
+        return None;
+    }
+    *rest = &rest[1..];
+    let no = parse_choice(rest)?;
+    Some(PluralExpr::Choice(
+        Box::new(cond),
+        Box::new(yes),
+        Box::new(no),
+    ))
+}
+
+fn parse_binary(rest: &mut &str, level: usize) -> Option<PluralExpr> {
+    let ops = match BINARY_LEVELS.get(level) {
+        Some(ops) => ops,
+        None => return parse_unary(rest),
+    };
+    let mut expr = parse_binary(rest, level + 1)?;
+    while let Some(&(token, op)) = ops.iter().find(|(token, _)| rest.starts_with(token)) {
+        *rest = &rest[token.len()..];
+        let right = parse_binary(rest, level + 1)?;
+        expr = PluralExpr::Binary(Box::new(expr), op, Box::new(right));
+    }
+    Some(expr)
+}
+
+fn parse_unary(rest: &mut &str) -> Option<PluralExpr> {
+    if rest.starts_with('!') && !rest.starts_with("!=") {
+        *rest = &rest[1..];
+        return Some(PluralExpr::Not(Box::new(parse_unary(rest)?)));
+    }
+    if rest.starts_with('(') {
+        *rest = &rest[1..];
+        let expr = parse_choice(rest)?;
+        if !rest.starts_with(')') {
+            return None;
+        }
+        *rest = &rest[1..];
+        return Some(expr);
+    }
+    if rest.starts_with('n') {
+        *rest = &rest[1..];
+        return Some(PluralExpr::N);
+    }
+    let digits = rest.chars().take_while(char::is_ascii_digit).count();
+    let num = rest[..digits].parse().ok()?;
+    *rest = &rest[digits..];
+    Some(PluralExpr::Number(num))
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use super::{I18n, PluralExpr};
+
+    /// A compiled catalog holding `messages`, as (msgid, msgid_plural,
+    /// forms), and the plural rule `plural`.
+    pub(crate) fn mo_file(plural: &str, messages: &[(&str, &str, Vec<&str>)]) -> Vec<u8> {
+        let header = format!(
+            "Content-Type: text/plain; charset=UTF-8\nPlural-Forms: nplurals=4; plural={};\n",
+            plural
+        );
+        let mut entries: Vec<(String, String)> = vec![(String::new(), header)];
+        for (one, other, forms) in messages {
+            let msgid = if other.is_empty() {
+                one.to_string()
+            } else {
+                format!("{}\0{}", one, other)
+            };
+            entries.push((msgid, forms.join("\0")));
+        }
+        entries.sort();
+
+        let count = entries.len() as u32;
+        let mut words = vec![0x9504_12de, 0, count, 28, 28 + count * 8, 0, 0];
+        let mut strings = vec![];
+        let start = 28 + count * 16;
+        let mut tables = (vec![], vec![]);
+        for (msgid, msgstr) in &entries {
+            tables.0.push((msgid.len(), strings.len()));
+            strings.extend_from_slice(msgid.as_bytes());
+            strings.push(0);
+            tables.1.push((msgstr.len(), strings.len()));
+            strings.extend_from_slice(msgstr.as_bytes());
+            strings.push(0);
+        }
+        for (len, offset) in tables.0.into_iter().chain(tables.1) {
+            words.push(len as u32);
+            words.push(start + offset as u32);
+        }
+        let mut data: Vec<u8> = words
+            .into_iter()
+            .flat_map(|w| w.to_le_bytes().to_vec())
+            .collect();
+        data.extend(strings);
+        data
+    }
+
+    #[test]
+    fn test_plural_rules() {
+        let russian = PluralExpr::parse(
+            "((n%10==1 && n%100!=11) ? 0 : ((n%10 >= 2 && n%10 <=4 && (n%100 < 12 || \
+             n%100 > 14)) ? 1 : ((n%10 == 0 || (n%10 >= 5 && n%10 <=9)) || (n%100 >= 11 \
+             && n%100 <= 14)) ? 2 : 3))",
+        )
+        .unwrap();
+        let forms: Vec<_> = [1, 2, 5, 11, 21, 22, 112]
+            .iter()
+            .map(|&n| russian.eval(n))
+            .collect();
+        assert_eq!(forms, vec![0, 1, 2, 2, 0, 1, 2]);
+
+        let english = PluralExpr::parse("(n != 1)").unwrap();
+        assert_eq!((english.eval(1), english.eval(0)), (0, 1));
+        assert_eq!(PluralExpr::parse("0").unwrap().eval(5), 0);
+        assert_eq!(PluralExpr::parse("!(n > 1)").unwrap().eval(2), 0);
+        assert_eq!(PluralExpr::parse("n ? 1"), None);
+        assert_eq!(PluralExpr::parse("(n"), None);
+    }
+
+    #[test]
+    fn test_catalog() {
+        let data = mo_file(
+            "(n==1 ? 0 : n<5 ? 1 : 2)",
+            &[
+                ("(end)", "", vec!["(konec)"]),
+                ("%s day", "%s days", vec!["%s den", "%s dny", "%s dní"]),
+            ],
+        );
+        let i18n = I18n::from_mo(&data).unwrap();
+        assert_eq!(i18n.tr("(end)"), "(konec)");
+        assert_eq!(i18n.tr("missing"), "missing");
+        assert_eq!(i18n.ntr("%s day", "%s days", 1), "%s den");
+        assert_eq!(i18n.ntr("%s day", "%s days", 3), "%s dny");
+        assert_eq!(i18n.ntr("%s day", "%s days", 7), "%s dní");
+        // untranslated messages use English rules
+        assert_eq!(i18n.ntr("%s hour", "%s hours", 1), "%s hour");
+        assert_eq!(i18n.ntr("%s hour", "%s hours", 3), "%s hours");
+
+        assert!(I18n::from_mo(b"not a catalog").is_err());
+
+        // catalogs are found as gettext finds them
+        let dir = std::env::temp_dir().join(format!("anki-i18n-{}", std::process::id()));
+        let lang_dir = dir.join("cs").join("LC_MESSAGES");
+        std::fs::create_dir_all(&lang_dir).unwrap();
+        std::fs::write(lang_dir.join("anki.mo"), &data).unwrap();
+        let loaded = I18n::load(&dir, "cs-CZ", ",").unwrap();
+        assert_eq!(loaded.tr("(end)"), "(konec)");
+        assert_eq!(loaded.decimal_point(), ",");
+        assert_eq!(I18n::load(&dir, "xx_XX", "").unwrap(), I18n::english());
+        std::fs::remove_dir_all(&dir).unwrap();
+        let english = I18n::english();
+        assert_eq!(english.ntr("%s day", "%s days", 0), "%s days");
+        assert_eq!(english.decimal_point(), ".");
+    }
+}
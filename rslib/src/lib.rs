@@ -1,6 +1,20 @@
 mod backend_proto;
 
 pub mod backend;
+pub mod card;
+pub mod collection;
+pub mod config;
+pub mod deckconf;
+pub mod decks;
 pub mod err;
+pub mod i18n;
+pub mod notetypes;
+pub mod revlog;
 pub mod sched;
+pub mod search;
+pub mod stats;
+mod storage;
+pub mod tags;
 pub mod template;
+mod time;
+pub mod undo;
@@ -0,0 +1,41 @@
+use serde_derive::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+
+/// A note type, as stored in the `models` column of the col table.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct NoteType {
+    pub id: i64,
+    pub name: String,
+    /// 0 for standard note types, 1 for cloze.
+    #[serde(rename = "type", default)]
+    pub kind: u8,
+    #[serde(rename = "flds")]
+    pub fields: Vec<NoteField>,
+    #[serde(rename = "tmpls")]
+    pub templates: Vec<CardTemplate>,
+    #[serde(flatten)]
+    pub other: HashMap<String, Value>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct NoteField {
+    pub name: String,
+    pub ord: u16,
+    #[serde(flatten)]
+    pub other: HashMap<String, Value>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct CardTemplate {
+    pub name: String,
+    pub ord: u16,
+    #[serde(flatten)]
+    pub other: HashMap<String, Value>,
+}
+
+impl NoteType {
+    pub fn is_cloze(&self) -> bool {
+        self.kind == 1
+    }
+}
@@ -0,0 +1,46 @@
+/// The kind of review, stored in the revlog `type` column.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum RevlogReviewKind {
+    Learning = 0,
+    Review = 1,
+    Relearning = 2,
+    /// A review card answered early in a filtered deck.
+    EarlyReview = 3,
+    /// A card rescheduled by the user rather than answered.
+    Manual = 4,
+}
+
+impl RevlogReviewKind {
+    /// Convert a kind as stored in the revlog.
+    pub fn from_u8(kind: u8) -> Option<RevlogReviewKind> {
+        Some(match kind {
+            0 => RevlogReviewKind::Learning,
+            1 => RevlogReviewKind::Review,
+            2 => RevlogReviewKind::Relearning,
+            3 => RevlogReviewKind::EarlyReview,
+            4 => RevlogReviewKind::Manual,
+            _ => return None,
+        })
+    }
+}
+
+/// A row from the revlog table.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RevlogEntry {
+    /// Timestamp in milliseconds.
+    pub id: i64,
+    pub cid: i64,
+    pub usn: i32,
+    /// The button pressed, 1-4.
+    pub ease: u8,
+    /// Positive values are in days, negative values in seconds.
+    pub interval: i32,
+    /// Positive values are in days, negative values in seconds.
+    pub last_interval: i32,
+    /// The card's ease factor after answering, in permille.
+    pub factor: u32,
+    /// Time spent answering, in milliseconds.
+    pub taken_millis: u32,
+    pub kind: RevlogReviewKind,
+}
@@ -0,0 +1,218 @@
+//! The interface between the scheduler and the algorithms that decide how
+//! review cards are rescheduled, and the default SM-2 based algorithm.
+
+use crate::card::Card;
+use crate::deckconf::DeckConf;
+use crate::err::{AnkiError, Result};
+use crate::sched::fuzz::FuzzedInterval;
+use crate::sched::interval::{early_interval, review_fuzzed_interval};
+use crate::sched::learning::lapse_interval;
+use crate::sched::Answer;
+use serde_json::Value;
+
+/// The state of a review card, as seen by a scheduling algorithm.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CardState {
+    /// The interval in days.
+    pub interval: u32,
+    /// The ease factor, in permille.
+    pub ease_factor: u16,
+    /// The number of times the card has been answered, including this time.
+    pub reps: u32,
+    /// The number of times the card has lapsed before this answer.
+    pub lapses: u32,
+    /// Any extra state the algorithm stored when the card was last answered.
+    pub memory: Option<Value>,
+}
+
+/// A review card being answered.
+#[derive(Debug, Clone)]
+pub struct ReviewInput<'a> {
+    pub state: CardState,
+    pub answer: Answer,
+    /// The days since the card was last reviewed.
+    pub days_elapsed: u32,
+    pub conf: &'a DeckConf,
+    /// A sample in [0, 1) to fuzz the interval with, or None for no fuzz.
+    pub fuzz: Option<f32>,
+}
+
+impl ReviewInput<'_> {
+    /// The input for answering review `card` on day `today`.
+    pub fn from_card<'a>(
+        card: &Card,
+        answer: Answer,
+        today: u32,
+        conf: &'a DeckConf,
+        fuzz: Option<f32>,
+    ) -> ReviewInput<'a> {
+        let due = if card.odid != 0 { card.odue } else { card.due };
+        let last_review = i64::from(due) - i64::from(card.ivl);
+        ReviewInput {
+            state: CardState {
+                interval: card.ivl,
+                ease_factor: card.factor,
+                reps: card.reps,
+                lapses: card.lapses,
+                memory: card.memory_state(),
+            },
+            answer,
+            days_elapsed: (i64::from(today) - last_review).max(0) as u32,
+            conf,
+            fuzz,
+        }
+    }
+}
+
+/// What an algorithm decided for a review card.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReviewOutput {
+    /// The new ease factor, in permille.
+    pub ease_factor: u16,
+    /// The new interval, and the range it was fuzzed within. When the card
+    /// lapsed, this is the interval it will have after relearning.
+    pub interval: FuzzedInterval,
+    /// Extra state to store with the card, or None to clear it.
+    pub memory: Option<Value>,
+}
+
+impl ReviewOutput {
+    /// Update `card`'s ease, interval and stored state. Its queue and due
+    /// date are left to the caller.
+    pub fn apply_to(self, card: &mut Card) {
+        card.factor = self.ease_factor;
+        card.ivl = self.interval.interval;
+        card.set_memory_state(self.memory);
+    }
+}
+
+/// Decides the next interval of review cards. Learning steps and daily
+/// limits are handled by the scheduler, and are the same for every
+/// algorithm.
+pub trait SchedulingAlgorithm {
+    /// The name that selects this algorithm in the deck options.
+    fn name(&self) -> &'static str;
+
+    fn answer_review(&self, input: &ReviewInput) -> ReviewOutput;
+}
+
+/// The algorithm used by the v2 scheduler, a variant of SM-2.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Sm2;
+
+impl SchedulingAlgorithm for Sm2 {
+    fn name(&self) -> &'static str {
+        "sm2"
+    }
+
+    fn answer_review(&self, input: &ReviewInput) -> ReviewOutput {
+        let state = &input.state;
+        let conf = input.conf;
+        let adjustment = match input.answer {
+            Answer::Again => -200,
+            Answer::Hard => -150,
+            Answer::Good => 0,
+            Answer::Easy => 150,
+        };
+        let ease_factor = (i32::from(state.ease_factor) + adjustment).max(1300) as u16;
+
+        let interval = if input.answer == Answer::Again {
+            FuzzedInterval::new(lapse_interval(state.interval, &conf.lapse), None)
+        } else if input.days_elapsed < state.interval {
+            FuzzedInterval::new(
+                early_interval(
+                    state.interval,
+                    state.ease_factor,
+                    input.days_elapsed,
+                    input.answer,
+                    &conf.rev,
+                ),
+                None,
+            )
+        } else {
+            review_fuzzed_interval(
+                state.interval,
+                state.ease_factor,
+                input.days_elapsed - state.interval,
+                input.answer,
+                &conf.rev,
+                input.fuzz,
+            )
+        };
+
+        ReviewOutput {
+            ease_factor,
+            interval,
+            memory: None,
+        }
+    }
+}
+
+/// The algorithm selected by `conf`.
+pub fn algorithm_for_conf(conf: &DeckConf) -> Result<Box<dyn SchedulingAlgorithm>> {
+    if conf.algorithm.is_empty() {
+        return Ok(Box::new(Sm2));
+    }
+    let algorithms: Vec<Box<dyn SchedulingAlgorithm>> = vec![Box::new(Sm2)];
+    algorithms
+        .into_iter()
+        .find(|algorithm| algorithm.name() == conf.algorithm)
+        .ok_or_else(|| {
+            AnkiError::invalid_input(format!("unknown scheduling algorithm: {}", conf.algorithm))
+        })
+}
+
+#[cfg(test)]
+mod test {
+    use super::{algorithm_for_conf, ReviewInput, SchedulingAlgorithm, Sm2};
+    use crate::card::{Card, CardQueue, CardType};
+    use crate::deckconf::DeckConf;
+    use crate::sched::Answer;
+
+    #[test]
+    fn test_sm2() {
+        let conf = DeckConf::default();
+        let card = Card {
+            ctype: CardType::Review,
+            queue: CardQueue::Review,
+            ivl: 10,
+            due: 100,
+            factor: 2500,
+            ..Default::default()
+        };
+        let answer = |answer, today| {
+            Sm2.answer_review(&ReviewInput::from_card(&card, answer, today, &conf, None))
+        };
+
+        // matches the v2 scheduler's intervals, including overdue days
+        let out = answer(Answer::Good, 100);
+        assert_eq!((out.interval.interval, out.ease_factor), (25, 2500));
+        let out = answer(Answer::Easy, 104);
+        assert_eq!((out.interval.interval, out.ease_factor), (45, 2650));
+        let out = answer(Answer::Hard, 100);
+        assert_eq!((out.interval.interval, out.ease_factor), (12, 2350));
+        let out = answer(Answer::Again, 100);
+        assert_eq!((out.interval.interval, out.ease_factor), (1, 2300));
+        // reviewed 4 days early in a filtered deck
+        let card = Card {
+            odid: 5,
+            odue: 104,
+            due: -100_000,
+            ..card
+        };
+        let input = ReviewInput::from_card(&card, Answer::Good, 100, &conf, None);
+        assert_eq!(input.days_elapsed, 6);
+        assert_eq!(Sm2.answer_review(&input).interval.interval, 15);
+        assert_eq!(Sm2.answer_review(&input).memory, None);
+    }
+
+    #[test]
+    fn test_algorithm_for_conf() {
+        let mut conf = DeckConf::default();
+        assert_eq!(algorithm_for_conf(&conf).unwrap().name(), "sm2");
+        conf.algorithm = "sm2".into();
+        assert_eq!(algorithm_for_conf(&conf).unwrap().name(), "sm2");
+        conf.algorithm = "other".into();
+        assert!(algorithm_for_conf(&conf).is_err());
+    }
+}
@@ -0,0 +1,604 @@
+//! Answering cards with the v2 scheduler.
+
+use crate::card::{Card, CardQueue, CardType};
+use crate::collection::Collection;
+use crate::deckconf::{CardConf, DeckConf};
+use crate::decks::{deck_and_parent_ids, Deck, TodayCounter};
+use crate::err::{AnkiError, Result};
+use crate::revlog::{RevlogEntry, RevlogReviewKind};
+use crate::sched::algorithm::{algorithm_for_conf, ReviewInput};
+use crate::sched::fuzz::{balanced_interval, fuzz_sample, FuzzedInterval};
+use crate::sched::learning::{answer_learning_card, start_relearning, LearningContext};
+use crate::sched::session::TimeboxReached;
+use crate::sched::Answer;
+use crate::time::i64_unix_millis;
+use crate::undo::UndoableOp;
+use std::collections::HashMap;
+
+/// The result of answering a card.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AnswerOutcome {
+    /// True if the card was handled as a leech.
+    pub leech: bool,
+    /// Siblings that were buried because the card was answered.
+    pub buried_sibling_ids: Vec<i64>,
+    /// Set when the card was given a fuzzed review interval: the interval,
+    /// and the range of days it was picked from.
+    pub fuzzed_interval: Option<FuzzedInterval>,
+    /// The time until the card is shown again, in seconds, or 0 if it left
+    /// a filtered deck that doesn't reschedule.
+    pub next_interval_secs: u32,
+    /// Set when the answer ran out the timebox, which then starts again.
+    pub timebox_reached: Option<TimeboxReached>,
+}
+
+impl Collection {
+    /// Answer a card, updating it, its siblings, the deck counters and the
+    /// review log in a single transaction, and add it to the study session.
+    pub fn answer_card(
+        &mut self,
+        cid: i64,
+        answer: Answer,
+        taken_millis: u32,
+    ) -> Result<AnswerOutcome> {
+        self.transact(Some(UndoableOp::AnswerCard), |col| {
+            let card = col.storage.get_card(cid)?.ok_or(AnkiError::NotFound)?;
+            let decks = col.storage.get_all_decks()?;
+            let confs = col.storage.get_all_deck_conf()?;
+            // the session counts the same time as the review log
+            let max_taken = CardConf::for_card(card.did, card.odid, &decks, &confs)
+                .conf
+                .max_taken;
+            let taken_millis = taken_millis.min(max_taken * 1000);
+            let mut outcome = col.answer_card_inner(cid, answer, taken_millis, true)?;
+            let day = col.timing_today()?.days_elapsed;
+            outcome.timebox_reached =
+                col.record_study(card.did, taken_millis, i64_unix_millis(), day)?;
+            Ok(outcome)
+        })
+    }
+
+    /// The time until a card would be shown again after each answer from
+    /// Again to Easy, as in `AnswerOutcome` but without fuzz. Each answer
+    /// is made and then rolled back.
+    pub fn next_interval_secs(&mut self, cid: i64) -> Result<Vec<u32>> {
+        let mut secs = vec![];
+        for answer in &[Answer::Again, Answer::Hard, Answer::Good, Answer::Easy] {
+            self.storage.begin()?;
+            let outcome = self.answer_card_inner(cid, *answer, 0, false);
+            self.storage.rollback()?;
+            secs.push(outcome?.next_interval_secs);
+        }
+        Ok(secs)
+    }
+
+    fn answer_card_inner(
+        &mut self,
+        cid: i64,
+        answer: Answer,
+        taken_millis: u32,
+        fuzz: bool,
+    ) -> Result<AnswerOutcome> {
+        let now_millis = i64_unix_millis();
+        let mtime = now_millis / 1000;
+        let timing = self.timing_today()?;
+        let today = timing.days_elapsed;
+        let usn = self.usn()?;
+
+        let mut card = self.storage.get_card(cid)?.ok_or(AnkiError::NotFound)?;
+        let mut decks = self.storage.get_all_decks()?;
+        let confs = self.storage.get_all_deck_conf()?;
+        let CardConf {
+            conf,
+            preview_delay_secs,
+        } = CardConf::for_card(card.did, card.odid, &decks, &confs);
+
+        let buried_sibling_ids = self.bury_siblings(&card, &conf, today, mtime, usn)?;
+
+        let mut leech = false;
+        let mut fuzzed_interval = None;
+        let mut step_delay_secs = None;
+        let revlog = if let Some(delay) = preview_delay_secs {
+            answer_preview_card(&mut card, answer, delay, mtime)?;
+            None
+        } else {
+            card.reps += 1;
+            let ctx = LearningContext {
+                now_millis,
+                today,
+                next_day_at: timing.next_day_at,
+                usn,
+                new_conf: &conf.new,
+                lapse_conf: &conf.lapse,
+                fuzz: if fuzz {
+                    Some(fuzz_sample(card.id, card.reps))
+                } else {
+                    None
+                },
+            };
+            let mut entry = match card.queue {
+                CardQueue::New | CardQueue::Learn | CardQueue::DayLearn => {
+                    if card.queue == CardQueue::New {
+                        bump_today(&mut decks, &card, TodayCounter::New, 1, today, mtime, usn);
+                    }
+                    let (entry, fuzzed, delay) =
+                        answer_learning_card(&mut card, answer, taken_millis, &ctx)?;
+                    fuzzed_interval = fuzzed;
+                    step_delay_secs = delay;
+                    if card.queue == CardQueue::Review {
+                        // graduating moves the card back to its home deck
+                        card.remove_from_filtered_deck();
+                    }
+                    entry
+                }
+                CardQueue::Review => {
+                    let (entry, was_leech, fuzzed) =
+                        self.answer_review_card(&mut card, answer, taken_millis, &ctx, &conf)?;
+                    leech = was_leech;
+                    fuzzed_interval = fuzzed;
+                    bump_today(
+                        &mut decks,
+                        &card,
+                        TodayCounter::Review,
+                        1,
+                        today,
+                        mtime,
+                        usn,
+                    );
+                    entry
+                }
+                _ => {
+                    return Err(AnkiError::invalid_input(
+                        "card is not in an answerable queue",
+                    ))
+                }
+            };
+            if let Some(fuzzed) = fuzzed_interval.as_mut() {
+                if fuzzed.min < fuzzed.max {
+                    let config = self.storage.get_config()?;
+                    if config.load_balance {
+                        fuzzed.interval =
+                            self.load_balanced_interval(*fuzzed, today, &config.light_days)?;
+                        card.ivl = fuzzed.interval;
+                        card.due = (today + card.ivl) as i32;
+                        entry.interval = card.ivl as i32;
+                    }
+                }
+            }
+            // once answered, the original due date no longer applies
+            card.odue = 0;
+            Some(entry)
+        };
+
+        // the step delay or interval the answer gave, as the Python code
+        // shows it, rather than the time until the card is due, which
+        // differs once a step crosses the day cutoff
+        let next_interval_secs = match (&revlog, step_delay_secs) {
+            (None, _) if answer == Answer::Again => preview_delay_secs.unwrap_or_default(),
+            (None, _) => 0,
+            (Some(_), Some(delay)) => delay,
+            // relearning steps are logged in negative seconds
+            (Some(entry), None) if entry.interval < 0 => (-entry.interval) as u32,
+            (Some(entry), None) => entry.interval as u32 * 86_400,
+        };
+
+        bump_today(
+            &mut decks,
+            &card,
+            TodayCounter::Time,
+            taken_millis as i32,
+            today,
+            mtime,
+            usn,
+        );
+        self.storage.set_all_decks(&decks)?;
+
+        card.mtime = mtime;
+        card.usn = usn;
+        self.storage.update_card(&card)?;
+        if let Some(entry) = revlog {
+            self.storage.add_revlog_entry(&entry)?;
+        }
+
+        Ok(AnswerOutcome {
+            leech,
+            buried_sibling_ids,
+            fuzzed_interval,
+            next_interval_secs,
+            timebox_reached: None,
+        })
+    }
+
+    /// The interval in `fuzzed`'s range with the fewest reviews due.
+    fn load_balanced_interval(
+        &self,
+        fuzzed: FuzzedInterval,
+        today: u32,
+        light_days: &[u8],
+    ) -> Result<u32> {
+        let due_counts = self
+            .storage
+            .review_counts_between(today + fuzzed.min, today + fuzzed.max)?
+            .into_iter()
+            .map(|(day, count)| (day - today, count))
+            .collect();
+        Ok(balanced_interval(
+            fuzzed,
+            &due_counts,
+            self.study_day()?.weekday,
+            light_days,
+        ))
+    }
+
+    /// Answer a card in the review queue, using the scheduling algorithm
+    /// selected in its options. Returns the revlog entry, whether
+    /// the card was handled as a leech, and the fuzzed interval if one was
+    /// given.
+    fn answer_review_card(
+        &mut self,
+        card: &mut Card,
+        answer: Answer,
+        taken_millis: u32,
+        ctx: &LearningContext,
+        conf: &DeckConf,
+    ) -> Result<(RevlogEntry, bool, Option<FuzzedInterval>)> {
+        let early = card.odid != 0 && card.odue > ctx.today as i32;
+        let last_interval = card.ivl;
+        let mut leech = false;
+        let mut delay = 0;
+        let mut fuzzed_interval = None;
+
+        let input = ReviewInput::from_card(card, answer, ctx.today, conf, ctx.fuzz);
+        let output = algorithm_for_conf(conf)?.answer_review(&input);
+        let interval = output.interval;
+        output.apply_to(card);
+
+        if answer == Answer::Again {
+            card.lapses += 1;
+            leech = self.check_leech(card, ctx)?;
+            let suspended = leech && card.queue == CardQueue::Suspended;
+            if !ctx.lapse_conf.delays.is_empty() && !suspended {
+                delay = start_relearning(card, ctx);
+            } else {
+                // no relearning steps, so the card stays in review
+                card.ctype = CardType::Review;
+                card.queue = if suspended {
+                    CardQueue::Suspended
+                } else {
+                    CardQueue::Review
+                };
+                card.due = (ctx.today + card.ivl) as i32;
+                card.remove_from_filtered_deck();
+            }
+        } else {
+            if !early {
+                fuzzed_interval = Some(interval);
+            }
+            card.due = (ctx.today + card.ivl) as i32;
+            card.remove_from_filtered_deck();
+        }
+
+        let entry = RevlogEntry {
+            id: ctx.now_millis,
+            cid: card.id,
+            usn: ctx.usn,
+            ease: answer as u8,
+            interval: if delay > 0 {
+                -(delay as i32)
+            } else {
+                card.ivl as i32
+            },
+            last_interval: last_interval as i32,
+            factor: u32::from(card.factor),
+            taken_millis,
+            kind: if early {
+                RevlogReviewKind::EarlyReview
+            } else {
+                RevlogReviewKind::Review
+            },
+        };
+        Ok((entry, leech, fuzzed_interval))
+    }
+}
+
+/// Answer a card in a filtered deck that doesn't reschedule. Again shows
+/// the card again after a delay; anything else returns it to its home deck
+/// as it was.
+fn answer_preview_card(card: &mut Card, answer: Answer, delay_secs: u32, now: i64) -> Result<()> {
+    match card.queue {
+        CardQueue::New
+        | CardQueue::Learn
+        | CardQueue::Review
+        | CardQueue::DayLearn
+        | CardQueue::PreviewRepeat => (),
+        _ => {
+            return Err(AnkiError::invalid_input(
+                "card is not in an answerable queue",
+            ))
+        }
+    }
+
+    if answer == Answer::Again {
+        card.queue = CardQueue::PreviewRepeat;
+        card.due = (now + i64::from(delay_secs)) as i32;
+    } else {
+        card.due = card.odue;
+        card.queue = match card.ctype {
+            // learning cards may be due on a timestamp or a day
+            CardType::Learn | CardType::Relearn => {
+                if card.odue > 1_000_000_000 {
+                    CardQueue::Learn
+                } else {
+                    CardQueue::DayLearn
+                }
+            }
+            CardType::New => CardQueue::New,
+            CardType::Review => CardQueue::Review,
+        };
+        card.remove_from_filtered_deck();
+    }
+    Ok(())
+}
+
+/// Add to a daily counter on the card's deck and its parents.
+fn bump_today(
+    decks: &mut HashMap<i64, Deck>,
+    card: &Card,
+    counter: TodayCounter,
+    amount: i32,
+    today: u32,
+    mtime: i64,
+    usn: i32,
+) {
+    for did in deck_and_parent_ids(decks, card.did) {
+        if let Some(deck) = decks.get_mut(&did) {
+            deck.bump_today(counter, today, amount);
+            deck.mtime = mtime;
+            deck.usn = usn;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::card::{Card, CardQueue, CardType};
+    use crate::collection::{open_test_collection, Collection};
+    use crate::err::AnkiError;
+    use crate::revlog::RevlogReviewKind;
+    use crate::sched::Answer;
+    use rusqlite::{params, NO_PARAMS};
+
+    fn revlog(col: &Collection) -> Vec<(i64, u8, i32, i32, u8)> {
+        let mut stmt = col
+            .storage
+            .db
+            .prepare("select cid, ease, ivl, lastIvl, type from revlog order by id")
+            .unwrap();
+        stmt.query_map(NO_PARAMS, |row| {
+            Ok((
+                row.get(0)?,
+                row.get(1)?,
+                row.get(2)?,
+                row.get(3)?,
+                row.get(4)?,
+            ))
+        })
+        .unwrap()
+        .collect::<rusqlite::Result<_>>()
+        .unwrap()
+    }
+
+    #[test]
+    fn test_answer_new_card() {
+        let mut col = open_test_collection();
+        let today = col.timing_today().unwrap().days_elapsed;
+        col.storage.add_test_note(1, "").unwrap();
+        for cid in &[1, 2] {
+            col.storage
+                .add_card(&Card {
+                    id: *cid,
+                    nid: 1,
+                    ..Default::default()
+                })
+                .unwrap();
+        }
+
+        let outcome = col.answer_card(1, Answer::Good, 5000).unwrap();
+        assert!(!outcome.leech);
+        // new siblings are not buried by default
+        assert!(outcome.buried_sibling_ids.is_empty());
+
+        let card = col.storage.get_card(1).unwrap().unwrap();
+        assert_eq!(card.ctype, CardType::Learn);
+        assert_ne!(card.queue, CardQueue::New);
+        assert_eq!(card.reps, 1);
+        assert_eq!(card.usn, -1);
+        assert_eq!(revlog(&col), vec![(1, 3, -600, -60, 0)]);
+
+        let deck = col.storage.get_all_decks().unwrap().remove(&1).unwrap();
+        assert_eq!(deck.new_today, (today, 1));
+        assert_eq!(deck.time_today, (today, 5000));
+
+        // time is capped by the options
+        col.answer_card(1, Answer::Good, 100_000).unwrap();
+        let deck = col.storage.get_all_decks().unwrap().remove(&1).unwrap();
+        assert_eq!(deck.new_today, (today, 1));
+        assert_eq!(deck.time_today, (today, 65000));
+        assert_eq!(col.study_today().unwrap().millis, 65000);
+        let card = col.storage.get_card(1).unwrap().unwrap();
+        assert_eq!(card.queue, CardQueue::Review);
+
+        // with burying enabled, the sibling is buried
+        let mut confs = col.storage.get_all_deck_conf().unwrap();
+        confs.get_mut(&1).unwrap().new.bury = true;
+        col.storage.set_all_deck_conf(&confs).unwrap();
+        col.storage
+            .db
+            .execute("update cards set due = 0 where id = 1", NO_PARAMS)
+            .unwrap();
+        let outcome = col.answer_card(1, Answer::Good, 1000).unwrap();
+        assert_eq!(outcome.buried_sibling_ids, vec![2]);
+        let card = col.storage.get_card(2).unwrap().unwrap();
+        assert_eq!(card.queue, CardQueue::SiblingBuried);
+    }
+
+    #[test]
+    fn test_answer_review_card() {
+        let mut col = open_test_collection();
+        let today = col.timing_today().unwrap().days_elapsed;
+        col.storage.add_test_note(1, " foo ").unwrap();
+        let card = Card {
+            id: 1,
+            nid: 1,
+            ctype: CardType::Review,
+            queue: CardQueue::Review,
+            due: today as i32,
+            ivl: 10,
+            factor: 2500,
+            lapses: 7,
+            ..Default::default()
+        };
+        col.storage.add_card(&card).unwrap();
+
+        // the eighth lapse makes it a leech, which suspends it
+        let outcome = col.answer_card(1, Answer::Again, 1000).unwrap();
+        assert!(outcome.leech);
+        let card = col.storage.get_card(1).unwrap().unwrap();
+        assert_eq!(card.queue, CardQueue::Suspended);
+        assert_eq!(card.ctype, CardType::Review);
+        assert_eq!(card.lapses, 8);
+        assert_eq!(card.factor, 2300);
+        assert_eq!(card.ivl, 1);
+        assert_eq!(col.storage.get_note_tags(1).unwrap(), " foo leech ");
+        assert!(col.storage.get_all_tags().unwrap().contains_key("leech"));
+        assert_eq!(
+            revlog(&col),
+            vec![(1, 1, 1, 10, RevlogReviewKind::Review as u8)]
+        );
+
+        // a normal lapse moves it into relearning
+        col.storage
+            .db
+            .execute("update cards set queue = 2, ivl = 10", NO_PARAMS)
+            .unwrap();
+        let outcome = col.answer_card(1, Answer::Again, 1000).unwrap();
+        assert!(!outcome.leech);
+        let card = col.storage.get_card(1).unwrap().unwrap();
+        assert_eq!(card.ctype, CardType::Relearn);
+        assert_ne!(card.queue, CardQueue::Review);
+        assert_eq!(card.left % 1000, 1);
+        assert_eq!(revlog(&col)[1], (1, 1, -600, 10, 1));
+
+        // graduating keeps the reduced interval
+        col.answer_card(1, Answer::Good, 1000).unwrap();
+        let card = col.storage.get_card(1).unwrap().unwrap();
+        assert_eq!(card.ctype, CardType::Review);
+        assert_eq!(card.due, (today + 1) as i32);
+
+        // a review that succeeds
+        col.storage
+            .db
+            .execute("update cards set due = ?", params![today])
+            .unwrap();
+        let outcome = col.answer_card(1, Answer::Good, 1000).unwrap();
+        let card = col.storage.get_card(1).unwrap().unwrap();
+        assert!(card.ivl >= 2);
+        assert_eq!(card.due, (today + card.ivl) as i32);
+        let fuzzed = outcome.fuzzed_interval.unwrap();
+        assert_eq!(fuzzed.interval, card.ivl);
+        assert!(fuzzed.min <= card.ivl && card.ivl <= fuzzed.max);
+        let deck = col.storage.get_all_decks().unwrap().remove(&1).unwrap();
+        assert_eq!(deck.rev_today, (today, 3));
+    }
+
+    #[test]
+    fn test_fuzz_is_reproducible() {
+        let mut col = open_test_collection();
+        let today = col.timing_today().unwrap().days_elapsed;
+        col.storage.add_test_note(1, "").unwrap();
+        let card = Card {
+            id: 1,
+            nid: 1,
+            ctype: CardType::Review,
+            queue: CardQueue::Review,
+            due: today as i32,
+            ivl: 100,
+            factor: 2500,
+            reps: 5,
+            ..Default::default()
+        };
+        col.storage.add_card(&card).unwrap();
+
+        let first = col.answer_card(1, Answer::Good, 1000).unwrap();
+        let first_ivl = col.storage.get_card(1).unwrap().unwrap().ivl;
+        col.storage.update_card(&card).unwrap();
+        let second = col.answer_card(1, Answer::Good, 1000).unwrap();
+        assert_eq!(col.storage.get_card(1).unwrap().unwrap().ivl, first_ivl);
+        assert_eq!(first.fuzzed_interval, second.fuzzed_interval);
+        let fuzzed = first.fuzzed_interval.unwrap();
+        assert_eq!((fuzzed.min, fuzzed.max), (238, 262));
+    }
+
+    #[test]
+    fn test_load_balancing() {
+        let mut col = open_test_collection();
+        let today = col.timing_today().unwrap().days_elapsed;
+        let mut config = col.storage.get_config().unwrap();
+        config.load_balance = true;
+        col.storage.set_config(&config).unwrap();
+
+        col.storage.add_test_note(1, "").unwrap();
+        let card = Card {
+            id: 1,
+            nid: 1,
+            ctype: CardType::Review,
+            queue: CardQueue::Review,
+            due: today as i32,
+            ivl: 100,
+            factor: 2500,
+            ..Default::default()
+        };
+        col.storage.add_card(&card).unwrap();
+        // every day in the range is busy apart from one
+        for ivl in 238..=262 {
+            if ivl != 245 {
+                col.storage
+                    .add_card(&Card {
+                        id: 1000 + i64::from(ivl),
+                        nid: 1,
+                        ctype: CardType::Review,
+                        queue: CardQueue::Review,
+                        due: (today + ivl) as i32,
+                        ..Default::default()
+                    })
+                    .unwrap();
+            }
+        }
+
+        let outcome = col.answer_card(1, Answer::Good, 1000).unwrap();
+        assert_eq!(outcome.fuzzed_interval.unwrap().interval, 245);
+        let card = col.storage.get_card(1).unwrap().unwrap();
+        assert_eq!((card.ivl, card.due), (245, (today + 245) as i32));
+        assert_eq!(revlog(&col)[0].2, 245);
+    }
+
+    #[test]
+    fn test_answer_errors() {
+        let mut col = open_test_collection();
+        match col.answer_card(5, Answer::Good, 0) {
+            Err(AnkiError::NotFound) => (),
+            other => panic!("unexpected result: {:?}", other),
+        }
+
+        col.storage
+            .add_card(&Card {
+                id: 1,
+                queue: CardQueue::Suspended,
+                ..Default::default()
+            })
+            .unwrap();
+        assert!(col.answer_card(1, Answer::Good, 0).is_err());
+        // nothing is logged on failure
+        assert!(revlog(&col).is_empty());
+    }
+}
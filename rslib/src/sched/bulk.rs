@@ -0,0 +1,358 @@
+//! Changing the scheduling state of many cards at once.
+
+use crate::card::{Card, CardQueue, CardType};
+use crate::collection::Collection;
+use crate::err::{AnkiError, Result};
+use crate::revlog::{RevlogEntry, RevlogReviewKind};
+use crate::sched::due_date::MAX_DUE_DAYS;
+use crate::time::{i64_unix_millis, i64_unix_secs};
+use crate::undo::UndoableOp;
+use rand::Rng;
+use std::collections::HashMap;
+
+/// The ease factor given to forgotten and rescheduled cards, in permille.
+const STARTING_FACTOR: u16 = 2500;
+
+/// Return a card in a filtered deck to its home deck, restoring its
+/// original due number.
+pub(super) fn remove_from_filtered_deck(card: &mut Card) {
+    if card.odid != 0 {
+        if card.odue != 0 {
+            card.due = card.odue;
+        }
+        card.remove_from_filtered_deck();
+    }
+}
+
+impl Collection {
+    /// Apply `func` to each of the cards in `cids`, saving those it returns
+    /// true for with the modification time and usn updated. Missing cards
+    /// are skipped. Returns the number of cards saved.
+    pub(super) fn update_cards<F>(&mut self, cids: &[i64], mut func: F) -> Result<u32>
+    where
+        F: FnMut(&mut Card) -> bool,
+    {
+        let mtime = i64_unix_secs();
+        let usn = self.usn()?;
+        let mut count = 0;
+        for cid in cids {
+            if let Some(mut card) = self.storage.get_card(*cid)? {
+                if func(&mut card) {
+                    card.mtime = mtime;
+                    card.usn = usn;
+                    self.storage.update_card(&card)?;
+                    count += 1;
+                }
+            }
+        }
+        Ok(count)
+    }
+
+    /// Suspend cards, returning the number that were not already
+    /// suspended.
+    pub fn suspend_cards(&mut self, cids: &[i64]) -> Result<u32> {
+        self.transact(Some(UndoableOp::Suspend), |col| {
+            col.update_cards(cids, |card| {
+                if card.queue == CardQueue::Suspended {
+                    return false;
+                }
+                card.queue = CardQueue::Suspended;
+                true
+            })
+        })
+    }
+
+    /// Return suspended cards to their normal queues, returning the number
+    /// that were suspended.
+    pub fn unsuspend_cards(&mut self, cids: &[i64]) -> Result<u32> {
+        self.transact(Some(UndoableOp::Unsuspend), |col| {
+            col.update_cards(cids, |card| {
+                if card.queue != CardQueue::Suspended {
+                    return false;
+                }
+                card.restore_queue();
+                true
+            })
+        })
+    }
+
+    /// Bury cards until the next day, as the user or as a sibling of an
+    /// answered card. Suspended and already buried cards are left alone.
+    /// Returns the number of cards buried.
+    pub fn bury_cards(&mut self, cids: &[i64], manual: bool) -> Result<u32> {
+        let queue = if manual {
+            CardQueue::UserBuried
+        } else {
+            CardQueue::SiblingBuried
+        };
+        self.transact(Some(UndoableOp::Bury), |col| {
+            col.update_cards(cids, |card| match card.queue {
+                CardQueue::Suspended | CardQueue::SiblingBuried | CardQueue::UserBuried => false,
+                _ => {
+                    card.queue = queue;
+                    true
+                }
+            })
+        })
+    }
+
+    /// Bury all the cards of the provided notes until the next day.
+    pub fn bury_notes(&mut self, nids: &[i64]) -> Result<u32> {
+        let cids = self.storage.card_ids_of_notes(nids)?;
+        self.bury_cards(&cids, true)
+    }
+
+    /// Return buried cards to their normal queues, returning the number
+    /// that were buried.
+    pub fn unbury_cards(&mut self, cids: &[i64]) -> Result<u32> {
+        self.transact(Some(UndoableOp::Unbury), |col| {
+            col.update_cards(cids, |card| match card.queue {
+                CardQueue::SiblingBuried | CardQueue::UserBuried => {
+                    card.restore_queue();
+                    true
+                }
+                _ => false,
+            })
+        })
+    }
+
+    /// Turn cards back into new cards, placed at the end of the new queue
+    /// in the order given. Cards of the same note share a position.
+    pub fn forget_cards(&mut self, cids: &[i64]) -> Result<u32> {
+        self.transact(Some(UndoableOp::Forget), |col| col.forget_cards_inner(cids))
+    }
+
+    pub(super) fn forget_cards_inner(&mut self, cids: &[i64]) -> Result<u32> {
+        let mut next_position = self.storage.max_new_position()? + 1;
+        let mut positions = HashMap::new();
+        self.update_cards(cids, |card| {
+            remove_from_filtered_deck(card);
+            card.ctype = CardType::New;
+            card.queue = CardQueue::New;
+            card.ivl = 0;
+            card.odue = 0;
+            card.left = 0;
+            card.factor = STARTING_FACTOR;
+            card.due = *positions.entry(card.nid).or_insert_with(|| {
+                next_position += 1;
+                next_position - 1
+            });
+            true
+        })
+    }
+
+    /// Make cards review cards due in a random number of days between
+    /// `min_days` and `max_days`, with the starting ease. A manual entry is
+    /// added to the review log for each card.
+    pub fn reschedule_cards(&mut self, cids: &[i64], min_days: u32, max_days: u32) -> Result<u32> {
+        if min_days > max_days {
+            return Err(AnkiError::invalid_input("min days is greater than max"));
+        }
+        if max_days > MAX_DUE_DAYS {
+            return Err(AnkiError::invalid_input("max days is too large"));
+        }
+        self.transact(Some(UndoableOp::Reschedule), |col| {
+            let today = col.timing_today()?.days_elapsed;
+            let usn = col.usn()?;
+            let now_millis = i64_unix_millis();
+            let mut rng = rand::thread_rng();
+            let mut entries = vec![];
+            let count = col.update_cards(cids, |card| {
+                let days = rng.gen_range(min_days, max_days + 1);
+                let last_interval = card.ivl;
+                remove_from_filtered_deck(card);
+                card.ctype = CardType::Review;
+                card.queue = CardQueue::Review;
+                card.ivl = days.max(1);
+                card.due = (today + days) as i32;
+                card.odue = 0;
+                card.left = 0;
+                card.factor = STARTING_FACTOR;
+                entries.push(RevlogEntry {
+                    id: now_millis,
+                    cid: card.id,
+                    usn,
+                    ease: 0,
+                    interval: card.ivl as i32,
+                    last_interval: last_interval as i32,
+                    factor: u32::from(card.factor),
+                    taken_millis: 0,
+                    kind: RevlogReviewKind::Manual,
+                });
+                true
+            })?;
+            col.storage.add_revlog_entries(&entries)?;
+            Ok(count)
+        })
+    }
+
+    /// Reset cards to their state when first added, as when exporting
+    /// without scheduling. Cards that have been studied are forgotten and
+    /// moved to the end of the new queue; new cards keep their position.
+    pub fn reset_cards(&mut self, cids: &[i64]) -> Result<u32> {
+        self.transact(Some(UndoableOp::ResetCards), |col| {
+            let mut studied = vec![];
+            let count = col.update_cards(cids, |card| {
+                if card.ctype != CardType::New || card.queue != CardQueue::New {
+                    studied.push(card.id);
+                }
+                remove_from_filtered_deck(card);
+                card.queue = CardQueue::New;
+                card.reps = 0;
+                card.lapses = 0;
+                card.odue = 0;
+                true
+            })?;
+            col.forget_cards_inner(&studied)?;
+            Ok(count)
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::card::{Card, CardQueue, CardType};
+    use crate::collection::{open_test_collection, Collection};
+    use crate::revlog::RevlogReviewKind;
+    use crate::sched::due_date::MAX_DUE_DAYS;
+    use crate::time::i64_unix_millis;
+    use rusqlite::{params, NO_PARAMS};
+
+    fn add_cards(col: &Collection) {
+        let cards = vec![
+            (1, 1, CardType::New, CardQueue::New, 5),
+            (2, 1, CardType::Review, CardQueue::Review, 100),
+            (3, 2, CardType::Learn, CardQueue::Learn, 1_500_000_000),
+            (4, 2, CardType::Relearn, CardQueue::Suspended, 100),
+            (5, 3, CardType::Review, CardQueue::UserBuried, 100),
+        ];
+        for (id, nid, ctype, queue, due) in cards {
+            col.storage
+                .add_card(&Card {
+                    id,
+                    nid,
+                    ctype,
+                    queue,
+                    due,
+                    ivl: 10,
+                    reps: 3,
+                    lapses: 1,
+                    ..Default::default()
+                })
+                .unwrap();
+        }
+    }
+
+    fn queue(col: &Collection, cid: i64) -> CardQueue {
+        col.storage.get_card(cid).unwrap().unwrap().queue
+    }
+
+    #[test]
+    fn test_suspend_and_bury() {
+        let mut col = open_test_collection();
+        add_cards(&col);
+
+        assert_eq!(col.suspend_cards(&[1, 3, 4, 99]).unwrap(), 2);
+        assert_eq!(queue(&col, 1), CardQueue::Suspended);
+        assert_eq!(col.unsuspend_cards(&[1, 2, 3, 4]).unwrap(), 3);
+        assert_eq!(queue(&col, 1), CardQueue::New);
+        assert_eq!(queue(&col, 3), CardQueue::Learn);
+        assert_eq!(queue(&col, 4), CardQueue::DayLearn);
+
+        assert_eq!(col.bury_cards(&[1, 5], false).unwrap(), 1);
+        assert_eq!(queue(&col, 1), CardQueue::SiblingBuried);
+        assert_eq!(col.bury_notes(&[2]).unwrap(), 2);
+        assert_eq!(queue(&col, 3), CardQueue::UserBuried);
+        assert_eq!(col.unbury_cards(&[1, 2, 3, 4, 5]).unwrap(), 4);
+        assert_eq!(queue(&col, 5), CardQueue::Review);
+        let card = col.storage.get_card(5).unwrap().unwrap();
+        assert_ne!(card.mtime, 0);
+    }
+
+    #[test]
+    fn test_forget_and_reschedule() {
+        let mut col = open_test_collection();
+        add_cards(&col);
+        let today = col.timing_today().unwrap().days_elapsed as i32;
+
+        assert_eq!(col.forget_cards(&[3, 2, 4]).unwrap(), 3);
+        let card = |id| col.storage.get_card(id).unwrap().unwrap();
+        // cards of the same note share a position after existing new cards
+        assert_eq!(
+            (card(3).ctype, card(3).queue, card(3).due, card(3).ivl),
+            (CardType::New, CardQueue::New, 6, 0)
+        );
+        assert_eq!(card(2).due, 7);
+        assert_eq!(card(4).due, 6);
+        assert_eq!(card(2).factor, 2500);
+
+        assert_eq!(col.reschedule_cards(&[1, 2], 5, 5).unwrap(), 2);
+        let card = |id| col.storage.get_card(id).unwrap().unwrap();
+        assert_eq!(
+            (card(1).ctype, card(1).queue, card(1).due, card(1).ivl),
+            (CardType::Review, CardQueue::Review, today + 5, 5)
+        );
+        let kinds: Vec<u8> = col
+            .storage
+            .db
+            .prepare("select type from revlog")
+            .unwrap()
+            .query_map(NO_PARAMS, |row| row.get(0))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(kinds, vec![RevlogReviewKind::Manual as u8; 2]);
+        assert!(col.reschedule_cards(&[1], 5, 4).is_err());
+        assert!(col.reschedule_cards(&[1], 0, MAX_DUE_DAYS + 1).is_err());
+
+        assert_eq!(col.reset_cards(&[1, 5]).unwrap(), 2);
+        let card = |id| col.storage.get_card(id).unwrap().unwrap();
+        assert_eq!((card(1).reps, card(1).lapses), (0, 0));
+        assert_eq!(
+            (card(5).ctype, card(5).queue, card(5).due),
+            (CardType::New, CardQueue::New, 8)
+        );
+
+        // entries logged in the same millisecond are given unique ids
+        let cids: Vec<_> = (1..=5).collect();
+        assert_eq!(col.reschedule_cards(&cids, 1, 3).unwrap(), 5);
+        assert_eq!(col.reschedule_cards(&cids, 1, 3).unwrap(), 5);
+        let (entries, ids): (u32, u32) = col
+            .storage
+            .db
+            .query_row(
+                "select count(), count(distinct id) from revlog",
+                NO_PARAMS,
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .unwrap();
+        assert_eq!((entries, ids), (12, 12));
+
+        // an entry dated in the future does not move the ids of later ones
+        let future = i64_unix_millis() + 86_400_000;
+        col.storage
+            .db
+            .execute("delete from revlog", NO_PARAMS)
+            .unwrap();
+        col.storage
+            .db
+            .execute(
+                "insert into revlog values (?, 1, 0, 0, 0, 0, 0, 0, 0)",
+                params![future],
+            )
+            .unwrap();
+        let before = i64_unix_millis();
+        col.reschedule_cards(&[1, 2], 1, 3).unwrap();
+        let ids: Vec<i64> = col
+            .storage
+            .db
+            .prepare("select id from revlog where id != ? order by id")
+            .unwrap()
+            .query_map(params![future], |row| row.get(0))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(ids.len(), 2);
+        assert!(ids[0] >= before && ids[1] < before + 60_000);
+    }
+}
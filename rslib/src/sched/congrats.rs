@@ -0,0 +1,139 @@
+//! What is left in the selected decks once their cards have been studied,
+//! for the screen shown when there is nothing more to study.
+
+use crate::card::CardQueue;
+use crate::collection::Collection;
+use crate::err::Result;
+use crate::sched::limits::{
+    new_limit_single, overdue_in_decks, review_limit, walk_limits, REPORT_LIMIT,
+};
+
+/// The state of the selected deck and its children, leaving the wording to
+/// the client.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CongratsInfo {
+    /// The selected deck.
+    pub deck_id: i64,
+    pub is_filtered_deck: bool,
+    /// When the next learning card is due, as a timestamp in seconds, if
+    /// one is waiting.
+    pub next_learning_due: Option<i64>,
+    /// Reviews due today that are over the daily limit, up to 1000.
+    pub reviews_over_limit: u32,
+    /// New cards that are over today's limit.
+    pub new_over_limit: u32,
+    pub have_sibling_buried: bool,
+    pub have_user_buried: bool,
+    pub have_suspended: bool,
+}
+
+impl Collection {
+    pub fn congrats_info(&mut self) -> Result<CongratsInfo> {
+        let study_day = self.study_day()?;
+        let config = self.storage.get_config()?;
+        let decks = self.storage.get_all_decks()?;
+        let confs = self.storage.get_all_deck_conf()?;
+        let active: Vec<i64> = config
+            .active_decks
+            .iter()
+            .filter(|did| decks.contains_key(did))
+            .cloned()
+            .collect();
+
+        let mut new_within_limit = 0;
+        walk_limits(
+            &active,
+            &decks,
+            |deck| new_limit_single(deck, &confs, study_day),
+            |did, limit| {
+                let count = self.storage.new_count_in_deck(did, limit)?;
+                new_within_limit += count;
+                Ok(count)
+            },
+        )?;
+        let new_counts = self.storage.new_counts()?;
+        let new_total: u32 = active.iter().filter_map(|did| new_counts.get(did)).sum();
+
+        let overdue = overdue_in_decks(&decks, &self.storage.overdue_review_counts(study_day.day)?);
+        let review_limit = review_limit(config.current_deck, &decks, &confs, study_day, &overdue);
+        let reviews_due = self.storage.review_count_in_decks(
+            &active,
+            study_day.day,
+            review_limit.saturating_add(REPORT_LIMIT),
+        )?;
+
+        Ok(CongratsInfo {
+            deck_id: config.current_deck,
+            is_filtered_deck: decks
+                .get(&config.current_deck)
+                .map(|deck| deck.is_filtered())
+                .unwrap_or(false),
+            next_learning_due: self.storage.next_learning_due(&active)?,
+            reviews_over_limit: reviews_due.saturating_sub(review_limit),
+            new_over_limit: new_total.saturating_sub(new_within_limit),
+            have_sibling_buried: self
+                .storage
+                .have_cards_in_queue(&active, CardQueue::SiblingBuried)?,
+            have_user_buried: self
+                .storage
+                .have_cards_in_queue(&active, CardQueue::UserBuried)?,
+            have_suspended: self
+                .storage
+                .have_cards_in_queue(&active, CardQueue::Suspended)?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::card::{Card, CardQueue, CardType};
+    use crate::collection::open_test_collection;
+
+    #[test]
+    fn test_congrats_info() {
+        let mut col = open_test_collection();
+        let today = col.timing_today().unwrap().days_elapsed as i32;
+        col.storage.add_test_note(1, "").unwrap();
+        let info = col.congrats_info().unwrap();
+        assert_eq!(info.deck_id, 1);
+        assert!(!info.is_filtered_deck);
+        assert_eq!(info.next_learning_due, None);
+        assert_eq!((info.new_over_limit, info.reviews_over_limit), (0, 0));
+        assert!(!info.have_sibling_buried && !info.have_user_buried && !info.have_suspended);
+
+        let mut confs = col.storage.get_all_deck_conf().unwrap();
+        let conf = confs.get_mut(&1).unwrap();
+        conf.new.per_day = 2;
+        conf.rev.per_day = 1;
+        col.storage.set_all_deck_conf(&confs).unwrap();
+        let mut id = 0;
+        let mut add = |ctype, queue, due| {
+            id += 1;
+            col.storage
+                .add_card(&Card {
+                    id,
+                    nid: 1,
+                    ctype,
+                    queue,
+                    due,
+                    ..Default::default()
+                })
+                .unwrap();
+        };
+        for due in 1..=5 {
+            add(CardType::New, CardQueue::New, due);
+        }
+        add(CardType::Review, CardQueue::Review, today);
+        add(CardType::Review, CardQueue::Review, today - 1);
+        add(CardType::Review, CardQueue::Review, today + 1);
+        add(CardType::Learn, CardQueue::Learn, 2_000_000_000);
+        add(CardType::Learn, CardQueue::Learn, 1_900_000_000);
+        add(CardType::Review, CardQueue::UserBuried, today);
+        add(CardType::Review, CardQueue::Suspended, today);
+
+        let info = col.congrats_info().unwrap();
+        assert_eq!(info.next_learning_due, Some(1_900_000_000));
+        assert_eq!((info.new_over_limit, info.reviews_over_limit), (3, 1));
+        assert!(!info.have_sibling_buried && info.have_user_buried && info.have_suspended);
+    }
+}
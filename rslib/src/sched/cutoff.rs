@@ -26,6 +26,33 @@ pub fn sched_timing_today(
     }
 }
 
+/// Timing information for the current day, calculated the way the older
+/// Python code does when the new timezone handling is off: days are
+/// counted from the creation time's local date, and the rollover is based
+/// on the machine's local timezone.
+pub fn sched_timing_today_legacy(created: i64, now: i64, rollover_hour: i8) -> SchedTimingToday {
+    let rollover_hour = normalized_rollover_hour(rollover_hour) as u32;
+    let local_rollover = |timestamp: i64| {
+        let date = Local.timestamp(timestamp, 0).date();
+        date.and_hms_opt(rollover_hour, 0, 0)
+            .map(|dt| dt.timestamp())
+            .unwrap_or(timestamp)
+    };
+
+    let created_rollover = local_rollover(created);
+    let days_elapsed = ((now - created_rollover) / 86_400).max(0) as u32;
+
+    let mut next_day_at = local_rollover(now);
+    if next_day_at < now {
+        next_day_at += 86_400;
+    }
+
+    SchedTimingToday {
+        days_elapsed,
+        next_day_at,
+    }
+}
+
 /// Convert timestamp to the local timezone, with the provided rollover hour.
 fn rollover_for_today(
     timestamp: i64,
@@ -66,7 +93,7 @@ fn normalized_rollover_hour(hour: i8) -> u8 {
 }
 
 /// Build a FixedOffset struct, capping minutes_west if out of bounds.
-fn fixed_offset_from_minutes(minutes_west: i32) -> FixedOffset {
+pub(crate) fn fixed_offset_from_minutes(minutes_west: i32) -> FixedOffset {
     let bounded_minutes = minutes_west.max(-23 * 60).min(23 * 60);
     FixedOffset::west(bounded_minutes * 60)
 }
@@ -74,16 +101,15 @@ fn fixed_offset_from_minutes(minutes_west: i32) -> FixedOffset {
 /// Relative to the local timezone, the number of minutes UTC differs by.
 /// eg, Australia at +10 hours is -600.
 /// Includes the daylight savings offset if applicable.
-#[allow(dead_code)]
-fn utc_minus_local_mins() -> i32 {
+pub(crate) fn utc_minus_local_mins() -> i32 {
     Local::now().offset().utc_minus_local() / 60
 }
 
 #[cfg(test)]
 mod test {
-    use crate::sched::{
+    use crate::sched::cutoff::{
         fixed_offset_from_minutes, normalized_rollover_hour, rollover_for_today,
-        sched_timing_today, utc_minus_local_mins,
+        sched_timing_today, sched_timing_today_legacy, utc_minus_local_mins,
     };
     use chrono::{Datelike, FixedOffset, TimeZone, Timelike};
 
@@ -155,4 +181,23 @@ mod test {
         let offset = mdt.utc_minus_local() / 60;
         assert_eq!(elap(crt, now, offset, 4), 507);
     }
+
+    #[test]
+    fn test_legacy_timing() {
+        let offset = utc_minus_local_mins();
+        let crt = FixedOffset::west(offset * 60)
+            .ymd(2019, 12, 1)
+            .and_hms(2, 0, 0)
+            .timestamp();
+
+        // the creation day starts at the rollover, even if created before it
+        let today = sched_timing_today_legacy(crt, crt + 3600, 4);
+        assert_eq!(today.days_elapsed, 0);
+        assert_eq!(today.next_day_at, crt + 2 * 3600);
+        let today = sched_timing_today_legacy(crt, crt + 3 * 3600, 4);
+        assert_eq!(today.days_elapsed, 0);
+        assert_eq!(today.next_day_at, crt + 26 * 3600);
+        let today = sched_timing_today_legacy(crt, crt + 26 * 3600, 4);
+        assert_eq!(today.days_elapsed, 1);
+    }
 }
@@ -0,0 +1,279 @@
+//! The deck list, with the number of cards due in each deck.
+
+use crate::collection::Collection;
+use crate::decks::{deck_and_child_ids, decks_in_tree_order, Deck};
+use crate::err::Result;
+use crate::sched::limits::{new_limit_single, overdue_in_decks, review_limit_single, REPORT_LIMIT};
+use crate::time::i64_unix_secs;
+use std::collections::HashMap;
+
+/// A deck in the deck list. The top node has no deck, and holds the
+/// top-level decks as its children.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct DeckTreeNode {
+    /// The components of the deck's name, split on `::`.
+    pub names: Vec<String>,
+    pub deck_id: i64,
+    pub review_count: u32,
+    pub learn_count: u32,
+    pub new_count: u32,
+    pub children: Vec<DeckTreeNode>,
+    pub collapsed: bool,
+}
+
+/// The counts of a single deck before they are grouped into a tree.
+#[derive(Debug, Clone)]
+struct DeckCounts<'a> {
+    components: Vec<&'a str>,
+    deck: &'a Deck,
+    review: u32,
+    learn: u32,
+    new: u32,
+}
+
+/// Nest the provided decks under their first `depth` name components.
+/// `counts` must be sorted by name components, so that decks sharing a
+/// component are adjacent.
+///
+/// Learning and new counts of children are added to their parents, and
+/// the new count is then capped by the parent's own limit. Review counts
+/// already include children.
+fn group_children(
+    counts: &[DeckCounts],
+    depth: usize,
+    new_limit: &dyn Fn(&Deck) -> u32,
+) -> Vec<DeckTreeNode> {
+    let mut nodes = vec![];
+    let mut start = 0;
+    while start < counts.len() {
+        let head = counts[start].components[depth];
+        let end = start
+            + counts[start..]
+                .iter()
+                .take_while(|c| c.components[depth] == head)
+                .count();
+        let group = &counts[start..end];
+        start = end;
+
+        let own = group.iter().find(|c| c.components.len() == depth + 1);
+        let children: Vec<_> = group
+            .iter()
+            .filter(|c| c.components.len() > depth + 1)
+            .cloned()
+            .collect();
+        let children = group_children(&children, depth + 1, new_limit);
+
+        let mut node = DeckTreeNode {
+            names: group[0].components[..=depth]
+                .iter()
+                .map(|s| s.to_string())
+                .collect(),
+            ..Default::default()
+        };
+        if let Some(own) = own {
+            node.deck_id = own.deck.id;
+            node.review_count = own.review;
+            node.learn_count = own.learn;
+            node.new_count = own.new;
+            node.collapsed = own.deck.collapsed;
+        }
+        for child in &children {
+            node.learn_count += child.learn_count;
+            node.new_count += child.new_count;
+        }
+        if let Some(own) = own {
+            if !own.deck.is_filtered() {
+                node.new_count = node.new_count.min(new_limit(own.deck));
+            }
+        }
+        node.children = children;
+        nodes.push(node);
+    }
+    nodes
+}
+
+impl Collection {
+    /// Build the deck list, with the number of cards due today in each
+    /// deck.
+    ///
+    /// A deck's new and review counts are capped by its own daily limits
+    /// and those of its parents. Parents include the new and learning
+    /// cards of their children; review counts include children as they
+    /// are counted. All counts are capped at 1000 per deck.
+    pub fn deck_tree(&mut self) -> Result<DeckTreeNode> {
+        let now = i64_unix_secs();
+        let study_day = self.study_day()?;
+        let today = study_day.day;
+        let config = self.storage.get_config()?;
+        let decks = self.storage.get_all_decks()?;
+        let confs = self.storage.get_all_deck_conf()?;
+        let overdue = overdue_in_decks(&decks, &self.storage.overdue_review_counts(today)?);
+        let learn_cutoff = now + i64::from(config.collapse_time);
+
+        // parents come before their children, so their limits are known
+        // by the time the children are counted
+        let mut limits: HashMap<String, (u32, u32)> = HashMap::new();
+        let mut counts = Vec::with_capacity(decks.len());
+        for deck in decks_in_tree_order(&decks) {
+            let components: Vec<&str> = deck.name.split("::").collect();
+            let parent_limits = if components.len() > 1 {
+                limits
+                    .get(&components[..components.len() - 1].join("::").to_lowercase())
+                    .cloned()
+            } else {
+                None
+            };
+
+            let mut new_limit = new_limit_single(deck, &confs, study_day);
+            let mut review_limit = review_limit_single(deck, &confs, study_day, &overdue);
+            if let Some((parent_new, parent_review)) = parent_limits {
+                new_limit = new_limit.min(parent_new);
+                // filtered decks ignore the review limits of their parents
+                if !deck.is_filtered() {
+                    review_limit = review_limit.min(parent_review);
+                }
+            }
+
+            let new = if new_limit > 0 {
+                self.storage
+                    .new_count_in_deck(deck.id, new_limit.min(REPORT_LIMIT))?
+            } else {
+                0
+            };
+            let learn =
+                self.storage
+                    .learning_count_in_deck(deck.id, learn_cutoff, today, REPORT_LIMIT)?;
+            let review = self.storage.review_count_in_decks(
+                &deck_and_child_ids(&decks, deck.id),
+                today,
+                review_limit.min(REPORT_LIMIT),
+            )?;
+
+            limits.insert(deck.name.to_lowercase(), (new_limit, review_limit));
+            counts.push(DeckCounts {
+                components,
+                deck,
+                review,
+                learn,
+                new,
+            });
+        }
+
+        Ok(DeckTreeNode {
+            children: group_children(&counts, 0, &|deck| {
+                new_limit_single(deck, &confs, study_day)
+            }),
+            ..Default::default()
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::DeckTreeNode;
+    use crate::card::{Card, CardQueue, CardType};
+    use crate::collection::open_test_collection;
+    use crate::decks::Deck;
+    use crate::time::i64_unix_secs;
+
+    fn find<'a>(node: &'a DeckTreeNode, names: &[&str]) -> &'a DeckTreeNode {
+        match names.split_first() {
+            None => node,
+            Some((head, rest)) => find(
+                node.children
+                    .iter()
+                    .find(|c| c.names.last().map(String::as_str) == Some(*head))
+                    .unwrap(),
+                rest,
+            ),
+        }
+    }
+
+    #[test]
+    fn test_deck_tree() {
+        let mut col = open_test_collection();
+        let today = col.timing_today().unwrap().days_elapsed as i32;
+        let now = i64_unix_secs();
+
+        let mut decks = col.storage.get_all_decks().unwrap();
+        let default = decks[&1].clone();
+        for (id, name) in &[
+            (2, "Default::Child"),
+            (3, "Default::Child::Leaf"),
+            (4, "Other"),
+        ] {
+            decks.insert(
+                *id,
+                Deck {
+                    id: *id,
+                    name: name.to_string(),
+                    collapsed: *id == 2,
+                    ..default.clone()
+                },
+            );
+        }
+        // only 3 more new cards may be studied in the top-level deck
+        decks.get_mut(&1).unwrap().new_today = (today as u32, 17);
+        col.storage.set_all_decks(&decks).unwrap();
+
+        let mut id = 0;
+        let mut add = |did, ctype, queue, due| {
+            id += 1;
+            col.storage
+                .add_card(&Card {
+                    id,
+                    nid: id,
+                    did,
+                    ctype,
+                    queue,
+                    due,
+                    ..Default::default()
+                })
+                .unwrap();
+        };
+        for did in &[1, 2, 2, 3, 3, 4] {
+            add(*did, CardType::New, CardQueue::New, 0);
+        }
+        add(3, CardType::Review, CardQueue::Review, today);
+        add(3, CardType::Review, CardQueue::Review, today + 1);
+        add(2, CardType::Review, CardQueue::Review, today - 5);
+        add(2, CardType::Learn, CardQueue::Learn, (now + 60) as i32);
+        add(3, CardType::Relearn, CardQueue::DayLearn, today);
+        add(4, CardType::Learn, CardQueue::Learn, (now + 3600) as i32);
+
+        let tree = col.deck_tree().unwrap();
+        let names: Vec<_> = tree.children.iter().map(|c| c.names.clone()).collect();
+        assert_eq!(names, vec![vec!["Default"], vec!["Other"]]);
+
+        let leaf = find(&tree, &["Default", "Child", "Leaf"]);
+        assert_eq!(leaf.names, vec!["Default", "Child", "Leaf"]);
+        assert_eq!(leaf.deck_id, 3);
+        assert_eq!(
+            (leaf.review_count, leaf.learn_count, leaf.new_count),
+            (1, 1, 2)
+        );
+        assert!(!leaf.collapsed);
+
+        // children's new and learning cards are included in their parents
+        let child = find(&tree, &["Default", "Child"]);
+        assert_eq!(
+            (child.review_count, child.learn_count, child.new_count),
+            (2, 2, 4)
+        );
+        assert!(child.collapsed);
+
+        // but the new count can't exceed the 3 cards left for today
+        let top = find(&tree, &["Default"]);
+        assert_eq!(
+            (top.review_count, top.learn_count, top.new_count),
+            (2, 2, 3)
+        );
+
+        // learning cards outside the learn ahead limit are not counted
+        let other = find(&tree, &["Other"]);
+        assert_eq!(
+            (other.review_count, other.learn_count, other.new_count),
+            (0, 0, 1)
+        );
+    }
+}
@@ -0,0 +1,240 @@
+//! Moving cards to a chosen due date.
+
+use crate::card::{CardQueue, CardType};
+use crate::collection::Collection;
+use crate::deckconf::CardConf;
+use crate::err::{AnkiError, Result};
+use crate::revlog::{RevlogEntry, RevlogReviewKind};
+use crate::sched::bulk::remove_from_filtered_deck;
+use crate::time::i64_unix_millis;
+use crate::undo::UndoableOp;
+use rand::Rng;
+
+/// The furthest due date that can be set, in days from today.
+pub(crate) const MAX_DUE_DAYS: u32 = 36_500;
+
+/// A range of days from today to pick a due date from, parsed from text
+/// like `0`, `3-7` or `7!`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DueDateSpecifier {
+    pub min: u32,
+    pub max: u32,
+    /// Set the interval to the number of days picked, instead of keeping
+    /// the card's current interval.
+    pub set_interval: bool,
+}
+
+impl DueDateSpecifier {
+    pub fn parse(text: &str) -> Result<DueDateSpecifier> {
+        let invalid = || AnkiError::invalid_input(format!("invalid due date: '{}'", text));
+        let mut parts = text.trim().splitn(2, '!');
+        let range = parts.next().unwrap_or_default();
+        let set_interval = match parts.next() {
+            Some("") => true,
+            Some(_) => return Err(invalid()),
+            None => false,
+        };
+        let mut parts = range.splitn(2, '-');
+        let min: u32 = parts
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .parse()
+            .map_err(|_| invalid())?;
+        let max: u32 = match parts.next() {
+            Some(max) => max.trim().parse().map_err(|_| invalid())?,
+            None => min,
+        };
+        if min > max || max > MAX_DUE_DAYS {
+            return Err(invalid());
+        }
+        Ok(DueDateSpecifier {
+            min,
+            max,
+            set_interval,
+        })
+    }
+}
+
+impl Collection {
+    /// Make cards due a number of days from today picked from `spec`, such
+    /// as `3-7` for three to seven days. Review and relearning cards keep
+    /// their interval, unless the spec ends with `!`, which sets it to the
+    /// days picked. New and learning cards become review cards, with an
+    /// interval of the days picked and their deck's starting ease.
+    /// Relearning cards are returned to review. Suspended
+    /// and buried cards stay that way, and cards in filtered decks are
+    /// returned to their home decks.
+    ///
+    /// A manual entry is added to the review log for each card. Returns
+    /// the number of cards changed.
+    pub fn set_due_date(&mut self, cids: &[i64], spec: &str) -> Result<u32> {
+        let spec = DueDateSpecifier::parse(spec)?;
+        self.transact(Some(UndoableOp::SetDueDate), |col| {
+            let today = col.timing_today()?.days_elapsed;
+            let usn = col.usn()?;
+            let now_millis = i64_unix_millis();
+            let decks = col.storage.get_all_decks()?;
+            let confs = col.storage.get_all_deck_conf()?;
+            let mut rng = rand::thread_rng();
+            let mut entries = vec![];
+            let count = col.update_cards(cids, |card| {
+                let days = rng.gen_range(spec.min, spec.max + 1);
+                let due = (today + days) as i32;
+                let last_interval = card.ivl;
+                let was_review = card.ctype == CardType::Review || card.ctype == CardType::Relearn;
+                if card.factor == 0 {
+                    let conf = CardConf::for_card(card.did, card.odid, &decks, &confs).conf;
+                    card.factor = conf.new.initial_factor;
+                }
+                remove_from_filtered_deck(card);
+                if spec.set_interval || !was_review || card.ivl == 0 {
+                    card.ivl = days.max(1);
+                }
+                card.ctype = CardType::Review;
+                card.queue = match card.queue {
+                    CardQueue::Suspended | CardQueue::SiblingBuried | CardQueue::UserBuried => {
+                        card.queue
+                    }
+                    _ => CardQueue::Review,
+                };
+                card.due = due;
+                card.odue = 0;
+                card.left = 0;
+                entries.push(RevlogEntry {
+                    id: now_millis,
+                    cid: card.id,
+                    usn,
+                    ease: 0,
+                    interval: card.ivl as i32,
+                    last_interval: last_interval as i32,
+                    factor: u32::from(card.factor),
+                    taken_millis: 0,
+                    kind: RevlogReviewKind::Manual,
+                });
+                true
+            })?;
+            col.storage.add_revlog_entries(&entries)?;
+            Ok(count)
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::DueDateSpecifier;
+    use crate::card::{Card, CardQueue, CardType};
+    use crate::collection::{open_test_collection, Collection};
+
+    fn summary(col: &Collection, cid: i64) -> (CardType, CardQueue, i32, u32, i32) {
+        let card = col.storage.get_card(cid).unwrap().unwrap();
+        (card.ctype, card.queue, card.due, card.ivl, card.left)
+    }
+
+    #[test]
+    fn test_parse_spec() {
+        let spec = |min, max, set_interval| DueDateSpecifier {
+            min,
+            max,
+            set_interval,
+        };
+        assert_eq!(DueDateSpecifier::parse("0").unwrap(), spec(0, 0, false));
+        assert_eq!(DueDateSpecifier::parse("3-7").unwrap(), spec(3, 7, false));
+        assert_eq!(DueDateSpecifier::parse(" 7! ").unwrap(), spec(7, 7, true));
+        assert_eq!(DueDateSpecifier::parse("1 - 2!").unwrap(), spec(1, 2, true));
+        assert_eq!(
+            DueDateSpecifier::parse("36500").unwrap(),
+            spec(36500, 36500, false)
+        );
+        for bad in &[
+            "",
+            "!",
+            "a",
+            "-1",
+            "7-3",
+            "3-",
+            "1-2-3",
+            "3!!",
+            "36501",
+            "0-4294967295",
+        ] {
+            assert!(DueDateSpecifier::parse(bad).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn test_set_due_date() {
+        let mut col = open_test_collection();
+        let today = col.timing_today().unwrap().days_elapsed as i32;
+        let cards = vec![
+            (1, CardType::Review, CardQueue::Review, today + 2, 10, 2000),
+            (2, CardType::New, CardQueue::New, 1, 0, 0),
+            (3, CardType::Learn, CardQueue::Learn, 1, 0, 0),
+            (4, CardType::Relearn, CardQueue::Suspended, 1, 5, 1800),
+        ];
+        for (id, ctype, queue, due, ivl, factor) in cards {
+            col.storage
+                .add_card(&Card {
+                    id,
+                    nid: id,
+                    ctype,
+                    queue,
+                    due,
+                    ivl,
+                    factor,
+                    left: 2,
+                    ..Default::default()
+                })
+                .unwrap();
+        }
+
+        assert_eq!(col.set_due_date(&[1, 2, 3, 4], "3").unwrap(), 4);
+        let factor = |col: &Collection, cid| col.storage.get_card(cid).unwrap().unwrap().factor;
+        // review cards keep their interval
+        assert_eq!(
+            summary(&col, 1),
+            (CardType::Review, CardQueue::Review, today + 3, 10, 0)
+        );
+        assert_eq!(
+            summary(&col, 2),
+            (CardType::Review, CardQueue::Review, today + 3, 3, 0)
+        );
+        assert_eq!(factor(&col, 2), 2500);
+        assert_eq!(
+            summary(&col, 3),
+            (CardType::Review, CardQueue::Review, today + 3, 3, 0)
+        );
+        assert_eq!(
+            summary(&col, 4),
+            (CardType::Review, CardQueue::Suspended, today + 3, 5, 0)
+        );
+        assert_eq!(factor(&col, 4), 1800);
+
+        // moving it earlier keeps the interval too
+        col.set_due_date(&[1], "1").unwrap();
+        assert_eq!(
+            summary(&col, 1),
+            (CardType::Review, CardQueue::Review, today + 1, 10, 0)
+        );
+
+        // setting the interval too
+        col.set_due_date(&[1], "0!").unwrap();
+        assert_eq!(
+            summary(&col, 1),
+            (CardType::Review, CardQueue::Review, today, 1, 0)
+        );
+        col.set_due_date(&[1], "4!").unwrap();
+        assert_eq!(
+            summary(&col, 1),
+            (CardType::Review, CardQueue::Review, today + 4, 4, 0)
+        );
+
+        // a range
+        col.set_due_date(&[1], "5-6").unwrap();
+        let (_, _, due, ivl, _) = summary(&col, 1);
+        assert!(due == today + 5 || due == today + 6);
+        assert_eq!(ivl, 4);
+
+        assert!(col.set_due_date(&[1], "x").is_err());
+    }
+}
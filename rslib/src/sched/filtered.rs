@@ -0,0 +1,236 @@
+//! Building and emptying filtered decks.
+
+use crate::collection::Collection;
+use crate::decks::deck_and_child_ids;
+use crate::err::{AnkiError, Result};
+use crate::undo::UndoableOp;
+
+/// The order cards are gathered in for a filtered deck search.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FilteredSearchOrder {
+    OldestReviewedFirst = 0,
+    Random = 1,
+    IntervalsAscending = 2,
+    IntervalsDescending = 3,
+    Lapses = 4,
+    Added = 5,
+    Due = 6,
+    ReverseAdded = 7,
+    DuePriority = 8,
+}
+
+impl FilteredSearchOrder {
+    /// Convert the order stored in a filtered deck's terms. Unknown orders
+    /// fall back on due order.
+    pub fn from_u8(order: u8) -> FilteredSearchOrder {
+        use FilteredSearchOrder::*;
+        match order {
+            0 => OldestReviewedFirst,
+            1 => Random,
+            2 => IntervalsAscending,
+            3 => IntervalsDescending,
+            4 => Lapses,
+            5 => Added,
+            7 => ReverseAdded,
+            8 => DuePriority,
+            _ => Due,
+        }
+    }
+
+    /// An SQL order by clause for this order.
+    fn sql(self, today: u32) -> String {
+        use FilteredSearchOrder::*;
+        match self {
+            OldestReviewedFirst => "(select max(id) from revlog where cid = c.id)".into(),
+            Random => "random()".into(),
+            IntervalsAscending => "c.ivl".into(),
+            IntervalsDescending => "c.ivl desc".into(),
+            Lapses => "c.lapses desc".into(),
+            Added => "n.id".into(),
+            ReverseAdded => "n.id desc".into(),
+            Due => "c.due, c.ord".into(),
+            // overdue reviews first, ordered by how overdue they are
+            // relative to their interval
+            DuePriority => format!(
+                "(case when c.queue = 2 and c.due <= {today} \
+                 then (c.ivl / cast({today} - c.due + 0.001 as real)) \
+                 else 100000 + c.due end)",
+                today = today
+            ),
+        }
+    }
+}
+
+/// Cards moved into a filtered deck are given due numbers starting here,
+/// so they sort before any new cards.
+const FILTERED_DUE_START: i32 = -100_000;
+
+impl Collection {
+    /// Return the cards in a filtered deck to their home decks, then fill
+    /// it again using its search terms. If any cards were found, the deck
+    /// is selected.
+    ///
+    /// Returns the number of cards moved into the deck. If a search is
+    /// invalid, a search error is returned and no changes are made.
+    pub fn rebuild_filtered_deck(&mut self, did: i64) -> Result<u32> {
+        self.transact(Some(UndoableOp::RebuildFilteredDeck), |col| {
+            col.rebuild_filtered_deck_inner(did)
+        })
+    }
+
+    /// Return the cards in a filtered deck to their home decks.
+    pub fn empty_filtered_deck(&mut self, did: i64) -> Result<()> {
+        self.transact(Some(UndoableOp::EmptyFilteredDeck), |col| {
+            let decks = col.storage.get_all_decks()?;
+            let deck = decks.get(&did).ok_or(AnkiError::NotFound)?;
+            if !deck.is_filtered() {
+                return Err(AnkiError::invalid_input("not a filtered deck"));
+            }
+            let usn = col.usn()?;
+            col.storage.empty_filtered_deck(did, usn)?;
+            Ok(())
+        })
+    }
+
+    fn rebuild_filtered_deck_inner(&mut self, did: i64) -> Result<u32> {
+        let decks = self.storage.get_all_decks()?;
+        let deck = decks.get(&did).ok_or(AnkiError::NotFound)?;
+        if !deck.is_filtered() {
+            return Err(AnkiError::invalid_input("not a filtered deck"));
+        }
+        let usn = self.usn()?;
+        let today = self.timing_today()?.days_elapsed;
+        let preview = !deck.resched.unwrap_or(true);
+
+        self.storage.empty_filtered_deck(did, usn)?;
+
+        let mut total = 0;
+        for (search, limit, order) in deck.terms.iter().flatten() {
+            let search = if search.trim().is_empty() {
+                String::new()
+            } else {
+                format!("({})", search)
+            };
+            let search = format!("{} -is:suspended -is:buried -deck:filtered", search);
+            let order = format!(
+                "{} limit {}",
+                FilteredSearchOrder::from_u8(*order).sql(today),
+                limit
+            );
+            let cids = self.search_cards(&search, Some(&order))?;
+            self.storage.move_cards_to_filtered_deck(
+                &cids,
+                did,
+                FILTERED_DUE_START + total as i32,
+                preview,
+                usn,
+            )?;
+            total += cids.len() as u32;
+        }
+
+        if total > 0 {
+            let mut config = self.storage.get_config()?;
+            let mut children = deck_and_child_ids(&decks, did);
+            children[1..].sort();
+            config.current_deck = did;
+            config.active_decks = children;
+            self.storage.set_config(&config)?;
+        }
+
+        Ok(total)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::card::{Card, CardQueue, CardType};
+    use crate::collection::{open_test_collection, Collection};
+    use crate::decks::Deck;
+    use crate::err::AnkiError;
+
+    #[test]
+    fn test_filtered() {
+        let mut col = open_test_collection();
+        let today = col.timing_today().unwrap().days_elapsed as i32;
+
+        let mut decks = col.storage.get_all_decks().unwrap();
+        let filtered = Deck {
+            id: 2,
+            name: "Filtered".into(),
+            dynamic: 1,
+            conf: None,
+            resched: Some(true),
+            terms: Some(vec![("is:due".into(), 2, 2), ("is:new".into(), 100, 6)]),
+            ..decks[&1].clone()
+        };
+        decks.insert(2, filtered);
+        col.storage.set_all_decks(&decks).unwrap();
+
+        let cards = vec![
+            (1, CardType::Review, CardQueue::Review, today, 10),
+            (2, CardType::Review, CardQueue::Review, today - 1, 5),
+            (3, CardType::Review, CardQueue::Review, today, 2),
+            (4, CardType::Review, CardQueue::Suspended, today, 1),
+            (5, CardType::New, CardQueue::New, 7, 0),
+            (6, CardType::Relearn, CardQueue::DayLearn, today + 2, 1),
+        ];
+        for (id, ctype, queue, due, ivl) in cards {
+            col.storage.add_test_note(id, "").unwrap();
+            col.storage
+                .add_card(&Card {
+                    id,
+                    nid: id,
+                    did: 1,
+                    ctype,
+                    queue,
+                    due,
+                    ivl,
+                    ..Default::default()
+                })
+                .unwrap();
+        }
+
+        // the two smallest due intervals, then the new card
+        assert_eq!(col.rebuild_filtered_deck(2).unwrap(), 3);
+        let card = |col: &Collection, id| col.storage.get_card(id).unwrap().unwrap();
+        for (id, due) in &[(3, -100_000), (2, -99_999), (5, -99_998)] {
+            let c = card(&col, *id);
+            assert_eq!((c.did, c.odid, c.due), (2, 1, *due));
+        }
+        assert_eq!(card(&col, 3).odue, today);
+        assert_eq!(card(&col, 1).did, 1);
+        assert_eq!(card(&col, 4).did, 1);
+        assert_eq!(col.storage.get_config().unwrap().current_deck, 2);
+
+        // rebuilding again gives the same result
+        assert_eq!(col.rebuild_filtered_deck(2).unwrap(), 3);
+
+        col.empty_filtered_deck(2).unwrap();
+        for (id, due) in &[(3, today), (2, today - 1), (5, 7)] {
+            let c = card(&col, *id);
+            assert_eq!((c.did, c.odid, c.odue, c.due), (1, 0, 0, *due));
+        }
+
+        // invalid searches are reported, and nothing is changed
+        decks.get_mut(&2).unwrap().terms =
+            Some(vec![("is:new".into(), 100, 6), ("is:foo".into(), 100, 6)]);
+        col.storage.set_all_decks(&decks).unwrap();
+        match col.rebuild_filtered_deck(2) {
+            Err(AnkiError::SearchError { .. }) => (),
+            other => panic!("unexpected {:?}", other),
+        }
+        assert_eq!(card(&col, 5).did, 1);
+
+        // normal decks can't be rebuilt or emptied
+        assert!(col.rebuild_filtered_deck(1).is_err());
+        match col.empty_filtered_deck(1) {
+            Err(AnkiError::InvalidInput { .. }) => (),
+            other => panic!("unexpected {:?}", other),
+        }
+        assert_eq!((card(&col, 1).did, card(&col, 5).did), (1, 1));
+        match col.empty_filtered_deck(123) {
+            Err(AnkiError::NotFound) => (),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+}
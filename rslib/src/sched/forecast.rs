@@ -0,0 +1,207 @@
+//! Projecting the number of cards that will be due in the coming days.
+
+use crate::card::CardQueue;
+use crate::collection::Collection;
+use crate::decks::decks_in_tree_order;
+use crate::err::{AnkiError, Result};
+use crate::sched::limits::{new_limit_single, walk_limits};
+use std::collections::HashMap;
+
+/// The longest period that can be projected, matching the maximum review
+/// interval.
+const MAX_FORECAST_DAYS: u32 = 36500;
+
+/// The cards a deck will have to be studied on a single day.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ForecastDay {
+    /// Reviews due that day. Today includes overdue reviews.
+    pub review_count: u32,
+    /// Learning cards due that day. Today includes all intraday learning
+    /// cards.
+    pub learn_count: u32,
+    /// New cards that will be introduced under the deck's daily limits.
+    pub new_count: u32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DeckForecast {
+    pub deck_id: i64,
+    /// One entry per day, starting with today.
+    pub days: Vec<ForecastDay>,
+}
+
+/// The entry for a deck on a given day, adding the deck if it is missing.
+fn day_entry(
+    forecast: &mut HashMap<i64, Vec<ForecastDay>>,
+    did: i64,
+    day: u32,
+    days: u32,
+) -> &mut ForecastDay {
+    &mut forecast
+        .entry(did)
+        .or_insert_with(|| vec![ForecastDay::default(); days as usize])[day as usize]
+}
+
+impl Collection {
+    /// Project the workload of each deck over the next `days` days,
+    /// starting with today.
+    ///
+    /// Reviews and learning cards are counted on the day they are due. New
+    /// cards are introduced each day up to the daily limits of the deck and
+    /// its parents, taking into account the cards already studied today.
+    /// Cards that will be due as a result of studying are not included.
+    pub fn due_forecast(&mut self, days: u32) -> Result<Vec<DeckForecast>> {
+        if days > MAX_FORECAST_DAYS {
+            return Err(AnkiError::invalid_input(format!(
+                "forecast is limited to {} days",
+                MAX_FORECAST_DAYS
+            )));
+        }
+        let study_day = self.study_day()?;
+        let today = study_day.day;
+        let decks = self.storage.get_all_decks()?;
+        let confs = self.storage.get_all_deck_conf()?;
+
+        if days == 0 {
+            return Ok(vec![]);
+        }
+
+        let mut forecast: HashMap<i64, Vec<ForecastDay>> = HashMap::new();
+        for (did, queue, day, count) in self.storage.due_counts_by_day(today, days)? {
+            let day = day_entry(&mut forecast, did, day, days);
+            if queue == CardQueue::Review {
+                day.review_count += count;
+            } else {
+                day.learn_count += count;
+            }
+        }
+        for (did, count) in self.storage.intraday_learning_counts()? {
+            day_entry(&mut forecast, did, 0, days).learn_count += count;
+        }
+
+        let mut new_left = self.storage.new_counts()?;
+        for day in 0..days {
+            // decks that have run out of new cards are skipped, but their
+            // limits still apply to their children
+            let dids: Vec<i64> = decks_in_tree_order(&decks)
+                .into_iter()
+                .map(|deck| deck.id)
+                .filter(|did| new_left.get(did).cloned().unwrap_or(0) > 0)
+                .collect();
+            if dids.is_empty() {
+                break;
+            }
+            walk_limits(
+                &dids,
+                &decks,
+                // the counters on the deck only apply to today
+                |deck| new_limit_single(deck, &confs, study_day.days_later(day)),
+                |did, limit| {
+                    let left = new_left.get_mut(&did).unwrap();
+                    let count = limit.min(*left);
+                    *left -= count;
+                    day_entry(&mut forecast, did, day, days).new_count += count;
+                    Ok(count)
+                },
+            )?;
+        }
+
+        let order: HashMap<i64, usize> = decks_in_tree_order(&decks)
+            .into_iter()
+            .enumerate()
+            .map(|(idx, deck)| (deck.id, idx))
+            .collect();
+        let mut forecast: Vec<_> = forecast
+            .into_iter()
+            .map(|(deck_id, days)| DeckForecast { deck_id, days })
+            .collect();
+        // decks that no longer exist come last
+        forecast.sort_by_key(|f| {
+            (
+                order
+                    .get(&f.deck_id)
+                    .cloned()
+                    .unwrap_or_else(|| order.len()),
+                f.deck_id,
+            )
+        });
+        Ok(forecast)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::ForecastDay;
+    use crate::card::{Card, CardQueue, CardType};
+    use crate::collection::open_test_collection;
+    use crate::decks::Deck;
+    use crate::time::i64_unix_secs;
+
+    fn day(review_count: u32, learn_count: u32, new_count: u32) -> ForecastDay {
+        ForecastDay {
+            review_count,
+            learn_count,
+            new_count,
+        }
+    }
+
+    #[test]
+    fn test_forecast() {
+        let mut col = open_test_collection();
+        let today = col.timing_today().unwrap().days_elapsed as i32;
+
+        let mut decks = col.storage.get_all_decks().unwrap();
+        let child = Deck {
+            id: 2,
+            name: "Default::Child".into(),
+            ..decks[&1].clone()
+        };
+        decks.insert(2, child);
+        // 15 new cards were already studied today
+        decks.get_mut(&1).unwrap().new_today = (today as u32, 15);
+        col.storage.set_all_decks(&decks).unwrap();
+
+        let mut id = 0;
+        let mut add = |did, ctype, queue, due| {
+            id += 1;
+            col.storage
+                .add_card(&Card {
+                    id,
+                    nid: id,
+                    did,
+                    ctype,
+                    queue,
+                    due,
+                    ..Default::default()
+                })
+                .unwrap();
+        };
+        add(1, CardType::Review, CardQueue::Review, today - 3);
+        add(1, CardType::Review, CardQueue::Review, today);
+        add(1, CardType::Review, CardQueue::Review, today + 2);
+        add(1, CardType::Review, CardQueue::Review, today + 3);
+        add(1, CardType::Relearn, CardQueue::DayLearn, today + 1);
+        add(1, CardType::Learn, CardQueue::Learn, i64_unix_secs() as i32);
+        add(1, CardType::Review, CardQueue::Suspended, today);
+        for _ in 0..30 {
+            add(2, CardType::New, CardQueue::New, 0);
+        }
+
+        let forecast = col.due_forecast(3).unwrap();
+        assert_eq!(forecast.len(), 2);
+        assert_eq!(forecast[0].deck_id, 1);
+        assert_eq!(
+            forecast[0].days,
+            vec![day(2, 1, 0), day(0, 1, 0), day(1, 0, 0)]
+        );
+        // the parent's limit applies to the child's new cards
+        assert_eq!(forecast[1].deck_id, 2);
+        assert_eq!(
+            forecast[1].days,
+            vec![day(0, 0, 5), day(0, 0, 20), day(0, 0, 5)]
+        );
+
+        assert!(col.due_forecast(0).unwrap().is_empty());
+        assert!(col.due_forecast(40_000).is_err());
+    }
+}
@@ -0,0 +1,203 @@
+//! Interval fuzz, which spreads out cards that were introduced or answered
+//! together.
+//!
+//! The fuzz applied to a card is derived from its id and review count, so
+//! answering the same card in the same state always gives the same result.
+
+use std::collections::HashMap;
+
+/// An interval picked from a range of days.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct FuzzedInterval {
+    pub interval: u32,
+    /// The smallest interval that could have been picked.
+    pub min: u32,
+    /// The largest interval that could have been picked.
+    pub max: u32,
+}
+
+impl FuzzedInterval {
+    /// Fuzz `ivl` with a sample in [0, 1), or leave it as is if no sample
+    /// is provided.
+    pub fn new(ivl: u32, sample: Option<f32>) -> FuzzedInterval {
+        match sample {
+            Some(sample) => {
+                let (min, max) = fuzz_range(ivl);
+                FuzzedInterval {
+                    interval: fuzzed_interval(ivl, sample),
+                    min,
+                    max,
+                }
+            }
+            None => FuzzedInterval {
+                interval: ivl,
+                min: ivl,
+                max: ivl,
+            },
+        }
+    }
+
+    /// Apply `constrain` to the interval and both ends of the range.
+    pub fn map<F: Fn(u32) -> u32>(self, constrain: F) -> FuzzedInterval {
+        FuzzedInterval {
+            interval: constrain(self.interval),
+            min: constrain(self.min),
+            max: constrain(self.max),
+        }
+    }
+}
+
+/// A sample in [0, 1) for fuzzing a card, derived from its id and the
+/// number of times it has been reviewed including the current answer.
+///
+/// This does not depend on the random number generator in use, so
+/// schedules can be reproduced across versions.
+pub fn fuzz_sample(card_id: i64, reps: u32) -> f32 {
+    // splitmix64
+    let mut z = (card_id as u64)
+        .wrapping_mul(0x9e37_79b9_7f4a_7c15)
+        .wrapping_add(u64::from(reps))
+        .wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^= z >> 31;
+    // the top 24 bits fit exactly in an f32
+    (z >> 40) as f32 / (1u64 << 24) as f32
+}
+
+/// The range of days an interval may be fuzzed to, inclusive.
+pub fn fuzz_range(ivl: u32) -> (u32, u32) {
+    if ivl < 2 {
+        return (1, 1);
+    } else if ivl == 2 {
+        return (2, 3);
+    }
+    let ivl_f = f64::from(ivl);
+    let fuzz = if ivl < 7 {
+        (ivl_f * 0.25) as u32
+    } else if ivl < 30 {
+        ((ivl_f * 0.15) as u32).max(2)
+    } else {
+        ((ivl_f * 0.05) as u32).max(4)
+    };
+    // fuzz at least a day
+    let fuzz = fuzz.max(1);
+    (ivl - fuzz, ivl + fuzz)
+}
+
+/// Pick an interval from the fuzz range, using a sample in [0, 1).
+pub fn fuzzed_interval(ivl: u32, sample: f32) -> u32 {
+    let (min, max) = fuzz_range(ivl);
+    let span = max - min + 1;
+    min + ((sample * span as f32) as u32).min(span - 1)
+}
+
+/// Pick the interval in `fuzzed`'s range with the fewest reviews due, so
+/// that cards answered together don't all come due on the same day. Days
+/// falling on one of `light_days` are avoided unless the whole range does.
+/// Ties go to the interval closest to the one originally picked.
+///
+/// `due_counts` holds the number of reviews due, keyed by days from today,
+/// and `weekday` is today's weekday. Weekdays count from 0 for Monday.
+pub fn balanced_interval(
+    fuzzed: FuzzedInterval,
+    due_counts: &HashMap<u32, u32>,
+    weekday: u8,
+    light_days: &[u8],
+) -> u32 {
+    let is_light = |ivl: u32| light_days.contains(&(((u32::from(weekday) + ivl) % 7) as u8));
+    let all: Vec<u32> = (fuzzed.min..=fuzzed.max).collect();
+    let normal: Vec<u32> = all.iter().cloned().filter(|ivl| !is_light(*ivl)).collect();
+    let candidates = if normal.is_empty() { all } else { normal };
+
+    candidates
+        .into_iter()
+        .min_by_key(|ivl| {
+            let count = due_counts.get(ivl).cloned().unwrap_or(0);
+            let distance = (i64::from(*ivl) - i64::from(fuzzed.interval)).abs();
+            (count, distance)
+        })
+        .unwrap_or(fuzzed.interval)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{balanced_interval, fuzz_range, fuzz_sample, fuzzed_interval, FuzzedInterval};
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_fuzz_range() {
+        assert_eq!(fuzz_range(0), (1, 1));
+        assert_eq!(fuzz_range(1), (1, 1));
+        assert_eq!(fuzz_range(2), (2, 3));
+        assert_eq!(fuzz_range(4), (3, 5));
+        assert_eq!(fuzz_range(7), (5, 9));
+        assert_eq!(fuzz_range(20), (17, 23));
+        assert_eq!(fuzz_range(30), (26, 34));
+        assert_eq!(fuzz_range(100), (95, 105));
+
+        assert_eq!(fuzzed_interval(20, 0.0), 17);
+        assert_eq!(fuzzed_interval(20, 0.5), 20);
+        assert_eq!(fuzzed_interval(20, 0.999), 23);
+    }
+
+    #[test]
+    fn test_fuzz_sample() {
+        // the same card and review count always gives the same sample
+        assert_eq!(fuzz_sample(1234, 5), fuzz_sample(1234, 5));
+        assert_ne!(fuzz_sample(1234, 5), fuzz_sample(1234, 6));
+        assert_ne!(fuzz_sample(1234, 5), fuzz_sample(1235, 5));
+        for reps in 0..1000 {
+            let sample = fuzz_sample(1_500_000_000_000, reps);
+            assert!((0.0..1.0).contains(&sample));
+        }
+
+        let fuzzed = FuzzedInterval::new(100, Some(fuzz_sample(1234, 5)));
+        assert_eq!((fuzzed.min, fuzzed.max), (95, 105));
+        assert!(fuzzed.interval >= 95 && fuzzed.interval <= 105);
+        assert_eq!(
+            FuzzedInterval::new(100, None),
+            FuzzedInterval {
+                interval: 100,
+                min: 100,
+                max: 100
+            }
+        );
+        assert_eq!(
+            FuzzedInterval::new(100, Some(0.0)).map(|ivl| ivl.max(98)),
+            FuzzedInterval {
+                interval: 98,
+                min: 98,
+                max: 105
+            }
+        );
+    }
+
+    #[test]
+    fn test_balanced_interval() {
+        let fuzzed = FuzzedInterval {
+            interval: 10,
+            min: 8,
+            max: 12,
+        };
+        let mut counts = HashMap::new();
+        // nothing due anywhere keeps the original pick
+        assert_eq!(balanced_interval(fuzzed, &counts, 0, &[]), 10);
+
+        for (ivl, count) in &[(8, 3), (9, 1), (10, 5), (11, 1), (12, 4)] {
+            counts.insert(*ivl, *count);
+        }
+        // the quietest days, with ties going to the closest
+        assert_eq!(balanced_interval(fuzzed, &counts, 0, &[]), 9);
+        counts.insert(11, 0);
+        assert_eq!(balanced_interval(fuzzed, &counts, 0, &[]), 11);
+
+        // starting on a Monday, day 11 is a Friday
+        assert_eq!(balanced_interval(fuzzed, &counts, 0, &[4]), 9);
+        // unless every day is light
+        assert_eq!(
+            balanced_interval(fuzzed, &counts, 0, &[0, 1, 2, 3, 4, 5, 6]),
+            11
+        );
+    }
+}
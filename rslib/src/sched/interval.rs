@@ -0,0 +1,254 @@
+//! Review intervals of the v2 scheduler.
+
+use crate::card::Card;
+use crate::deckconf::RevConf;
+use crate::sched::fuzz::FuzzedInterval;
+use crate::sched::Answer;
+
+/// The number of days a review card is overdue by.
+pub fn days_late(card: &Card, today: u32) -> u32 {
+    let due = if card.odid != 0 { card.odue } else { card.due };
+    (i64::from(today) - i64::from(due)).max(0) as u32
+}
+
+/// Apply the interval modifier and fuzz, then make sure the interval is
+/// larger than `prev` and no larger than the maximum interval.
+pub fn constrained_interval(ivl: f64, conf: &RevConf, prev: u32, fuzz: Option<f32>) -> u32 {
+    constrained_fuzzed_interval(ivl, conf, prev, fuzz).interval
+}
+
+/// Like `constrained_interval`, but also returns the range the interval
+/// was picked from, after the same constraints are applied.
+pub fn constrained_fuzzed_interval(
+    ivl: f64,
+    conf: &RevConf,
+    prev: u32,
+    fuzz: Option<f32>,
+) -> FuzzedInterval {
+    let ivl = (ivl * conf.ivl_fct) as u32;
+    FuzzedInterval::new(ivl, fuzz).map(|ivl| ivl.max(prev + 1).max(1).min(conf.max_ivl))
+}
+
+/// The next interval of a review card answered on or after its due date.
+/// Overdue days count towards the new interval.
+pub fn next_review_interval(
+    card: &Card,
+    answer: Answer,
+    conf: &RevConf,
+    today: u32,
+    fuzz: Option<f32>,
+) -> u32 {
+    next_review_fuzzed_interval(card, answer, conf, today, fuzz).interval
+}
+
+/// Like `next_review_interval`, but also returns the range the interval
+/// was picked from.
+pub fn next_review_fuzzed_interval(
+    card: &Card,
+    answer: Answer,
+    conf: &RevConf,
+    today: u32,
+    fuzz: Option<f32>,
+) -> FuzzedInterval {
+    review_fuzzed_interval(
+        card.ivl,
+        card.factor,
+        days_late(card, today),
+        answer,
+        conf,
+        fuzz,
+    )
+}
+
+/// The next interval of a review card with interval `ivl` and ease `factor`
+/// in permille, answered `days_late` days after it was due.
+pub fn review_fuzzed_interval(
+    ivl: u32,
+    factor: u16,
+    days_late: u32,
+    answer: Answer,
+    conf: &RevConf,
+    fuzz: Option<f32>,
+) -> FuzzedInterval {
+    let late = f64::from(days_late);
+    let hard_min = if conf.hard_factor > 1.0 { ivl } else { 0 };
+    let ivl = f64::from(ivl);
+    let factor = f64::from(factor) / 1000.0;
+
+    let hard = constrained_fuzzed_interval(ivl * conf.hard_factor, conf, hard_min, fuzz);
+    if answer == Answer::Hard {
+        return hard;
+    }
+
+    let good = constrained_fuzzed_interval(
+        (ivl + (late / 2.0).floor()) * factor,
+        conf,
+        hard.interval,
+        fuzz,
+    );
+    if answer == Answer::Good {
+        return good;
+    }
+
+    constrained_fuzzed_interval(
+        (ivl + late) * factor * conf.ease4,
+        conf,
+        good.interval,
+        fuzz,
+    )
+}
+
+/// The next interval of a review card answered before its due date in a
+/// filtered deck. Only the days that have actually elapsed are considered.
+pub fn early_review_interval(card: &Card, answer: Answer, conf: &RevConf, today: u32) -> u32 {
+    let elapsed = i64::from(card.ivl) - (i64::from(card.odue) - i64::from(today));
+    early_interval(card.ivl, card.factor, elapsed.max(0) as u32, answer, conf)
+}
+
+/// The next interval of a review card with interval `ivl` and ease `factor`
+/// in permille, answered `elapsed` days after its previous review, before
+/// it was due.
+pub fn early_interval(ivl: u32, factor: u16, elapsed: u32, answer: Answer, conf: &RevConf) -> u32 {
+    let elapsed = f64::from(elapsed);
+    let card_factor = f64::from(factor) / 1000.0;
+
+    let (factor, min_ratio, bonus) = match answer {
+        // Hard shouldn't shrink the interval by more than half the factor
+        Answer::Hard | Answer::Again => (conf.hard_factor, conf.hard_factor / 2.0, 1.0),
+        Answer::Good => (card_factor, 1.0, 1.0),
+        // half the usual easy bonus, eg 1.3 -> 1.15
+        Answer::Easy => (card_factor, 1.0, conf.ease4 - (conf.ease4 - 1.0) / 2.0),
+    };
+
+    let new_ivl = (elapsed * factor).max(1.0);
+    let new_ivl = (f64::from(ivl) * min_ratio).max(new_ivl) * bonus;
+    constrained_interval(new_ivl, conf, 0, None)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        constrained_fuzzed_interval, constrained_interval, days_late, early_review_interval,
+        next_review_fuzzed_interval, next_review_interval,
+    };
+    use crate::card::{Card, CardQueue, CardType};
+    use crate::deckconf::RevConf;
+    use crate::sched::Answer;
+
+    fn review_card(ivl: u32, due: i32) -> Card {
+        Card {
+            ctype: CardType::Review,
+            queue: CardQueue::Review,
+            ivl,
+            due,
+            factor: 2500,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_constrained() {
+        let conf = RevConf::default();
+        assert_eq!(constrained_interval(0.0, &conf, 0, None), 1);
+        assert_eq!(constrained_interval(10.9, &conf, 0, None), 10);
+        assert_eq!(constrained_interval(10.0, &conf, 12, None), 13);
+        assert_eq!(constrained_interval(50_000.0, &conf, 0, None), 36500);
+
+        let conf = RevConf {
+            ivl_fct: 0.5,
+            ..Default::default()
+        };
+        assert_eq!(constrained_interval(10.0, &conf, 0, None), 5);
+
+        // fuzz stays within the range
+        let conf = RevConf::default();
+        assert_eq!(constrained_interval(100.0, &conf, 0, Some(0.0)), 95);
+        assert_eq!(constrained_interval(100.0, &conf, 0, Some(0.999)), 105);
+
+        // and the range is constrained too
+        let fuzzed = constrained_fuzzed_interval(100.0, &conf, 99, Some(0.0));
+        assert_eq!((fuzzed.interval, fuzzed.min, fuzzed.max), (100, 100, 105));
+    }
+
+    #[test]
+    fn test_next_interval() {
+        let conf = RevConf::default();
+        let card = review_card(10, 100);
+        assert_eq!(days_late(&card, 100), 0);
+        assert_eq!(
+            next_review_interval(&card, Answer::Hard, &conf, 100, None),
+            12
+        );
+        assert_eq!(
+            next_review_interval(&card, Answer::Good, &conf, 100, None),
+            25
+        );
+        assert_eq!(
+            next_review_interval(&card, Answer::Easy, &conf, 100, None),
+            32
+        );
+
+        // overdue days count
+        assert_eq!(days_late(&card, 104), 4);
+        assert_eq!(
+            next_review_interval(&card, Answer::Hard, &conf, 104, None),
+            12
+        );
+        assert_eq!(
+            next_review_interval(&card, Answer::Good, &conf, 104, None),
+            30
+        );
+        assert_eq!(
+            next_review_interval(&card, Answer::Easy, &conf, 104, None),
+            45
+        );
+
+        // the range is reported for the chosen button
+        let fuzzed = next_review_fuzzed_interval(&card, Answer::Good, &conf, 104, Some(0.5));
+        assert_eq!((fuzzed.interval, fuzzed.min, fuzzed.max), (30, 26, 34));
+
+        // each button is at least a day more than the previous one
+        let card = Card {
+            factor: 1300,
+            ..review_card(1, 100)
+        };
+        assert_eq!(
+            next_review_interval(&card, Answer::Hard, &conf, 100, None),
+            2
+        );
+        assert_eq!(
+            next_review_interval(&card, Answer::Good, &conf, 100, None),
+            3
+        );
+        assert_eq!(
+            next_review_interval(&card, Answer::Easy, &conf, 100, None),
+            4
+        );
+
+        // a hard factor of 1 or less may keep the interval the same
+        let conf = RevConf {
+            hard_factor: 1.0,
+            ..Default::default()
+        };
+        let card = review_card(10, 100);
+        assert_eq!(
+            next_review_interval(&card, Answer::Hard, &conf, 100, None),
+            10
+        );
+    }
+
+    #[test]
+    fn test_early_interval() {
+        let conf = RevConf::default();
+        // a 10 day interval, reviewed 4 days early
+        let card = Card {
+            odid: 5,
+            odue: 104,
+            ..review_card(10, 0)
+        };
+        assert_eq!(days_late(&card, 100), 0);
+        assert_eq!(early_review_interval(&card, Answer::Hard, &conf, 100), 7);
+        assert_eq!(early_review_interval(&card, Answer::Good, &conf, 100), 15);
+        assert_eq!(early_review_interval(&card, Answer::Easy, &conf, 100), 17);
+    }
+}
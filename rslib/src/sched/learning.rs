@@ -0,0 +1,739 @@
+//! The learning and relearning steps of the v2 scheduler.
+//!
+//! Cards in learning store the number of steps they have left in the `left`
+//! column, encoded as `today * 1000 + total`, where `total` is the number of
+//! steps remaining until graduation, and `today` is the number of those
+//! steps that can be completed before the next day rollover.
+
+use crate::card::{Card, CardQueue, CardType};
+use crate::deckconf::{LapseConf, NewConf};
+use crate::err::{AnkiError, Result};
+use crate::revlog::{RevlogEntry, RevlogReviewKind};
+use crate::sched::fuzz::FuzzedInterval;
+use crate::sched::Answer;
+
+/// The learning steps a card has left.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StepsLeft {
+    /// Steps that can be completed before the day rolls over.
+    pub today: u32,
+    /// Steps remaining until graduation.
+    pub total: u32,
+}
+
+impl StepsLeft {
+    /// Decode the `left` column.
+    pub fn from_left(left: i32) -> StepsLeft {
+        let left = left.max(0) as u32;
+        StepsLeft {
+            today: left / 1000,
+            total: left % 1000,
+        }
+    }
+
+    /// Encode for the `left` column.
+    pub fn to_left(self) -> i32 {
+        (self.today * 1000 + self.total) as i32
+    }
+}
+
+/// Where a card is in the learning process.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LearningState {
+    /// A card in the new queue that has not been answered yet.
+    New,
+    /// A new card working through the learning steps.
+    Learning { steps: StepsLeft },
+    /// A lapsed review card working through the relearning steps, with the
+    /// interval it will return to the review queue with.
+    Relearning { steps: StepsLeft, interval: u32 },
+    /// A card that has graduated from (re)learning.
+    Review { interval: u32 },
+}
+
+/// When a card is next due.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Due {
+    /// Later today, at the given timestamp, in the learn queue.
+    Intraday(i64),
+    /// On the given day, in the day learn queue.
+    Interday(u32),
+    /// On the given day, in the review queue.
+    Review(u32),
+}
+
+impl Due {
+    /// Set the card's queue and due number.
+    fn apply_to(self, card: &mut Card) {
+        let (queue, due) = match self {
+            Due::Intraday(stamp) => (CardQueue::Learn, stamp as i32),
+            Due::Interday(day) => (CardQueue::DayLearn, day as i32),
+            Due::Review(day) => (CardQueue::Review, day as i32),
+        };
+        card.queue = queue;
+        card.due = due;
+    }
+}
+
+/// Everything needed to answer a card in learning.
+#[derive(Debug, Clone)]
+pub struct LearningContext<'a> {
+    /// The current time, in milliseconds.
+    pub now_millis: i64,
+    /// Days elapsed since the collection was created.
+    pub today: u32,
+    /// Timestamp of the next day rollover.
+    pub next_day_at: i64,
+    pub usn: i32,
+    pub new_conf: &'a NewConf,
+    pub lapse_conf: &'a LapseConf,
+    /// A sample in [0, 1) used to fuzz delays and graduating intervals, or
+    /// None to apply no fuzz.
+    pub fuzz: Option<f32>,
+}
+
+/// The result of answering a card in learning.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LearningTransition {
+    pub state: LearningState,
+    pub due: Due,
+    /// Set when a new card graduates and receives its initial ease.
+    pub factor: Option<u16>,
+    /// Set when a new card graduates: its interval, and the range of days
+    /// it was fuzzed within.
+    pub fuzzed_interval: Option<FuzzedInterval>,
+    /// The delay until the next step, in seconds, or None if the card
+    /// graduated. Unlike the logged interval, this is the repeated delay
+    /// when Hard is pressed.
+    pub delay_secs: Option<u32>,
+    /// The interval to log: negative seconds for steps, or days.
+    pub revlog_interval: i32,
+    /// The step delay before answering, in negative seconds.
+    pub revlog_last_interval: i32,
+    pub revlog_kind: RevlogReviewKind,
+}
+
+impl LearningState {
+    /// The learning state of a card, or None if it is not new or learning.
+    pub fn from_card(card: &Card) -> Option<LearningState> {
+        let steps = StepsLeft::from_left(card.left);
+        match card.queue {
+            CardQueue::New => Some(LearningState::New),
+            CardQueue::Learn | CardQueue::DayLearn => match card.ctype {
+                CardType::Review | CardType::Relearn => Some(LearningState::Relearning {
+                    steps,
+                    interval: card.ivl,
+                }),
+                CardType::New | CardType::Learn => Some(LearningState::Learning { steps }),
+            },
+            _ => None,
+        }
+    }
+
+    /// Apply an answer, returning the next state and when the card is due.
+    pub fn answer(self, answer: Answer, ctx: &LearningContext) -> Result<LearningTransition> {
+        use LearningState::*;
+        let state = match self {
+            New => Learning {
+                steps: starting_steps(&ctx.new_conf.delays, ctx),
+            },
+            Review { .. } => return Err(AnkiError::invalid_input("card is not in learning")),
+            other => other,
+        };
+
+        let (delays, last_steps, kind) = match state {
+            Relearning { steps, .. } => {
+                (&ctx.lapse_conf.delays, steps, RevlogReviewKind::Relearning)
+            }
+            Learning { steps } => (&ctx.new_conf.delays, steps, RevlogReviewKind::Learning),
+            New | Review { .. } => unreachable!(),
+        };
+        let last_delay = step_delay_secs(delays, last_steps.total);
+
+        let mut factor = None;
+        let mut fuzzed_interval = None;
+        let (next, delay) = match answer {
+            Answer::Easy => (
+                state.graduated(true, ctx, &mut factor, &mut fuzzed_interval),
+                None,
+            ),
+            Answer::Good if last_steps.total <= 1 => (
+                state.graduated(false, ctx, &mut factor, &mut fuzzed_interval),
+                None,
+            ),
+            Answer::Good => {
+                let total = last_steps.total - 1;
+                let steps = StepsLeft {
+                    today: steps_today(delays, total, ctx),
+                    total,
+                };
+                (
+                    state.with_steps(steps),
+                    Some(step_delay_secs(delays, total)),
+                )
+            }
+            Answer::Hard => (state, Some(repeat_delay_secs(delays, last_steps.total))),
+            Answer::Again => {
+                let steps = starting_steps(delays, ctx);
+                let next = match state {
+                    Relearning { interval, .. } => Relearning {
+                        steps,
+                        interval: lapse_interval(interval, ctx.lapse_conf),
+                    },
+                    _ => Learning { steps },
+                };
+                (next, Some(step_delay_secs(delays, steps.total)))
+            }
+        };
+
+        let (due, revlog_interval) = match (next, delay) {
+            (Review { interval }, _) => (Due::Review(ctx.today + interval), interval as i32),
+            (Learning { steps }, Some(delay)) | (Relearning { steps, .. }, Some(delay)) => (
+                step_due(delay, ctx),
+                -(step_delay_secs(delays, steps.total) as i32),
+            ),
+            _ => unreachable!(),
+        };
+
+        Ok(LearningTransition {
+            state: next,
+            due,
+            factor,
+            fuzzed_interval,
+            delay_secs: delay,
+            revlog_interval,
+            revlog_last_interval: -(last_delay as i32),
+            revlog_kind: kind,
+        })
+    }
+
+    fn with_steps(self, steps: StepsLeft) -> LearningState {
+        match self {
+            LearningState::Relearning { interval, .. } => {
+                LearningState::Relearning { steps, interval }
+            }
+            _ => LearningState::Learning { steps },
+        }
+    }
+
+    fn graduated(
+        self,
+        early: bool,
+        ctx: &LearningContext,
+        factor: &mut Option<u16>,
+        fuzzed_interval: &mut Option<FuzzedInterval>,
+    ) -> LearningState {
+        let interval = match self {
+            LearningState::Relearning { interval, .. } => {
+                if early {
+                    interval + 1
+                } else {
+                    interval
+                }
+            }
+            _ => {
+                let fuzzed = graduating_interval(ctx.new_conf, early, ctx.fuzz);
+                *factor = Some(ctx.new_conf.initial_factor);
+                *fuzzed_interval = Some(fuzzed);
+                fuzzed.interval
+            }
+        };
+        LearningState::Review { interval }
+    }
+}
+
+/// Answer a card in the new, learning or day learning queue, updating it in
+/// place and returning the revlog entry to record. If a new card graduated,
+/// its fuzzed interval is also returned, and otherwise the delay until its
+/// next step.
+///
+/// Rep counts, daily limits and filtered deck bookkeeping are left to the
+/// caller.
+pub fn answer_learning_card(
+    card: &mut Card,
+    answer: Answer,
+    taken_millis: u32,
+    ctx: &LearningContext,
+) -> Result<(RevlogEntry, Option<FuzzedInterval>, Option<u32>)> {
+    let state = match LearningState::from_card(card) {
+        Some(state) => state,
+        None => return Err(AnkiError::invalid_input("card is not in learning")),
+    };
+    let transition = state.answer(answer, ctx)?;
+
+    if state == LearningState::New {
+        card.ctype = CardType::Learn;
+        card.left = starting_steps(&ctx.new_conf.delays, ctx).to_left();
+    }
+    match transition.state {
+        LearningState::Learning { steps } => {
+            card.left = steps.to_left();
+        }
+        LearningState::Relearning { steps, interval } => {
+            card.left = steps.to_left();
+            card.ivl = interval;
+        }
+        LearningState::Review { interval } => {
+            card.ctype = CardType::Review;
+            card.ivl = interval;
+        }
+        LearningState::New => unreachable!(),
+    }
+    if let Some(factor) = transition.factor {
+        card.factor = factor;
+    }
+    transition.due.apply_to(card);
+
+    let entry = RevlogEntry {
+        id: ctx.now_millis,
+        cid: card.id,
+        usn: ctx.usn,
+        ease: answer as u8,
+        interval: transition.revlog_interval,
+        last_interval: transition.revlog_last_interval,
+        factor: u32::from(card.factor),
+        taken_millis,
+        kind: transition.revlog_kind,
+    };
+    Ok((entry, transition.fuzzed_interval, transition.delay_secs))
+}
+
+/// Move a lapsed review card to the first relearning step. Its interval
+/// should already have been reduced. Returns the delay until the step is
+/// due, in seconds.
+pub fn start_relearning(card: &mut Card, ctx: &LearningContext) -> u32 {
+    let delays = &ctx.lapse_conf.delays;
+    let steps = starting_steps(delays, ctx);
+    let delay = step_delay_secs(delays, steps.total);
+    card.ctype = CardType::Relearn;
+    card.left = steps.to_left();
+    step_due(delay, ctx).apply_to(card);
+    delay
+}
+
+/// The steps a card starts with when entering (re)learning.
+pub fn starting_steps(delays: &[f32], ctx: &LearningContext) -> StepsLeft {
+    let total = delays.len() as u32;
+    StepsLeft {
+        today: steps_today(delays, total, ctx),
+        total,
+    }
+}
+
+/// The number of the remaining steps that can be completed before the day
+/// rolls over. Always at least one.
+fn steps_today(delays: &[f32], remaining: u32, ctx: &LearningContext) -> u32 {
+    let remaining = remaining as usize;
+    let upcoming = if remaining == 0 || remaining > delays.len() {
+        delays
+    } else {
+        &delays[delays.len() - remaining..]
+    };
+    let mut now = ctx.now_millis / 1000;
+    let mut completed = 0;
+    for (idx, delay) in upcoming.iter().enumerate() {
+        now += i64::from(minutes_to_secs(*delay));
+        if now > ctx.next_day_at {
+            break;
+        }
+        completed = idx;
+    }
+    completed as u32 + 1
+}
+
+/// The delay for the step with the given number of steps remaining.
+/// Falls back on the first step if out of range, or one minute if there are
+/// no steps.
+pub fn step_delay_secs(delays: &[f32], remaining: u32) -> u32 {
+    let remaining = remaining as usize;
+    let delay = if remaining == 0 || remaining > delays.len() {
+        delays.first()
+    } else {
+        delays.get(delays.len() - remaining)
+    };
+    minutes_to_secs(delay.cloned().unwrap_or(1.0))
+}
+
+/// The delay when Hard is pressed: halfway between the current and next
+/// step, or 50% longer than the current step if there is only one.
+pub fn repeat_delay_secs(delays: &[f32], remaining: u32) -> u32 {
+    let current = step_delay_secs(delays, remaining);
+    let next = if delays.len() > 1 {
+        step_delay_secs(delays, remaining.saturating_sub(1))
+    } else {
+        current * 2
+    };
+    (current + current.max(next)) / 2
+}
+
+/// Where a card lands after waiting `delay` seconds. If that's after the
+/// day rollover, it moves to the day learn queue.
+fn step_due(delay: u32, ctx: &LearningContext) -> Due {
+    let due = ctx.now_millis / 1000 + i64::from(delay);
+    if due < ctx.next_day_at {
+        // add some randomness, up to 5 minutes or 25%
+        let max_extra = 300.min((f64::from(delay) * 0.25) as u32);
+        let extra = ctx
+            .fuzz
+            .map(|sample| ((sample * max_extra as f32) as u32).min(max_extra.saturating_sub(1)))
+            .unwrap_or(0);
+        Due::Intraday((due + i64::from(extra)).min(ctx.next_day_at - 1))
+    } else {
+        let days_ahead = (due - ctx.next_day_at) / 86_400 + 1;
+        Due::Interday(ctx.today + days_ahead as u32)
+    }
+}
+
+/// The interval a new card graduates with.
+pub fn graduating_interval(conf: &NewConf, early: bool, fuzz: Option<f32>) -> FuzzedInterval {
+    let idx = if early { 1 } else { 0 };
+    let ideal = u32::from(conf.ints.get(idx).cloned().unwrap_or(1));
+    FuzzedInterval::new(ideal, fuzz)
+}
+
+/// The interval a review card is given when it lapses.
+pub fn lapse_interval(ivl: u32, conf: &LapseConf) -> u32 {
+    let reduced = (f64::from(ivl) * conf.mult) as u32;
+    reduced.max(conf.min_int).max(1)
+}
+
+fn minutes_to_secs(minutes: f32) -> u32 {
+    (minutes * 60.0) as u32
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        answer_learning_card, repeat_delay_secs, start_relearning, step_delay_secs, Due,
+        LearningContext, LearningState, StepsLeft,
+    };
+    use crate::card::{Card, CardQueue, CardType};
+    use crate::deckconf::{LapseConf, NewConf};
+    use crate::revlog::RevlogReviewKind;
+    use crate::sched::Answer;
+
+    const TODAY: u32 = 100;
+    const CUTOFF: i64 = 1_000_000;
+
+    fn conf(delays: &[f32]) -> NewConf {
+        NewConf {
+            delays: delays.to_vec(),
+            ..Default::default()
+        }
+    }
+
+    fn ctx<'a>(secs_to_cutoff: i64, new: &'a NewConf, lapse: &'a LapseConf) -> LearningContext<'a> {
+        LearningContext {
+            now_millis: (CUTOFF - secs_to_cutoff) * 1000,
+            today: TODAY,
+            next_day_at: CUTOFF,
+            usn: -1,
+            new_conf: new,
+            lapse_conf: lapse,
+            fuzz: None,
+        }
+    }
+
+    fn steps(today: u32, total: u32) -> StepsLeft {
+        StepsLeft { today, total }
+    }
+
+    fn learning(today: u32, total: u32) -> LearningState {
+        LearningState::Learning {
+            steps: steps(today, total),
+        }
+    }
+
+    #[test]
+    fn test_left_encoding() {
+        assert_eq!(StepsLeft::from_left(2003), steps(2, 3));
+        assert_eq!(StepsLeft::from_left(1001), steps(1, 1));
+        assert_eq!(StepsLeft::from_left(0), steps(0, 0));
+        assert_eq!(StepsLeft::from_left(-5), steps(0, 0));
+        for left in &[0, 1, 1001, 2002, 3003, 12_015] {
+            assert_eq!(StepsLeft::from_left(*left).to_left(), *left);
+        }
+    }
+
+    #[test]
+    fn test_delays() {
+        let delays = [1.0, 10.0, 60.0];
+        assert_eq!(step_delay_secs(&delays, 3), 60);
+        assert_eq!(step_delay_secs(&delays, 2), 600);
+        assert_eq!(step_delay_secs(&delays, 1), 3600);
+        // out of range falls back on first step
+        assert_eq!(step_delay_secs(&delays, 0), 60);
+        assert_eq!(step_delay_secs(&delays, 4), 60);
+        // no steps
+        assert_eq!(step_delay_secs(&[], 1), 60);
+        // fractional minutes
+        assert_eq!(step_delay_secs(&[0.5], 1), 30);
+
+        // halfway between current and next step
+        assert_eq!(repeat_delay_secs(&delays, 3), 330);
+        assert_eq!(repeat_delay_secs(&delays, 2), 2100);
+        // last step repeats itself
+        assert_eq!(repeat_delay_secs(&delays, 1), 3600);
+        // single step is 50% longer
+        assert_eq!(repeat_delay_secs(&[10.0], 1), 900);
+    }
+
+    #[test]
+    fn test_new_card_steps() {
+        let new = conf(&[1.0, 10.0]);
+        let lapse = LapseConf::default();
+        let ctx = ctx(86_400 / 2, &new, &lapse);
+        let now = CUTOFF - 86_400 / 2;
+
+        let trans = LearningState::New.answer(Answer::Good, &ctx).unwrap();
+        assert_eq!(trans.state, learning(1, 1));
+        assert_eq!(trans.due, Due::Intraday(now + 600));
+        assert_eq!(trans.revlog_interval, -600);
+        assert_eq!(trans.revlog_last_interval, -60);
+        assert_eq!(trans.revlog_kind, RevlogReviewKind::Learning);
+        assert_eq!(trans.factor, None);
+
+        let trans = LearningState::New.answer(Answer::Again, &ctx).unwrap();
+        assert_eq!(trans.state, learning(2, 2));
+        assert_eq!(trans.due, Due::Intraday(now + 60));
+
+        let trans = LearningState::New.answer(Answer::Hard, &ctx).unwrap();
+        assert_eq!(trans.state, learning(2, 2));
+        assert_eq!(trans.due, Due::Intraday(now + 330));
+        // hard logs the current step, not the repeat delay
+        assert_eq!(trans.revlog_interval, -60);
+
+        // last step graduates with the first interval
+        let trans = learning(1, 1).answer(Answer::Good, &ctx).unwrap();
+        assert_eq!(trans.state, LearningState::Review { interval: 1 });
+        assert_eq!(trans.due, Due::Review(TODAY + 1));
+        assert_eq!(trans.factor, Some(2500));
+        assert_eq!(trans.revlog_interval, 1);
+        assert_eq!(trans.revlog_last_interval, -600);
+
+        // easy graduates early with the second interval
+        let trans = LearningState::New.answer(Answer::Easy, &ctx).unwrap();
+        assert_eq!(trans.state, LearningState::Review { interval: 4 });
+        assert_eq!(trans.due, Due::Review(TODAY + 4));
+    }
+
+    #[test]
+    fn test_day_crossing_steps() {
+        let new = conf(&[1.0, 10.0, 60.0, 1440.0]);
+        let lapse = LapseConf::default();
+
+        // 30 minutes before cutoff, the first two steps fit
+        let ctx30 = ctx(30 * 60, &new, &lapse);
+        let trans = LearningState::New.answer(Answer::Again, &ctx30).unwrap();
+        assert_eq!(trans.state, learning(2, 4));
+        // plenty of time, only the final day-long step crosses over
+        let ctx_early = ctx(12 * 3600, &new, &lapse);
+        let trans = LearningState::New
+            .answer(Answer::Again, &ctx_early)
+            .unwrap();
+        assert_eq!(trans.state, learning(3, 4));
+        // right before cutoff, at least one step is always counted
+        let ctx_late = ctx(10, &new, &lapse);
+        let trans = LearningState::New.answer(Answer::Again, &ctx_late).unwrap();
+        assert_eq!(trans.state, learning(1, 4));
+        // and the step itself is pushed to the next day
+        assert_eq!(trans.due, Due::Interday(TODAY + 1));
+
+        // the today count is recalculated after each step
+        let trans = learning(3, 4).answer(Answer::Good, &ctx30).unwrap();
+        assert_eq!(trans.state, learning(1, 3));
+        assert_eq!(trans.due, Due::Intraday(CUTOFF - 30 * 60 + 600));
+
+        // a 60 minute step 30 minutes before cutoff is due tomorrow
+        let trans = learning(1, 3).answer(Answer::Good, &ctx30).unwrap();
+        assert_eq!(trans.state, learning(1, 2));
+        assert_eq!(trans.due, Due::Interday(TODAY + 1));
+
+        // a day-long step taken early in the day is due tomorrow
+        let trans = learning(2, 2).answer(Answer::Good, &ctx_early).unwrap();
+        assert_eq!(trans.state, learning(1, 1));
+        assert_eq!(trans.due, Due::Interday(TODAY + 1));
+
+        // taken right before the cutoff, it's still due tomorrow
+        let trans = learning(1, 2).answer(Answer::Good, &ctx_late).unwrap();
+        assert_eq!(trans.due, Due::Interday(TODAY + 1));
+        // a two day step is due the day after tomorrow
+        let multi = conf(&[1.0, 2880.0]);
+        let ctx_multi = ctx(10, &multi, &lapse);
+        let trans = learning(1, 2).answer(Answer::Good, &ctx_multi).unwrap();
+        assert_eq!(trans.due, Due::Interday(TODAY + 2));
+        let ctx_multi = ctx(3600, &multi, &lapse);
+        let trans = learning(1, 2).answer(Answer::Good, &ctx_multi).unwrap();
+        assert_eq!(trans.due, Due::Interday(TODAY + 2));
+
+        // a step due exactly at the cutoff belongs to the next day
+        let exact = conf(&[1.0, 30.0]);
+        let ctx_exact = ctx(30 * 60, &exact, &lapse);
+        let trans = learning(2, 2).answer(Answer::Good, &ctx_exact).unwrap();
+        assert_eq!(trans.due, Due::Interday(TODAY + 1));
+        // but still counts as doable today
+        assert_eq!(trans.state, learning(1, 1));
+    }
+
+    #[test]
+    fn test_fuzz() {
+        let new = conf(&[1.0, 10.0]);
+        let lapse = LapseConf::default();
+        let mut ctx = ctx(12 * 3600, &new, &lapse);
+        let now = ctx.now_millis / 1000;
+        ctx.fuzz = Some(0.5);
+
+        // up to 25% extra
+        let trans = learning(2, 2).answer(Answer::Good, &ctx).unwrap();
+        assert_eq!(trans.due, Due::Intraday(now + 600 + 75));
+        // capped at 5 minutes
+        ctx.fuzz = Some(0.99);
+        let trans = learning(2, 2).answer(Answer::Hard, &ctx).unwrap();
+        assert_eq!(trans.due, Due::Intraday(now + 330 + 81));
+        let long = conf(&[1.0, 120.0]);
+        ctx.new_conf = &long;
+        let trans = learning(2, 2).answer(Answer::Good, &ctx).unwrap();
+        assert_eq!(trans.due, Due::Intraday(now + 7200 + 297));
+        // never past the cutoff
+        ctx.now_millis = (CUTOFF - 7200 - 10) * 1000;
+        let trans = learning(2, 2).answer(Answer::Good, &ctx).unwrap();
+        assert_eq!(trans.due, Due::Intraday(CUTOFF - 1));
+
+        // graduating intervals are fuzzed
+        ctx.fuzz = Some(0.0);
+        let trans = learning(1, 1).answer(Answer::Easy, &ctx).unwrap();
+        assert_eq!(trans.state, LearningState::Review { interval: 3 });
+        let fuzzed = trans.fuzzed_interval.unwrap();
+        assert_eq!((fuzzed.min, fuzzed.max), (3, 5));
+    }
+
+    #[test]
+    fn test_relearning() {
+        let new = NewConf::default();
+        let lapse = LapseConf {
+            delays: vec![10.0, 60.0],
+            mult: 0.5,
+            min_int: 2,
+            ..Default::default()
+        };
+        let ctx = ctx(12 * 3600, &new, &lapse);
+        let relearning = |today, total, interval| LearningState::Relearning {
+            steps: steps(today, total),
+            interval,
+        };
+
+        let trans = relearning(2, 2, 10).answer(Answer::Good, &ctx).unwrap();
+        assert_eq!(trans.state, relearning(1, 1, 10));
+        assert_eq!(trans.revlog_kind, RevlogReviewKind::Relearning);
+        assert_eq!(trans.revlog_interval, -3600);
+        assert_eq!(trans.revlog_last_interval, -600);
+
+        // failing again reduces the interval again
+        let trans = relearning(1, 1, 10).answer(Answer::Again, &ctx).unwrap();
+        assert_eq!(trans.state, relearning(2, 2, 5));
+        let trans = relearning(1, 1, 3).answer(Answer::Again, &ctx).unwrap();
+        assert_eq!(trans.state, relearning(2, 2, 2));
+
+        // graduating keeps the interval and ease
+        let trans = relearning(1, 1, 10).answer(Answer::Good, &ctx).unwrap();
+        assert_eq!(trans.state, LearningState::Review { interval: 10 });
+        assert_eq!(trans.due, Due::Review(TODAY + 10));
+        assert_eq!(trans.factor, None);
+        let trans = relearning(2, 2, 10).answer(Answer::Easy, &ctx).unwrap();
+        assert_eq!(trans.state, LearningState::Review { interval: 11 });
+
+        assert!(LearningState::Review { interval: 1 }
+            .answer(Answer::Good, &ctx)
+            .is_err());
+    }
+
+    #[test]
+    fn test_no_steps() {
+        let new = conf(&[]);
+        let lapse = LapseConf::default();
+        let ctx = ctx(12 * 3600, &new, &lapse);
+        let now = ctx.now_millis / 1000;
+
+        let trans = LearningState::New.answer(Answer::Good, &ctx).unwrap();
+        assert_eq!(trans.state, LearningState::Review { interval: 1 });
+        // a dummy one minute step is used
+        let trans = LearningState::New.answer(Answer::Again, &ctx).unwrap();
+        assert_eq!(trans.state, learning(1, 0));
+        assert_eq!(trans.due, Due::Intraday(now + 60));
+    }
+
+    #[test]
+    fn test_answer_card() {
+        let new = conf(&[1.0, 10.0]);
+        let lapse = LapseConf::default();
+        let ctx = ctx(12 * 3600, &new, &lapse);
+        let now = ctx.now_millis / 1000;
+        let mut card = Card {
+            id: 5,
+            ..Default::default()
+        };
+
+        let (entry, fuzzed, _) = answer_learning_card(&mut card, Answer::Good, 3000, &ctx).unwrap();
+        assert_eq!(card.ctype, CardType::Learn);
+        assert_eq!(card.queue, CardQueue::Learn);
+        assert_eq!(card.left, 1001);
+        assert_eq!(card.due, (now + 600) as i32);
+        assert_eq!(entry.cid, 5);
+        assert_eq!(entry.ease, 3);
+        assert_eq!(entry.interval, -600);
+        assert_eq!(entry.last_interval, -60);
+        assert_eq!(entry.factor, 0);
+        assert_eq!(entry.taken_millis, 3000);
+        assert_eq!(fuzzed, None);
+
+        let (entry, fuzzed, _) = answer_learning_card(&mut card, Answer::Good, 3000, &ctx).unwrap();
+        assert_eq!(card.ctype, CardType::Review);
+        assert_eq!(card.queue, CardQueue::Review);
+        assert_eq!(card.due, (TODAY + 1) as i32);
+        assert_eq!(card.ivl, 1);
+        assert_eq!(card.factor, 2500);
+        // left is not touched on graduation
+        assert_eq!(card.left, 1001);
+        assert_eq!(entry.interval, 1);
+        assert_eq!(entry.factor, 2500);
+        assert_eq!(fuzzed.map(|f| f.interval), Some(1));
+
+        assert!(answer_learning_card(&mut card, Answer::Good, 3000, &ctx).is_err());
+
+        // relearning cards stay in the relearning type
+        card.ctype = CardType::Relearn;
+        card.queue = CardQueue::DayLearn;
+        card.left = 1001;
+        card.ivl = 20;
+        let (entry, _, _) = answer_learning_card(&mut card, Answer::Again, 3000, &ctx).unwrap();
+        assert_eq!(card.ctype, CardType::Relearn);
+        assert_eq!(card.queue, CardQueue::Learn);
+        assert_eq!(card.ivl, 1);
+        assert_eq!(card.left, 1001);
+        assert_eq!(entry.kind, RevlogReviewKind::Relearning);
+    }
+
+    #[test]
+    fn test_start_relearning() {
+        let new = NewConf::default();
+        let lapse = LapseConf {
+            mult: 0.5,
+            ..Default::default()
+        };
+        let ctx = ctx(12 * 3600, &new, &lapse);
+        let now = ctx.now_millis / 1000;
+        let mut card = Card {
+            ctype: CardType::Review,
+            queue: CardQueue::Review,
+            ivl: 30,
+            ..Default::default()
+        };
+
+        assert_eq!(start_relearning(&mut card, &ctx), 600);
+        assert_eq!(card.ctype, CardType::Relearn);
+        assert_eq!(card.queue, CardQueue::Learn);
+        assert_eq!(card.due, (now + 600) as i32);
+        assert_eq!(card.ivl, 30);
+        assert_eq!(card.left, 1001);
+    }
+}
@@ -0,0 +1,286 @@
+use crate::card::{Card, CardQueue, CardType};
+use crate::collection::Collection;
+use crate::deckconf::CardConf;
+use crate::err::Result;
+use crate::sched::learning::LearningContext;
+
+/// What happens to a card when it becomes a leech, in addition to its note
+/// being tagged.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LeechAction {
+    Suspend = 0,
+    TagOnly = 1,
+    /// Move the card to the deck named in the options.
+    MoveToDeck = 2,
+    /// Set the card's ease back to the starting ease.
+    ResetEase = 3,
+}
+
+impl LeechAction {
+    /// Convert the action stored in a deck config. Unknown actions only
+    /// tag, as older clients do.
+    pub fn from_u8(action: u8) -> LeechAction {
+        match action {
+            0 => LeechAction::Suspend,
+            2 => LeechAction::MoveToDeck,
+            3 => LeechAction::ResetEase,
+            _ => LeechAction::TagOnly,
+        }
+    }
+}
+
+/// True if a card with the given number of lapses should be handled as a
+/// leech: when it first reaches the threshold, and every half threshold
+/// after that. A threshold of 0 disables leech handling.
+pub fn is_leech(lapses: u32, leech_fails: u32) -> bool {
+    if leech_fails == 0 {
+        return false;
+    }
+    lapses >= leech_fails && (lapses - leech_fails) % (leech_fails / 2).max(1) == 0
+}
+
+/// The number of further lapses before a card with `lapses` lapses is next
+/// handled as a leech, or None if leech handling is disabled.
+pub fn lapses_until_leech(lapses: u32, leech_fails: u32) -> Option<u32> {
+    if leech_fails == 0 {
+        return None;
+    }
+    Some(if lapses < leech_fails {
+        leech_fails - lapses
+    } else {
+        let step = (leech_fails / 2).max(1);
+        step - (lapses - leech_fails) % step
+    })
+}
+
+/// A card that will become a leech after a few more lapses.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LeechCandidate {
+    pub card_id: i64,
+    pub note_id: i64,
+    pub lapses: u32,
+    pub lapses_until_leech: u32,
+    /// When the card's recorded lapses happened, in milliseconds, oldest
+    /// first.
+    pub lapse_times: Vec<i64>,
+}
+
+impl Collection {
+    /// Tag the card's note if the card is a leech, and apply the leech
+    /// action from the options. Returns true if the card was a leech.
+    pub(crate) fn check_leech(&mut self, card: &mut Card, ctx: &LearningContext) -> Result<bool> {
+        let conf = ctx.lapse_conf;
+        if !is_leech(card.lapses, conf.leech_fails) {
+            return Ok(false);
+        }
+        self.add_tag_to_note(card.nid, "leech", ctx.now_millis / 1000, ctx.usn)?;
+        match LeechAction::from_u8(conf.leech_action) {
+            LeechAction::Suspend => card.queue = CardQueue::Suspended,
+            LeechAction::TagOnly => (),
+            LeechAction::MoveToDeck => {
+                // if no normal deck has the name, the card is only tagged
+                let name = conf.leech_deck.to_lowercase();
+                if let Some(deck) = self
+                    .storage
+                    .get_all_decks()?
+                    .values()
+                    .find(|d| !d.is_filtered() && d.name.to_lowercase() == name)
+                {
+                    if card.odid != 0 {
+                        card.odid = deck.id;
+                    } else {
+                        card.did = deck.id;
+                    }
+                }
+            }
+            LeechAction::ResetEase => card.factor = ctx.new_conf.initial_factor,
+        }
+        Ok(true)
+    }
+
+    /// Review cards matching `search` that will become leeches within
+    /// `margin` more lapses, using the threshold in each card's options.
+    /// Suspended cards are left out. Cards with the fewest lapses to go
+    /// come first.
+    pub fn leech_candidates(&self, search: &str, margin: u32) -> Result<Vec<LeechCandidate>> {
+        let decks = self.storage.get_all_decks()?;
+        let confs = self.storage.get_all_deck_conf()?;
+        let mut candidates = vec![];
+        for cid in self.search_cards(search, Some("c.id"))? {
+            let card = match self.storage.get_card(cid)? {
+                Some(card) => card,
+                None => continue,
+            };
+            if card.ctype == CardType::New
+                || card.ctype == CardType::Learn
+                || card.queue == CardQueue::Suspended
+            {
+                continue;
+            }
+            let conf = CardConf::for_card(card.did, card.odid, &decks, &confs).conf;
+            match lapses_until_leech(card.lapses, conf.lapse.leech_fails) {
+                Some(until) if until <= margin => candidates.push(LeechCandidate {
+                    card_id: card.id,
+                    note_id: card.nid,
+                    lapses: card.lapses,
+                    lapses_until_leech: until,
+                    lapse_times: vec![],
+                }),
+                _ => (),
+            }
+        }
+
+        let cids: Vec<i64> = candidates.iter().map(|c| c.card_id).collect();
+        let mut times = self.storage.lapse_times(&cids)?;
+        for candidate in &mut candidates {
+            candidate.lapse_times = times.remove(&candidate.card_id).unwrap_or_default();
+        }
+        candidates.sort_by_key(|c| (c.lapses_until_leech, c.card_id));
+        Ok(candidates)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{is_leech, lapses_until_leech};
+    use crate::card::{Card, CardQueue, CardType};
+    use crate::collection::open_test_collection;
+    use crate::decks::Deck;
+    use crate::revlog::{RevlogEntry, RevlogReviewKind};
+    use crate::sched::Answer;
+
+    #[test]
+    fn test_is_leech() {
+        assert!(!is_leech(7, 8));
+        assert!(is_leech(8, 8));
+        assert!(!is_leech(9, 8));
+        assert!(is_leech(12, 8));
+        assert!(is_leech(16, 8));
+
+        assert!(is_leech(1, 1));
+        assert!(is_leech(2, 1));
+        assert!(!is_leech(5, 0));
+
+        assert_eq!(lapses_until_leech(5, 8), Some(3));
+        assert_eq!(lapses_until_leech(8, 8), Some(4));
+        assert_eq!(lapses_until_leech(11, 8), Some(1));
+        assert_eq!(lapses_until_leech(3, 1), Some(1));
+        assert_eq!(lapses_until_leech(3, 0), None);
+        for lapses in 0..30 {
+            let until = lapses_until_leech(lapses, 8).unwrap();
+            assert!(is_leech(lapses + until, 8));
+            assert!((1..until).all(|n| !is_leech(lapses + n, 8)));
+        }
+    }
+
+    #[test]
+    fn test_leech_actions() {
+        let mut col = open_test_collection();
+        let today = col.timing_today().unwrap().days_elapsed as i32;
+        let mut decks = col.storage.get_all_decks().unwrap();
+        decks.insert(
+            2,
+            Deck {
+                id: 2,
+                name: "Leeches".into(),
+                ..decks[&1].clone()
+            },
+        );
+        col.storage.set_all_decks(&decks).unwrap();
+        let mut confs = col.storage.get_all_deck_conf().unwrap();
+        let conf = confs.get_mut(&1).unwrap();
+        conf.lapse.leech_action = 2;
+        conf.lapse.leech_deck = "leeches".into();
+        conf.new.initial_factor = 2500;
+        col.storage.set_all_deck_conf(&confs).unwrap();
+
+        for id in 1..=2 {
+            col.storage.add_test_note(id, "").unwrap();
+            col.storage
+                .add_card(&Card {
+                    id,
+                    nid: id,
+                    did: 1,
+                    ctype: CardType::Review,
+                    queue: CardQueue::Review,
+                    due: today,
+                    ivl: 10,
+                    factor: 1500,
+                    lapses: 7,
+                    ..Default::default()
+                })
+                .unwrap();
+        }
+
+        assert!(col.answer_card(1, Answer::Again, 1000).unwrap().leech);
+        let card = col.storage.get_card(1).unwrap().unwrap();
+        assert_eq!(card.did, 2);
+        assert_eq!(card.ctype, CardType::Relearn);
+        assert_eq!(col.storage.get_note_tags(1).unwrap(), " leech ");
+
+        let mut confs = col.storage.get_all_deck_conf().unwrap();
+        confs.get_mut(&1).unwrap().lapse.leech_action = 3;
+        col.storage.set_all_deck_conf(&confs).unwrap();
+        assert!(col.answer_card(2, Answer::Again, 1000).unwrap().leech);
+        let card = col.storage.get_card(2).unwrap().unwrap();
+        assert_eq!(card.did, 1);
+        assert_eq!(card.factor, 2500);
+    }
+
+    #[test]
+    fn test_leech_candidates() {
+        let col = open_test_collection();
+        let cards = vec![
+            (1, CardType::Review, CardQueue::Review, 6),
+            (2, CardType::Review, CardQueue::Review, 2),
+            (3, CardType::Relearn, CardQueue::Learn, 7),
+            (4, CardType::Review, CardQueue::Suspended, 7),
+            (5, CardType::Review, CardQueue::Review, 10),
+        ];
+        for (id, ctype, queue, lapses) in cards {
+            col.storage.add_test_note(id, "").unwrap();
+            col.storage
+                .add_card(&Card {
+                    id,
+                    nid: id,
+                    did: 1,
+                    ctype,
+                    queue,
+                    lapses,
+                    ..Default::default()
+                })
+                .unwrap();
+        }
+        for (id, ease, kind) in &[
+            (100, 1, RevlogReviewKind::Review),
+            (200, 1, RevlogReviewKind::Relearning),
+            (300, 3, RevlogReviewKind::Review),
+            (400, 1, RevlogReviewKind::EarlyReview),
+        ] {
+            col.storage
+                .add_revlog_entry(&RevlogEntry {
+                    id: *id,
+                    cid: 3,
+                    usn: 0,
+                    ease: *ease,
+                    interval: 1,
+                    last_interval: 1,
+                    factor: 2500,
+                    taken_millis: 1000,
+                    kind: *kind,
+                })
+                .unwrap();
+        }
+
+        let candidates = col.leech_candidates("", 2).unwrap();
+        let summary: Vec<_> = candidates
+            .iter()
+            .map(|c| (c.card_id, c.lapses_until_leech))
+            .collect();
+        assert_eq!(summary, vec![(3, 1), (1, 2), (5, 2)]);
+        assert_eq!(candidates[0].lapse_times, vec![100, 400]);
+        assert!(candidates[1].lapse_times.is_empty());
+
+        assert_eq!(col.leech_candidates("cid:1,2", 10).unwrap().len(), 2);
+    }
+}
@@ -0,0 +1,316 @@
+//! Daily new and review limits of decks and their parents.
+
+use crate::deckconf::{conf_for_deck, DeckConf, NewConf, RevConf};
+use crate::decks::{deck_and_parent_ids, Deck};
+use crate::err::Result;
+use chrono::{Duration, NaiveDate};
+use std::collections::HashMap;
+
+/// The limit used for filtered decks, which have no daily limits.
+pub(crate) const FILTERED_DECK_LIMIT: u32 = 99_999;
+
+/// Counts shown in the deck list are capped at this number.
+pub(crate) const REPORT_LIMIT: u32 = 1000;
+
+/// A scheduler day, with the local weekday and date it started on. Daily
+/// limits can depend on all three.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct StudyDay {
+    /// The number of days since the collection was created.
+    pub day: u32,
+    /// From 0 for Monday to 6 for Sunday.
+    pub weekday: u8,
+    pub date: NaiveDate,
+}
+
+impl StudyDay {
+    /// The scheduler day `days` after this one.
+    pub(crate) fn days_later(self, days: u32) -> StudyDay {
+        StudyDay {
+            day: self.day + days,
+            weekday: ((u32::from(self.weekday) + days) % 7) as u8,
+            date: self.date + Duration::days(i64::from(days)),
+        }
+    }
+}
+
+/// The number of cards studied today according to a deck counter. The
+/// counter is ignored if it was last updated on a previous day.
+fn studied_today(studied: (u32, i32), today: u32) -> u32 {
+    if studied.0 == today {
+        studied.1.max(0) as u32
+    } else {
+        0
+    }
+}
+
+/// Subtract the number of cards studied today from a daily limit.
+fn remaining(per_day: u32, studied: (u32, i32), today: u32) -> u32 {
+    per_day.saturating_sub(studied_today(studied, today))
+}
+
+/// The number of reviews due before `today` in each deck and its children,
+/// from the counts of each deck on its own.
+pub(crate) fn overdue_in_decks(
+    decks: &HashMap<i64, Deck>,
+    counts: &HashMap<i64, u32>,
+) -> HashMap<i64, u32> {
+    let mut overdue = HashMap::new();
+    for (&did, &count) in counts {
+        for id in deck_and_parent_ids(decks, did) {
+            *overdue.entry(id).or_insert(0) += count;
+        }
+    }
+    overdue
+}
+
+/// The number of new cards a deck may still show today, ignoring parents.
+pub(crate) fn new_limit_single(
+    deck: &Deck,
+    confs: &HashMap<i64, DeckConf>,
+    today: StudyDay,
+) -> u32 {
+    if deck.is_filtered() {
+        return FILTERED_DECK_LIMIT;
+    }
+    let per_day = conf_for_deck(deck, confs)
+        .map(|conf| conf.new_per_day(today))
+        .unwrap_or_else(|| NewConf::default().per_day);
+    remaining(per_day, deck.new_today, today.day)
+}
+
+/// The number of reviews a deck may still show today, ignoring parents.
+/// `overdue` holds the reviews due before today in each deck and its
+/// children, as returned by `overdue_in_decks`.
+pub(crate) fn review_limit_single(
+    deck: &Deck,
+    confs: &HashMap<i64, DeckConf>,
+    today: StudyDay,
+    overdue: &HashMap<i64, u32>,
+) -> u32 {
+    if deck.is_filtered() {
+        return FILTERED_DECK_LIMIT;
+    }
+    // reviews are shown most overdue first, so the ones already answered
+    // today were part of the backlog the day started with
+    let backlog =
+        overdue.get(&deck.id).cloned().unwrap_or(0) + studied_today(deck.rev_today, today.day);
+    let per_day = conf_for_deck(deck, confs)
+        .map(|conf| conf.review_per_day(today, backlog))
+        .unwrap_or_else(|| RevConf::default().per_day);
+    remaining(per_day, deck.rev_today, today.day)
+}
+
+/// The number of reviews a deck may still show today, capped by the limits
+/// of its parents.
+pub(crate) fn review_limit(
+    did: i64,
+    decks: &HashMap<i64, Deck>,
+    confs: &HashMap<i64, DeckConf>,
+    today: StudyDay,
+    overdue: &HashMap<i64, u32>,
+) -> u32 {
+    match decks.get(&did) {
+        None => 0,
+        Some(deck) if deck.is_filtered() => FILTERED_DECK_LIMIT,
+        Some(_) => deck_and_parent_ids(decks, did)
+            .into_iter()
+            .filter_map(|id| decks.get(&id))
+            .map(|deck| review_limit_single(deck, confs, today, overdue))
+            .min()
+            .unwrap_or(0),
+    }
+}
+
+/// Walk through `dids` in order, calling `fetch` with each deck and the
+/// number of cards it may contribute. The limit of a deck is shared with
+/// its parents, so the cards `fetch` reports as taken reduce what later
+/// decks under the same parent can take.
+pub(crate) fn walk_limits<L, F>(
+    dids: &[i64],
+    decks: &HashMap<i64, Deck>,
+    limit_fn: L,
+    mut fetch: F,
+) -> Result<()>
+where
+    L: Fn(&Deck) -> u32,
+    F: FnMut(i64, u32) -> Result<u32>,
+{
+    let mut remaining: HashMap<i64, u32> = HashMap::new();
+    for &did in dids {
+        let deck = match decks.get(&did) {
+            Some(deck) => deck,
+            None => continue,
+        };
+        let mut limit = limit_fn(deck);
+        if limit == 0 {
+            continue;
+        }
+
+        let parents: Vec<_> = deck_and_parent_ids(decks, did)
+            .into_iter()
+            .skip(1)
+            .collect();
+        for pid in &parents {
+            let parent_limit = *remaining
+                .entry(*pid)
+                .or_insert_with(|| limit_fn(&decks[pid]));
+            limit = limit.min(parent_limit);
+        }
+        if limit == 0 {
+            remaining.insert(did, 0);
+            continue;
+        }
+
+        let taken = fetch(did, limit)?;
+        for pid in &parents {
+            if let Some(parent_limit) = remaining.get_mut(pid) {
+                *parent_limit = parent_limit.saturating_sub(taken);
+            }
+        }
+        // we may also be a parent
+        remaining.insert(did, limit - taken);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        new_limit_single, overdue_in_decks, review_limit, review_limit_single, walk_limits,
+        StudyDay, FILTERED_DECK_LIMIT,
+    };
+    use crate::deckconf::{DeckConf, Vacation};
+    use crate::decks::Deck;
+    use chrono::NaiveDate;
+    use std::collections::HashMap;
+
+    /// Day `day` of a collection created on Monday 2020-01-06.
+    fn day(day: u32) -> StudyDay {
+        StudyDay {
+            day: 0,
+            weekday: 0,
+            date: NaiveDate::from_ymd(2020, 1, 6),
+        }
+        .days_later(day)
+    }
+
+    fn deck(id: i64, name: &str, new_today: (u32, i32)) -> Deck {
+        let json = format!(
+            r#"{{"id": {}, "name": "{}", "mod": 0, "usn": 0, "dyn": 0, "conf": 1,
+            "newToday": [{}, {}], "revToday": [{}, {}], "lrnToday": [0, 0],
+            "timeToday": [0, 0]}}"#,
+            id, name, new_today.0, new_today.1, new_today.0, new_today.1
+        );
+        serde_json::from_str(&json).unwrap()
+    }
+
+    fn confs() -> HashMap<i64, DeckConf> {
+        let mut confs = HashMap::new();
+        confs.insert(1, DeckConf::default());
+        confs
+    }
+
+    #[test]
+    fn test_limits() {
+        let confs = confs();
+        let decks: HashMap<_, _> = vec![
+            deck(1, "Default", (10, 5)),
+            deck(2, "Default::Child", (10, 195)),
+            deck(3, "Other", (9, 5)),
+        ]
+        .into_iter()
+        .map(|d| (d.id, d))
+        .collect();
+
+        assert_eq!(new_limit_single(&decks[&1], &confs, day(10)), 15);
+        // counters from a previous day are ignored
+        assert_eq!(new_limit_single(&decks[&3], &confs, day(10)), 20);
+        // studied more than the limit
+        assert_eq!(new_limit_single(&decks[&2], &confs, day(10)), 0);
+
+        let counts: HashMap<_, _> = vec![(2, 3), (3, 4)].into_iter().collect();
+        let mut rolled_up: Vec<_> = overdue_in_decks(&decks, &counts).into_iter().collect();
+        rolled_up.sort();
+        assert_eq!(rolled_up, vec![(1, 3), (2, 3), (3, 4)]);
+
+        let overdue = HashMap::new();
+        assert_eq!(review_limit(1, &decks, &confs, day(10), &overdue), 195);
+        assert_eq!(review_limit(2, &decks, &confs, day(10), &overdue), 5);
+        assert_eq!(review_limit(5, &decks, &confs, day(10), &overdue), 0);
+
+        let mut filtered = deck(4, "Filtered", (10, 0));
+        filtered.dynamic = 1;
+        assert_eq!(
+            new_limit_single(&filtered, &confs, day(10)),
+            FILTERED_DECK_LIMIT
+        );
+    }
+
+    #[test]
+    fn test_walk_limits() {
+        let confs = confs();
+        let decks: HashMap<_, _> = vec![
+            deck(1, "Parent", (10, 10)),
+            deck(2, "Parent::A", (10, 0)),
+            deck(3, "Parent::B", (10, 0)),
+        ]
+        .into_iter()
+        .map(|d| (d.id, d))
+        .collect();
+
+        // each deck has 8 cards available; the parent has room for 10
+        let mut taken = vec![];
+        walk_limits(
+            &[1, 2, 3],
+            &decks,
+            |deck| new_limit_single(deck, &confs, day(10)),
+            |did, limit| {
+                let count = limit.min(8);
+                taken.push((did, count));
+                Ok(count)
+            },
+        )
+        .unwrap();
+        assert_eq!(taken, vec![(1, 8), (2, 2)]);
+    }
+
+    #[test]
+    fn test_weekday_and_vacation_limits() {
+        let mut confs = confs();
+        let conf = confs.get_mut(&1).unwrap();
+        // fewer new cards on Saturday, none and more reviews on Sunday
+        conf.new.per_weekday = vec![None, None, None, None, None, Some(5), Some(0)];
+        conf.rev.per_weekday = vec![None, None, None, None, None, None, Some(300)];
+        conf.vacations = vec![Vacation {
+            start: "2020-01-20".into(),
+            end: "2020-01-22".into(),
+        }];
+        let mut deck = deck(1, "Default", (0, 0));
+
+        let limits = |deck: &Deck, day, overdue| {
+            let overdue: HashMap<_, _> = vec![(1, overdue)].into_iter().collect();
+            (
+                new_limit_single(deck, &confs, day),
+                review_limit_single(deck, &confs, day, &overdue),
+            )
+        };
+        // day 10 is a Thursday
+        assert_eq!(day(10).weekday, 3);
+        assert_eq!(limits(&deck, day(10), 50), (20, 200));
+        assert_eq!(limits(&deck, day(12), 50), (5, 200));
+        assert_eq!(limits(&deck, day(13), 50), (0, 300));
+        // nothing is studied during the vacation
+        assert_eq!(limits(&deck, day(14), 50), (0, 0));
+        assert_eq!(limits(&deck, day(16), 50), (0, 0));
+        // and the backlog is spread over as many days afterwards
+        assert_eq!(limits(&deck, day(17), 0), (20, 200));
+        assert_eq!(limits(&deck, day(17), 600), (20, 400));
+        assert_eq!(limits(&deck, day(18), 301), (20, 351));
+        assert_eq!(limits(&deck, day(19), 100), (5, 300));
+        assert_eq!(limits(&deck, day(20), 100), (0, 300));
+        // reviews answered today were part of the backlog
+        deck.rev_today = (17, 150);
+        assert_eq!(limits(&deck, day(17), 450), (20, 250));
+    }
+}
@@ -0,0 +1,48 @@
+pub mod algorithm;
+pub mod answering;
+pub mod bulk;
+pub mod congrats;
+pub mod cutoff;
+pub mod deck_tree;
+pub mod due_date;
+pub mod filtered;
+pub mod forecast;
+pub mod fuzz;
+pub mod interval;
+pub mod learning;
+pub mod leech;
+pub(crate) mod limits;
+pub mod optimizer;
+pub mod queue;
+pub mod reposition;
+pub mod retrievability;
+pub mod session;
+pub mod siblings;
+pub mod simulator;
+pub mod timespan;
+pub mod version;
+
+pub use cutoff::{sched_timing_today, SchedTimingToday};
+
+/// The button pressed when answering a card.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Answer {
+    Again = 1,
+    Hard = 2,
+    Good = 3,
+    Easy = 4,
+}
+
+impl Answer {
+    /// Convert an ease number as stored in the revlog.
+    pub fn from_ease(ease: u8) -> Option<Answer> {
+        Some(match ease {
+            1 => Answer::Again,
+            2 => Answer::Hard,
+            3 => Answer::Good,
+            4 => Answer::Easy,
+            _ => return None,
+        })
+    }
+}
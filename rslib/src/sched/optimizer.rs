@@ -0,0 +1,344 @@
+//! Fitting the scheduler to a user's memory, using their review history.
+
+use crate::collection::Collection;
+use crate::deckconf::CardConf;
+use crate::decks::deck_and_child_ids;
+use crate::err::{AnkiError, Result};
+use crate::revlog::RevlogReviewKind;
+
+/// Reviews of cards with an interval shorter than this count as young.
+pub(super) const MATURE_INTERVAL: i32 = 21;
+/// Reviews are only used to recommend a change or fit a curve once there
+/// are at least this many of them.
+pub(super) const MIN_REVIEWS: usize = 100;
+/// Well within the 0-999% the deck options screen accepts.
+const INTERVAL_MODIFIER_LIMITS: (f64, f64) = (0.5, 2.0);
+/// In permille, within the 130-999% the deck options screen accepts.
+const STARTING_EASE_LIMITS: (f64, f64) = (1300.0, 5000.0);
+
+/// A review, reduced to what a forgetting curve is fit with.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ReviewSample {
+    /// The time since the previous review, divided by the interval the card
+    /// had.
+    pub elapsed_ratio: f64,
+    pub remembered: bool,
+}
+
+/// A forgetting curve, where the chance of remembering a card after waiting
+/// `ratio` times its interval is `exp(-decay * ratio)`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ForgettingCurve {
+    pub decay: f64,
+}
+
+impl ForgettingCurve {
+    /// The curve on which cards are remembered with probability `retention`
+    /// on their due dates.
+    pub fn from_retention_at_due(retention: f64) -> ForgettingCurve {
+        ForgettingCurve {
+            decay: -retention.ln(),
+        }
+    }
+
+    /// Fit a curve to `samples` by maximum likelihood. Only a few passes
+    /// over the samples are needed, so large histories can be fit quickly.
+    pub fn fit(samples: &[ReviewSample]) -> ForgettingCurve {
+        // the slope of the log likelihood, which falls as decay rises
+        let slope = |decay: f64| -> f64 {
+            samples
+                .iter()
+                .map(|sample| {
+                    let x = sample.elapsed_ratio;
+                    if sample.remembered {
+                        -x
+                    } else {
+                        let recall = (-decay * x).exp();
+                        x * recall / (1.0 - recall)
+                    }
+                })
+                .sum()
+        };
+
+        let (mut low, mut high) = (1e-4f64.ln(), 20f64.ln());
+        if slope(low.exp()) <= 0.0 {
+            return ForgettingCurve { decay: low.exp() };
+        }
+        if slope(high.exp()) >= 0.0 {
+            return ForgettingCurve { decay: high.exp() };
+        }
+        for _ in 0..50 {
+            let mid = (low + high) / 2.0;
+            if slope(mid.exp()) > 0.0 {
+                low = mid;
+            } else {
+                high = mid;
+            }
+        }
+        ForgettingCurve {
+            decay: ((low + high) / 2.0).exp(),
+        }
+    }
+
+    pub fn recall_probability(self, ratio: f64) -> f64 {
+        (-self.decay * ratio).exp()
+    }
+
+    /// What intervals would need to be multiplied by for cards to be
+    /// remembered with probability `target` on their due date.
+    pub fn interval_scale(self, target: f64) -> f64 {
+        -target.ln() / self.decay
+    }
+
+    /// The average chance of remembering the cards in `samples` if they had
+    /// been given intervals `scale` times as long.
+    fn average_recall(self, samples: &[ReviewSample], scale: f64) -> f64 {
+        samples
+            .iter()
+            .map(|sample| self.recall_probability(sample.elapsed_ratio * scale))
+            .sum()
+    }
+}
+
+/// Deck options recommended by fitting forgetting curves, and the
+/// retention expected before and after applying them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OptimizedOptions {
+    /// The number of reviews the fit is based on.
+    pub review_count: u32,
+    pub current_interval_modifier: f64,
+    /// Rounded to a whole percent, as the deck options screen shows it.
+    pub interval_modifier: f64,
+    /// In permille.
+    pub current_starting_ease: u16,
+    /// In permille, rounded to a whole percent.
+    pub starting_ease: u16,
+    /// The estimated chance of remembering a review with the current
+    /// options.
+    pub retention_before: f32,
+    /// The estimated chance of remembering a review with the recommended
+    /// options.
+    pub retention_after: f32,
+}
+
+fn limit(val: f64, (min, max): (f64, f64)) -> f64 {
+    val.max(min).min(max)
+}
+
+/// Split reviews into young and mature samples. Entries are expected in
+/// card and then time order, as (cid, id, ease, last interval, type).
+#[derive(Default)]
+pub(super) struct SampleCollector {
+    pub(super) young: Vec<ReviewSample>,
+    pub(super) mature: Vec<ReviewSample>,
+    last: Option<(i64, i64)>,
+}
+
+impl SampleCollector {
+    pub(super) fn add(&mut self, cid: i64, id: i64, ease: u8, last_ivl: i32, kind: u8) {
+        if let Some((last_cid, last_id)) = self.last {
+            if last_cid == cid && kind == RevlogReviewKind::Review as u8 && last_ivl > 0 {
+                let elapsed_days = (id - last_id) as f64 / 86_400_000.0;
+                if elapsed_days > 0.0 {
+                    let sample = ReviewSample {
+                        elapsed_ratio: elapsed_days / f64::from(last_ivl),
+                        remembered: ease > 1,
+                    };
+                    if last_ivl < MATURE_INTERVAL {
+                        self.young.push(sample);
+                    } else {
+                        self.mature.push(sample);
+                    }
+                }
+            }
+        }
+        self.last = Some((cid, id));
+    }
+}
+
+impl Collection {
+    /// Fit forgetting curves to the reviews of deck `did` and its children,
+    /// and recommend the interval modifier and starting ease that would
+    /// have cards remembered with probability `target_retention` on their
+    /// due dates.
+    ///
+    /// Young and mature reviews are fit with separate curves. The interval
+    /// modifier is fit to the mature reviews. The starting ease is then fit
+    /// to the young reviews with the recommended modifier held fixed, as
+    /// the modifier lengthens young intervals too. Either is left as is if
+    /// there are too few reviews. The retention before and after is
+    /// estimated with the same two curves.
+    pub fn optimize_deck_options(
+        &self,
+        did: i64,
+        target_retention: f32,
+    ) -> Result<OptimizedOptions> {
+        if target_retention <= 0.0 || target_retention >= 1.0 {
+            return Err(AnkiError::invalid_input(
+                "target retention must be between 0 and 1",
+            ));
+        }
+        let target = f64::from(target_retention);
+        let decks = self.storage.get_all_decks()?;
+        let deck = decks.get(&did).ok_or(AnkiError::NotFound)?;
+        if deck.is_filtered() {
+            return Err(AnkiError::invalid_input("filtered decks have no options"));
+        }
+        let confs = self.storage.get_all_deck_conf()?;
+        let conf = CardConf::for_card(did, 0, &decks, &confs).conf;
+
+        let mut collector = SampleCollector::default();
+        self.storage.for_each_revlog_in_decks(
+            &deck_and_child_ids(&decks, did),
+            |cid, id, ease, last_ivl, kind| collector.add(cid, id, ease, last_ivl, kind),
+        )?;
+        let young = ForgettingCurve::fit(&collector.young);
+        let mature = ForgettingCurve::fit(&collector.mature);
+
+        let current_modifier = conf.rev.ivl_fct;
+        let modifier_scale = if collector.mature.len() >= MIN_REVIEWS {
+            mature.interval_scale(target)
+        } else {
+            1.0
+        };
+        let interval_modifier =
+            (limit(current_modifier * modifier_scale, INTERVAL_MODIFIER_LIMITS) * 100.0).round()
+                / 100.0;
+        let modifier_scale = interval_modifier / current_modifier;
+
+        // with the modifier fixed, the ease makes up the rest of the
+        // difference for young cards
+        let current_ease = f64::from(conf.new.initial_factor);
+        let young_scale = if collector.young.len() >= MIN_REVIEWS {
+            young.interval_scale(target)
+        } else {
+            modifier_scale
+        };
+        let starting_ease = (limit(
+            current_ease * young_scale / modifier_scale,
+            STARTING_EASE_LIMITS,
+        ) / 10.0)
+            .round()
+            * 10.0;
+        let young_scale = modifier_scale * starting_ease / current_ease;
+
+        let review_count = collector.young.len() + collector.mature.len();
+        let average = |young_scale: f64, mature_scale: f64| -> f32 {
+            if review_count == 0 {
+                return 0.0;
+            }
+            let total = young.average_recall(&collector.young, young_scale)
+                + mature.average_recall(&collector.mature, mature_scale);
+            (total / review_count as f64) as f32
+        };
+
+        Ok(OptimizedOptions {
+            review_count: review_count as u32,
+            current_interval_modifier: current_modifier,
+            interval_modifier,
+            current_starting_ease: conf.new.initial_factor,
+            starting_ease: starting_ease as u16,
+            retention_before: average(1.0, 1.0),
+            retention_after: average(young_scale, modifier_scale),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ForgettingCurve, ReviewSample};
+    use crate::card::Card;
+    use crate::collection::open_test_collection;
+    use crate::revlog::{RevlogEntry, RevlogReviewKind};
+
+    fn samples(count: usize, remembered: usize, elapsed_ratio: f64) -> Vec<ReviewSample> {
+        (0..count)
+            .map(|n| ReviewSample {
+                elapsed_ratio,
+                remembered: n < remembered,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_fit() {
+        let curve = ForgettingCurve::fit(&samples(1000, 900, 1.0));
+        assert!((curve.recall_probability(1.0) - 0.9).abs() < 0.001);
+        assert!((curve.interval_scale(0.9) - 1.0).abs() < 0.01);
+        // remembering less often means shorter intervals
+        let curve = ForgettingCurve::fit(&samples(1000, 800, 1.0));
+        assert!(curve.interval_scale(0.9) < 0.5);
+
+        // overdue reviews are taken into account
+        let mut mixed = samples(1000, 900, 1.0);
+        mixed.extend(samples(1000, 810, 2.0));
+        let curve = ForgettingCurve::fit(&mixed);
+        assert!((curve.recall_probability(1.0) - 0.9).abs() < 0.001);
+
+        // no lapses or no passes stay within bounds
+        assert!(ForgettingCurve::fit(&samples(10, 10, 1.0)).decay > 0.0);
+        assert!(ForgettingCurve::fit(&samples(10, 0, 1.0)).decay.is_finite());
+        assert!(ForgettingCurve::fit(&[]).decay > 0.0);
+    }
+
+    #[test]
+    fn test_optimize() {
+        let col = open_test_collection();
+        let day_millis = 86_400_000;
+        // mature cards reviewed on time and remembered 85% of the time,
+        // and young cards remembered 92% of the time
+        for cid in 1..=400 {
+            col.storage
+                .add_card(&Card {
+                    id: cid,
+                    nid: cid,
+                    ..Default::default()
+                })
+                .unwrap();
+            let ivl = if cid <= 200 { 30 } else { 5 };
+            let remembered = if cid <= 200 {
+                cid % 20 >= 3
+            } else {
+                cid % 25 >= 2
+            };
+            for (id, ease) in &[
+                (0, 3),
+                (i64::from(ivl) * day_millis, if remembered { 3 } else { 1 }),
+            ] {
+                col.storage
+                    .add_revlog_entry(&RevlogEntry {
+                        id: 1_000_000 + id + cid,
+                        cid,
+                        usn: 0,
+                        ease: *ease,
+                        interval: ivl,
+                        last_interval: ivl,
+                        factor: 2500,
+                        taken_millis: 1000,
+                        kind: RevlogReviewKind::Review,
+                    })
+                    .unwrap();
+            }
+        }
+
+        let opts = col.optimize_deck_options(1, 0.9).unwrap();
+        assert_eq!(opts.review_count, 400);
+        assert_eq!(opts.current_interval_modifier, 1.0);
+        assert_eq!(opts.interval_modifier, 0.65);
+        assert_eq!(opts.current_starting_ease, 2500);
+        assert_eq!(opts.starting_ease, 4860);
+        assert!((opts.retention_before - 0.885).abs() < 0.001);
+        assert!((opts.retention_after - 0.9).abs() < 0.001);
+
+        // recommendations stay within bounds
+        let mut confs = col.storage.get_all_deck_conf().unwrap();
+        confs.get_mut(&1).unwrap().rev.ivl_fct = 4.0;
+        col.storage.set_all_deck_conf(&confs).unwrap();
+        let opts = col.optimize_deck_options(1, 0.9).unwrap();
+        assert_eq!(opts.interval_modifier, 2.0);
+        assert_eq!(opts.starting_ease, 5000);
+
+        assert!(col.optimize_deck_options(1, 1.5).is_err());
+        assert!(col.optimize_deck_options(5, 0.9).is_err());
+    }
+}
@@ -0,0 +1,408 @@
+//! Building the study queue for the selected deck.
+
+use crate::card::CardQueue;
+use crate::collection::Collection;
+use crate::config::NewCardSpread;
+use crate::deckconf::CardConf;
+use crate::err::Result;
+use crate::sched::limits::{
+    new_limit_single, overdue_in_decks, review_limit, walk_limits, REPORT_LIMIT,
+};
+use crate::sched::siblings::BuryRules;
+use crate::time::i64_unix_secs;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::SeedableRng;
+use std::collections::{HashMap, VecDeque};
+
+/// The queue a card is shown from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QueueKind {
+    New,
+    Learning,
+    Review,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QueuedCard {
+    pub id: i64,
+    pub kind: QueueKind,
+}
+
+/// The next cards to show, and the number of cards left in each queue.
+#[derive(Debug, Clone, PartialEq)]
+pub struct QueuedCards {
+    pub cards: Vec<QueuedCard>,
+    pub new_count: u32,
+    pub learning_count: u32,
+    pub review_count: u32,
+}
+
+/// A card waiting in one of the queues.
+#[derive(Debug, Clone, Copy)]
+struct Candidate {
+    id: i64,
+    nid: i64,
+    /// The deck the card belongs to outside of filtered decks.
+    home_did: i64,
+    /// A timestamp for intraday learning cards, otherwise unused.
+    due: i64,
+}
+
+impl From<(i64, i64, i64)> for Candidate {
+    fn from((id, nid, home_did): (i64, i64, i64)) -> Self {
+        Candidate {
+            id,
+            nid,
+            home_did,
+            due: 0,
+        }
+    }
+}
+
+/// How often a new card should be mixed in with reviews: every nth card
+/// when distributing, or 0 if they are not distributed.
+fn new_card_modulus(spread: NewCardSpread, new_count: u32, review_count: u32) -> u32 {
+    if spread != NewCardSpread::Distribute || new_count == 0 {
+        return 0;
+    }
+    let modulus = (new_count + review_count) / new_count;
+    if review_count > 0 {
+        // make sure there are reviews in between
+        modulus.max(2)
+    } else {
+        modulus
+    }
+}
+
+/// Pop the next card whose note has not been shown yet. Siblings of shown
+/// cards are dropped, as they would be buried or held back for spacing
+/// once the shown card was answered.
+fn pop_unseen(
+    queue: &mut VecDeque<Candidate>,
+    seen_notes: &HashMap<i64, i64>,
+) -> Option<Candidate> {
+    while let Some(card) = queue.pop_front() {
+        if !seen_notes.contains_key(&card.nid) {
+            return Some(card);
+        }
+    }
+    None
+}
+
+/// Pop the next interday learning card that would not be buried by a
+/// shown sibling. `seen_notes` maps notes to the home deck of the shown
+/// card.
+fn pop_unburied_day_learning(
+    queue: &mut VecDeque<Candidate>,
+    seen_notes: &HashMap<i64, i64>,
+    rules: &HashMap<i64, BuryRules>,
+) -> Option<Candidate> {
+    while let Some(card) = queue.pop_front() {
+        let buried = match seen_notes.get(&card.nid) {
+            Some(did) => rules
+                .get(did)
+                .map(|r| r.buries(*did, card.home_did, CardQueue::DayLearn))
+                .unwrap_or(false),
+            None => false,
+        };
+        if !buried {
+            return Some(card);
+        }
+    }
+    None
+}
+
+impl Collection {
+    /// Build the study queue for the currently selected deck and its
+    /// children, returning up to `fetch_limit` cards in the order they
+    /// should be shown.
+    ///
+    /// New and review cards are capped by the daily limits of each deck
+    /// and its parents. Learning cards due within the learn ahead limit are
+    /// shown once nothing else is left.
+    pub fn get_queued_cards(&mut self, fetch_limit: usize) -> Result<QueuedCards> {
+        let now = i64_unix_secs();
+        let study_day = self.study_day()?;
+        let today = study_day.day;
+        let config = self.storage.get_config()?;
+        let decks = self.storage.get_all_decks()?;
+        let confs = self.storage.get_all_deck_conf()?;
+        let active: Vec<i64> = config
+            .active_decks
+            .iter()
+            .filter(|did| decks.contains_key(did))
+            .cloned()
+            .collect();
+
+        // new cards, walking through each deck's limits
+        let mut new_cards: VecDeque<Candidate> = VecDeque::new();
+        walk_limits(
+            &active,
+            &decks,
+            |deck| new_limit_single(deck, &confs, study_day),
+            |did, limit| {
+                let cards = self.storage.new_cards_in_deck(did, limit)?;
+                let count = cards.len() as u32;
+                new_cards.extend(cards.into_iter().map(Candidate::from));
+                Ok(count)
+            },
+        )?;
+
+        // reviews are capped by the selected deck's limit
+        let overdue = overdue_in_decks(&decks, &self.storage.overdue_review_counts(today)?);
+        let limit = review_limit(config.current_deck, &decks, &confs, study_day, &overdue);
+        let mut reviews: VecDeque<Candidate> = self
+            .storage
+            .due_reviews(&active, today, limit)?
+            .into_iter()
+            .map(Candidate::from)
+            .collect();
+
+        // learning cards
+        let learn_cutoff = now + i64::from(config.collapse_time);
+        let mut intraday: VecDeque<Candidate> = self
+            .storage
+            .intraday_learning(&active, learn_cutoff, REPORT_LIMIT)?
+            .into_iter()
+            .map(|(id, nid, home_did, due)| Candidate {
+                id,
+                nid,
+                home_did,
+                due,
+            })
+            .collect();
+        let mut interday: VecDeque<Candidate> = VecDeque::new();
+        let mut rng = StdRng::seed_from_u64(u64::from(today));
+        for did in &active {
+            let mut cards = self.storage.day_learning_in_deck(*did, today)?;
+            cards.shuffle(&mut rng);
+            interday.extend(cards.into_iter().map(Candidate::from));
+        }
+
+        let new_count = new_cards.len() as u32;
+        let review_count = reviews.len() as u32;
+        let learning_count = (intraday.len() + interday.len()) as u32;
+        let modulus = new_card_modulus(config.new_spread, new_count, review_count);
+
+        let mut cards = vec![];
+        let mut seen_notes = HashMap::new();
+        let rules: HashMap<i64, BuryRules> = decks
+            .keys()
+            .map(|did| {
+                let conf = CardConf::for_card(*did, 0, &decks, &confs).conf;
+                (*did, BuryRules::from_conf(&conf))
+            })
+            .collect();
+        while cards.len() < fetch_limit {
+            let position = cards.len() as u32;
+            let time_for_new = match config.new_spread {
+                NewCardSpread::First => true,
+                NewCardSpread::Last => false,
+                NewCardSpread::Distribute => modulus > 0 && position > 0 && position % modulus == 0,
+            };
+
+            let next = if intraday.front().map(|c| c.due < now).unwrap_or(false) {
+                intraday.pop_front().map(|c| (c, QueueKind::Learning))
+            } else {
+                None
+            }
+            .or_else(|| {
+                if time_for_new {
+                    pop_unseen(&mut new_cards, &seen_notes).map(|c| (c, QueueKind::New))
+                } else {
+                    None
+                }
+            })
+            .or_else(|| {
+                if config.day_learn_first {
+                    pop_unburied_day_learning(&mut interday, &seen_notes, &rules)
+                        .map(|c| (c, QueueKind::Learning))
+                } else {
+                    None
+                }
+            })
+            .or_else(|| pop_unseen(&mut reviews, &seen_notes).map(|c| (c, QueueKind::Review)))
+            .or_else(|| {
+                pop_unburied_day_learning(&mut interday, &seen_notes, &rules)
+                    .map(|c| (c, QueueKind::Learning))
+            })
+            .or_else(|| pop_unseen(&mut new_cards, &seen_notes).map(|c| (c, QueueKind::New)))
+            // learning cards due soon are shown early when nothing is left
+            .or_else(|| intraday.pop_front().map(|c| (c, QueueKind::Learning)));
+
+            match next {
+                Some((card, kind)) => {
+                    seen_notes.insert(card.nid, card.home_did);
+                    cards.push(QueuedCard { id: card.id, kind });
+                }
+                None => break,
+            }
+        }
+
+        Ok(QueuedCards {
+            cards,
+            new_count,
+            learning_count,
+            review_count,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{new_card_modulus, QueueKind, QueuedCard};
+    use crate::card::{Card, CardQueue, CardType};
+    use crate::collection::open_test_collection;
+    use crate::config::NewCardSpread;
+    use crate::deckconf::Vacation;
+    use crate::sched::Answer;
+    use crate::time::i64_unix_secs;
+    use chrono::Duration;
+    use rusqlite::NO_PARAMS;
+
+    #[test]
+    fn test_modulus() {
+        assert_eq!(new_card_modulus(NewCardSpread::Distribute, 0, 10), 0);
+        assert_eq!(new_card_modulus(NewCardSpread::Distribute, 10, 0), 1);
+        assert_eq!(new_card_modulus(NewCardSpread::Distribute, 10, 5), 2);
+        assert_eq!(new_card_modulus(NewCardSpread::Distribute, 10, 30), 4);
+        assert_eq!(new_card_modulus(NewCardSpread::First, 10, 30), 0);
+    }
+
+    #[test]
+    fn test_queue() {
+        let mut col = open_test_collection();
+        let today = col.timing_today().unwrap().days_elapsed as i32;
+        let now = i64_unix_secs();
+        let cards = vec![
+            // three new cards; 2 and 3 are siblings
+            (1, 1, CardType::New, CardQueue::New, 1),
+            (2, 2, CardType::New, CardQueue::New, 2),
+            (3, 2, CardType::New, CardQueue::New, 3),
+            // reviews, one not due yet
+            (4, 4, CardType::Review, CardQueue::Review, today - 1),
+            (5, 5, CardType::Review, CardQueue::Review, today),
+            (6, 6, CardType::Review, CardQueue::Review, today + 1),
+            // a learning card due now, and one due in 10 minutes
+            (7, 7, CardType::Learn, CardQueue::Learn, (now - 10) as i32),
+            (8, 8, CardType::Learn, CardQueue::Learn, (now + 600) as i32),
+            // interday learning
+            (9, 9, CardType::Learn, CardQueue::DayLearn, today),
+            // suspended
+            (10, 10, CardType::New, CardQueue::Suspended, 0),
+        ];
+        for (id, nid, ctype, queue, due) in cards {
+            col.storage
+                .add_card(&Card {
+                    id,
+                    nid,
+                    ctype,
+                    queue,
+                    due,
+                    ..Default::default()
+                })
+                .unwrap();
+        }
+
+        let queued = col.get_queued_cards(100).unwrap();
+        assert_eq!(queued.new_count, 3);
+        assert_eq!(queued.review_count, 2);
+        assert_eq!(queued.learning_count, 3);
+        let order: Vec<_> = queued.cards.iter().map(|c| (c.id, c.kind)).collect();
+        assert_eq!(
+            order,
+            vec![
+                (7, QueueKind::Learning),
+                (4, QueueKind::Review),
+                (1, QueueKind::New),
+                (5, QueueKind::Review),
+                (2, QueueKind::New),
+                (9, QueueKind::Learning),
+                // 3 is a sibling of 2
+                (8, QueueKind::Learning),
+            ]
+        );
+
+        // the fetch limit is respected
+        let queued = col.get_queued_cards(1).unwrap();
+        assert_eq!(
+            queued.cards,
+            vec![QueuedCard {
+                id: 7,
+                kind: QueueKind::Learning
+            }]
+        );
+
+        // daily limits apply
+        col.storage
+            .db
+            .execute(
+                "update col set decks = replace(decks, '\"newToday\": [0, 0]', ?)",
+                &[format!("\"newToday\": [{}, 19]", today)],
+            )
+            .unwrap();
+        let mut confs = col.storage.get_all_deck_conf().unwrap();
+        confs.get_mut(&1).unwrap().rev.per_day = 1;
+        col.storage.set_all_deck_conf(&confs).unwrap();
+        let queued = col.get_queued_cards(100).unwrap();
+        assert_eq!(queued.new_count, 1);
+        assert_eq!(queued.review_count, 1);
+
+        // only the selected decks are included
+        col.storage
+            .db
+            .execute(
+                "update col set conf = replace(conf, '\"activeDecks\": [1]', '\"activeDecks\": []')",
+                NO_PARAMS,
+            )
+            .unwrap();
+        let queued = col.get_queued_cards(100).unwrap();
+        assert!(queued.cards.is_empty());
+    }
+
+    #[test]
+    fn test_vacation_backlog() {
+        let mut col = open_test_collection();
+        let study_day = col.study_day().unwrap();
+        let today = study_day.day as i32;
+        // 30 reviews came due during a three day vacation that ended
+        // yesterday, and 5 are due today
+        for id in 1..=35 {
+            let due = if id <= 30 { today - 1 - id % 3 } else { today };
+            col.storage
+                .add_card(&Card {
+                    id: i64::from(id),
+                    nid: i64::from(id),
+                    ctype: CardType::Review,
+                    queue: CardQueue::Review,
+                    due,
+                    ..Default::default()
+                })
+                .unwrap();
+        }
+        let mut confs = col.storage.get_all_deck_conf().unwrap();
+        let conf = confs.get_mut(&1).unwrap();
+        conf.rev.per_day = 5;
+        conf.vacations = vec![Vacation {
+            start: (study_day.date - Duration::days(3))
+                .format("%Y-%m-%d")
+                .to_string(),
+            end: (study_day.date - Duration::days(1))
+                .format("%Y-%m-%d")
+                .to_string(),
+        }];
+        col.storage.set_all_deck_conf(&confs).unwrap();
+
+        // the backlog is spread over the three days after the vacation
+        let queued = col.get_queued_cards(100).unwrap();
+        assert_eq!(queued.review_count, 15);
+        assert_eq!(col.deck_tree().unwrap().children[0].review_count, 15);
+
+        // answering some of the backlog does not shrink what is left today
+        for card in queued.cards.iter().take(6) {
+            col.answer_card(card.id, Answer::Good, 1000).unwrap();
+        }
+        assert_eq!(col.get_queued_cards(100).unwrap().review_count, 9);
+    }
+}
@@ -0,0 +1,216 @@
+//! Changing the order new cards are shown in.
+
+use crate::card::{Card, CardType};
+use crate::collection::Collection;
+use crate::err::{AnkiError, Result};
+use crate::time::i64_unix_secs;
+use crate::undo::UndoableOp;
+use rand::seq::SliceRandom;
+use std::collections::{HashMap, HashSet};
+
+/// The first and last positions given to repositioned cards.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PositionRange {
+    pub first: u32,
+    pub last: u32,
+}
+
+/// Set a new card's position in the new queue. Cards in filtered decks
+/// keep their position in their original due number.
+fn set_new_position(card: &mut Card, position: i32) {
+    if card.odid != 0 && card.odue != 0 {
+        card.odue = position;
+    } else {
+        card.due = position;
+    }
+}
+
+impl Collection {
+    /// Give the new cards in `cids` positions in the new queue, starting at
+    /// `start` and `step` apart. Cards are placed in the order given, or
+    /// randomly if `randomize` is set, and cards of the same note share a
+    /// position. Cards that are not new are left alone.
+    ///
+    /// If `shift` is set, other new cards at `start` or later are moved
+    /// back to make room. Returns the positions used, or None if none of
+    /// the cards were new.
+    pub fn sort_cards(
+        &mut self,
+        cids: &[i64],
+        start: u32,
+        step: u32,
+        randomize: bool,
+        shift: bool,
+    ) -> Result<Option<PositionRange>> {
+        if step == 0 {
+            return Err(AnkiError::invalid_input("step must be at least 1"));
+        }
+        self.transact(Some(UndoableOp::Reposition), |col| {
+            col.sort_cards_inner(cids, start, step, randomize, shift)
+        })
+    }
+
+    fn sort_cards_inner(
+        &mut self,
+        cids: &[i64],
+        start: u32,
+        step: u32,
+        randomize: bool,
+        shift: bool,
+    ) -> Result<Option<PositionRange>> {
+        let mut cards = vec![];
+        for cid in cids {
+            if let Some(card) = self.storage.get_card(*cid)? {
+                if card.ctype == CardType::New {
+                    cards.push(card);
+                }
+            }
+        }
+        let mut seen = HashSet::new();
+        let mut nids: Vec<i64> = cards
+            .iter()
+            .map(|card| card.nid)
+            .filter(|nid| seen.insert(*nid))
+            .collect();
+        if nids.is_empty() {
+            return Ok(None);
+        }
+        if randomize {
+            nids.shuffle(&mut rand::thread_rng());
+        }
+        let positions: HashMap<i64, u32> = nids
+            .iter()
+            .enumerate()
+            .map(|(idx, nid)| (*nid, start + idx as u32 * step))
+            .collect();
+        let last = start + (nids.len() as u32 - 1) * step;
+
+        let mtime = i64_unix_secs();
+        let usn = self.usn()?;
+        if shift {
+            let sorted: Vec<i64> = cards.iter().map(|c| c.id).collect();
+            if let Some(low) = self.storage.min_new_position_from(start as i32, &sorted)? {
+                if low <= last as i32 {
+                    let by = last as i32 - low + 1;
+                    self.storage
+                        .shift_new_positions(low, by, &sorted, mtime, usn)?;
+                }
+            }
+        }
+        for mut card in cards {
+            let position = positions[&card.nid] as i32;
+            set_new_position(&mut card, position);
+            card.mtime = mtime;
+            card.usn = usn;
+            self.storage.update_card(&card)?;
+        }
+        Ok(Some(PositionRange { first: start, last }))
+    }
+
+    /// Renumber the new cards in a deck from position 1, in the order they
+    /// were added or randomly, as when the new card order of its options
+    /// is changed.
+    pub fn sort_deck(&mut self, did: i64, randomize: bool) -> Result<Option<PositionRange>> {
+        self.transact(Some(UndoableOp::Reposition), |col| {
+            let mut cards = col.storage.all_cards_in_decks(&[did])?;
+            cards.sort_by_key(|card| card.id);
+            let cids: Vec<i64> = cards.iter().map(|card| card.id).collect();
+            col.sort_cards_inner(&cids, 1, 1, randomize, false)
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::PositionRange;
+    use crate::card::{Card, CardQueue, CardType};
+    use crate::collection::{open_test_collection, Collection};
+
+    fn positions(col: &Collection) -> Vec<i32> {
+        (1..=6)
+            .map(|id| {
+                let card = col.storage.get_card(id).unwrap().unwrap();
+                if card.odid != 0 {
+                    card.odue
+                } else {
+                    card.due
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_sort_cards() {
+        let mut col = open_test_collection();
+        let cards = vec![
+            (1, 1, CardType::New, 1, false),
+            (2, 1, CardType::New, 1, false),
+            (3, 2, CardType::New, 2, false),
+            (4, 3, CardType::New, 3, false),
+            (5, 4, CardType::New, 4, true),
+            (6, 5, CardType::Review, 100, false),
+        ];
+        for (id, nid, ctype, due, filtered) in cards {
+            let (did, odid, due, odue) = if filtered {
+                (5, 1, -100, due)
+            } else {
+                (1, 0, due, 0)
+            };
+            col.storage
+                .add_card(&Card {
+                    id,
+                    nid,
+                    ctype,
+                    queue: CardQueue::New,
+                    due,
+                    odue,
+                    did,
+                    odid,
+                    ..Default::default()
+                })
+                .unwrap();
+        }
+
+        // siblings share a position, and other cards are left alone
+        assert_eq!(
+            col.sort_cards(&[4, 2, 1, 6], 10, 5, false, false).unwrap(),
+            Some(PositionRange {
+                first: 10,
+                last: 15
+            })
+        );
+        assert_eq!(positions(&col), vec![15, 15, 2, 10, 4, 100]);
+
+        // shifting makes room for the sorted cards
+        assert_eq!(
+            col.sort_cards(&[5, 3], 10, 1, false, true).unwrap(),
+            Some(PositionRange {
+                first: 10,
+                last: 11
+            })
+        );
+        assert_eq!(positions(&col), vec![17, 17, 11, 12, 10, 100]);
+        let card = col.storage.get_card(5).unwrap().unwrap();
+        assert_eq!((card.did, card.due), (5, -100));
+        // nothing is moved if the cards after the start are out of the way
+        col.sort_cards(&[3], 5, 1, false, true).unwrap();
+        assert_eq!(positions(&col), vec![17, 17, 5, 12, 10, 100]);
+
+        assert_eq!(col.sort_cards(&[6], 1, 1, false, false).unwrap(), None);
+        assert!(col.sort_cards(&[1], 1, 0, false, false).is_err());
+
+        // sorting a deck renumbers from 1 in the order added
+        assert_eq!(
+            col.sort_deck(1, false).unwrap(),
+            Some(PositionRange { first: 1, last: 4 })
+        );
+        assert_eq!(positions(&col), vec![1, 1, 2, 3, 4, 100]);
+        let range = col.sort_deck(1, true).unwrap().unwrap();
+        assert_eq!((range.first, range.last), (1, 4));
+        let mut sorted = positions(&col);
+        assert_eq!(sorted[0], sorted[1]);
+        sorted.sort();
+        sorted.dedup();
+        assert_eq!(sorted, vec![1, 2, 3, 4, 100]);
+    }
+}
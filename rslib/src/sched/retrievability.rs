@@ -0,0 +1,229 @@
+//! Estimating how likely cards are to be remembered right now.
+
+use crate::card::{Card, CardQueue, CardType};
+use crate::collection::Collection;
+use crate::err::{AnkiError, Result};
+use crate::sched::optimizer::{
+    ForgettingCurve, ReviewSample, SampleCollector, MATURE_INTERVAL, MIN_REVIEWS,
+};
+use crate::time::i64_unix_millis;
+use std::collections::HashMap;
+
+/// Used for young or mature cards when there is too little history to fit
+/// a curve to.
+const DEFAULT_RETENTION_AT_DUE: f64 = 0.9;
+
+const DAY_MILLIS: f64 = 86_400_000.0;
+
+/// An estimate of how likely a card is to be remembered.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Retrievability {
+    pub card_id: i64,
+    /// The chance of remembering the card now.
+    pub recall: f32,
+    /// The day the chance falls below the target, in the same units as a
+    /// review card's due number. It may be in the past for overdue cards.
+    pub below_target_day: u32,
+}
+
+/// The forgetting curves of young and mature cards.
+struct Curves {
+    young: ForgettingCurve,
+    mature: ForgettingCurve,
+}
+
+impl Curves {
+    fn fit(collector: &SampleCollector) -> Curves {
+        let fit = |samples: &[ReviewSample]| {
+            if samples.len() >= MIN_REVIEWS {
+                ForgettingCurve::fit(samples)
+            } else {
+                ForgettingCurve::from_retention_at_due(DEFAULT_RETENTION_AT_DUE)
+            }
+        };
+        Curves {
+            young: fit(&collector.young),
+            mature: fit(&collector.mature),
+        }
+    }
+
+    fn for_interval(&self, ivl: u32) -> ForgettingCurve {
+        if (ivl as i32) < MATURE_INTERVAL {
+            self.young
+        } else {
+            self.mature
+        }
+    }
+}
+
+/// When a card was last reviewed, in milliseconds. Cards without review
+/// history fall back on the start of the day their interval began, if they
+/// are in the review queue.
+fn last_review_millis(
+    card: &Card,
+    last_reviews: &HashMap<i64, i64>,
+    today: u32,
+    next_day_at: i64,
+) -> Option<i64> {
+    if let Some(id) = last_reviews.get(&card.id) {
+        return Some(*id);
+    }
+    let due = if card.odid != 0 && card.odue != 0 {
+        card.odue
+    } else if card.queue == CardQueue::Review {
+        card.due
+    } else {
+        return None;
+    };
+    let days_ago = today as i64 - i64::from(due) + i64::from(card.ivl) + 1;
+    Some((next_day_at - days_ago * 86_400) * 1000)
+}
+
+impl Collection {
+    /// Estimate the chance of remembering each of the review and relearning
+    /// cards in `cids` now, and the day the chance will drop below
+    /// `target_retention`. New and learning cards, and missing cards, are
+    /// left out.
+    ///
+    /// Estimates are based on the time since each card was last answered
+    /// and its interval, using forgetting curves fit to the review history
+    /// of the cards' home decks. Young and mature cards are fit
+    /// separately, and a curve that remembers cards 90% of the time on
+    /// their due dates is used if there are too few reviews.
+    pub fn card_retrievability(
+        &self,
+        cids: &[i64],
+        target_retention: f32,
+    ) -> Result<Vec<Retrievability>> {
+        if target_retention <= 0.0 || target_retention >= 1.0 {
+            return Err(AnkiError::invalid_input(
+                "target retention must be between 0 and 1",
+            ));
+        }
+        let mut cards = vec![];
+        for cid in cids {
+            if let Some(card) = self.storage.get_card(*cid)? {
+                if card.ivl > 0
+                    && (card.ctype == CardType::Review || card.ctype == CardType::Relearn)
+                {
+                    cards.push(card);
+                }
+            }
+        }
+        let mut dids: Vec<i64> = cards.iter().map(Card::home_deck_id).collect();
+        dids.sort();
+        dids.dedup();
+
+        let mut collector = SampleCollector::default();
+        let mut last_reviews: HashMap<i64, i64> = cards.iter().map(|card| (card.id, 0)).collect();
+        self.storage
+            .for_each_revlog_in_decks(&dids, |cid, id, ease, last_ivl, kind| {
+                collector.add(cid, id, ease, last_ivl, kind);
+                if let Some(last) = last_reviews.get_mut(&cid) {
+                    *last = id;
+                }
+            })?;
+        last_reviews.retain(|_, id| *id != 0);
+        let curves = Curves::fit(&collector);
+
+        let timing = self.timing_today()?;
+        let now = i64_unix_millis();
+        let target = f64::from(target_retention);
+        Ok(cards
+            .iter()
+            .filter_map(|card| {
+                let last = last_review_millis(
+                    card,
+                    &last_reviews,
+                    timing.days_elapsed,
+                    timing.next_day_at,
+                )?;
+                let ivl = f64::from(card.ivl);
+                let curve = curves.for_interval(card.ivl);
+                let elapsed = ((now - last) as f64 / DAY_MILLIS).max(0.0);
+                let below_target = last as f64 + ivl * curve.interval_scale(target) * DAY_MILLIS;
+                let days_from_today =
+                    ((below_target - (timing.next_day_at * 1000) as f64) / DAY_MILLIS).floor()
+                        + 1.0;
+                Some(Retrievability {
+                    card_id: card.id,
+                    recall: curve.recall_probability(elapsed / ivl) as f32,
+                    below_target_day: (f64::from(timing.days_elapsed) + days_from_today).max(0.0)
+                        as u32,
+                })
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::card::{Card, CardQueue, CardType};
+    use crate::collection::open_test_collection;
+    use crate::revlog::{RevlogEntry, RevlogReviewKind};
+    use crate::time::i64_unix_millis;
+
+    #[test]
+    fn test_retrievability() {
+        let col = open_test_collection();
+        let timing = col.timing_today().unwrap();
+        let today = timing.days_elapsed as i32;
+        let day_millis = 86_400_000;
+        let now = i64_unix_millis();
+
+        let cards = vec![
+            // reviewed 10 days ago with a 10 day interval
+            (1, CardType::Review, CardQueue::Review, today, 10),
+            // reviewed 5 days ago with a 20 day interval
+            (2, CardType::Review, CardQueue::Review, today + 15, 20),
+            // no history, due 10 days ago with a 10 day interval
+            (3, CardType::Review, CardQueue::Review, today - 10, 10),
+            (4, CardType::New, CardQueue::New, 1, 0),
+        ];
+        for (id, ctype, queue, due, ivl) in cards {
+            col.storage
+                .add_card(&Card {
+                    id,
+                    nid: id,
+                    ctype,
+                    queue,
+                    due,
+                    ivl,
+                    ..Default::default()
+                })
+                .unwrap();
+        }
+        for (cid, days_ago) in &[(1, 10), (2, 5)] {
+            col.storage
+                .add_revlog_entry(&RevlogEntry {
+                    id: now - days_ago * day_millis,
+                    cid: *cid,
+                    usn: 0,
+                    ease: 3,
+                    interval: 10,
+                    last_interval: 5,
+                    factor: 2500,
+                    taken_millis: 1000,
+                    kind: RevlogReviewKind::Review,
+                })
+                .unwrap();
+        }
+
+        let estimates = col.card_retrievability(&[1, 2, 3, 4, 5], 0.9).unwrap();
+        assert_eq!(estimates.len(), 3);
+        assert_eq!(estimates[0].card_id, 1);
+        assert!((estimates[0].recall - 0.9).abs() < 0.001);
+        assert_eq!(estimates[0].below_target_day, today as u32);
+        assert!((estimates[1].recall - 0.9f32.powf(0.25)).abs() < 0.001);
+        assert_eq!(estimates[1].below_target_day, today as u32 + 15);
+        // overdue cards are less likely to be remembered
+        assert!((estimates[2].recall - 0.81).abs() < 0.05);
+        assert_eq!(estimates[2].below_target_day, today as u32 - 10);
+
+        // a lower target is reached later
+        let estimates = col.card_retrievability(&[1], 0.81).unwrap();
+        assert_eq!(estimates[0].below_target_day, today as u32 + 10);
+
+        assert!(col.card_retrievability(&[1], 0.0).is_err());
+    }
+}
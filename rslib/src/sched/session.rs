@@ -0,0 +1,366 @@
+//! Study sessions and the timebox.
+//!
+//! A session starts when the client asks for one, or with the first answer
+//! after the previous session went idle or a new day started. Each answer
+//! saves the session in the collection, so it survives the collection
+//! being closed without ending it. Only the sessions of recent days are
+//! kept, in a table of their own so the collection config doesn't grow
+//! with them; the streak is kept in the config so it can outlast them.
+//! Undoing an answer does not remove it from its session.
+
+use crate::collection::Collection;
+use crate::err::Result;
+use crate::time::i64_unix_millis;
+use serde_derive::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// A session ends if no card is answered for this long.
+const SESSION_IDLE_MILLIS: i64 = 30 * 60 * 1000;
+
+/// Sessions that started this many days before today are dropped.
+const SESSION_DAYS_KEPT: u32 = 30;
+
+/// The answers given in one deck during a session.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DeckStudy {
+    pub reps: u32,
+    pub millis: u32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct StudySession {
+    /// When the session started, in milliseconds. Identifies the session.
+    #[serde(rename = "startedAt")]
+    pub started_at: i64,
+    /// When the latest card was answered, in milliseconds.
+    #[serde(rename = "lastAnswerAt")]
+    pub last_answer_at: i64,
+    /// The scheduler day the session started on.
+    pub day: u32,
+    pub reps: u32,
+    /// The time taken to answer the cards.
+    pub millis: u32,
+    /// The answers given in each deck, by deck id.
+    pub decks: HashMap<i64, DeckStudy>,
+}
+
+impl StudySession {
+    fn new(now_millis: i64, day: u32) -> Self {
+        StudySession {
+            started_at: now_millis,
+            last_answer_at: now_millis,
+            day,
+            reps: 0,
+            millis: 0,
+            decks: HashMap::new(),
+        }
+    }
+}
+
+/// The session in progress, and the timebox within it.
+#[derive(Debug, Clone)]
+pub(crate) struct ActiveSession {
+    session: StudySession,
+    timebox_started_at: i64,
+    timebox_reps: u32,
+}
+
+impl ActiveSession {
+    fn new(now_millis: i64, day: u32) -> Self {
+        ActiveSession {
+            session: StudySession::new(now_millis, day),
+            timebox_started_at: now_millis,
+            timebox_reps: 0,
+        }
+    }
+}
+
+/// The cards answered in a timebox that has run out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimeboxReached {
+    pub reps: u32,
+    pub secs: u32,
+}
+
+/// The study done in the current scheduler day.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct StudyToday {
+    pub sessions: u32,
+    pub reps: u32,
+    pub millis: u32,
+    pub decks: HashMap<i64, DeckStudy>,
+}
+
+/// The latest run of consecutive scheduler days with a session.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StudyStreak {
+    #[serde(rename = "lastDay")]
+    pub last_day: u32,
+    pub days: u32,
+}
+
+impl StudyStreak {
+    /// Add a day with a session. Days before the last one are ignored.
+    fn add_day(self, day: u32) -> StudyStreak {
+        if day <= self.last_day {
+            self
+        } else if day == self.last_day + 1 {
+            StudyStreak {
+                last_day: day,
+                days: self.days + 1,
+            }
+        } else {
+            StudyStreak {
+                last_day: day,
+                days: 1,
+            }
+        }
+    }
+}
+
+impl Collection {
+    /// Start a new session and timebox, ending the current session.
+    pub fn start_study_session(&mut self) -> Result<()> {
+        let day = self.timing_today()?.days_elapsed;
+        self.session = Some(ActiveSession::new(i64_unix_millis(), day));
+        Ok(())
+    }
+
+    /// End the current session, returning it if any cards were answered
+    /// in it.
+    pub fn end_study_session(&mut self) -> Option<StudySession> {
+        self.session
+            .take()
+            .map(|active| active.session)
+            .filter(|session| session.reps > 0)
+    }
+
+    /// Add an answer to the current session, starting a new one if needed,
+    /// and check the timebox. When the timebox has run out, a new one is
+    /// started.
+    pub(crate) fn record_study(
+        &mut self,
+        did: i64,
+        taken_millis: u32,
+        now_millis: i64,
+        day: u32,
+    ) -> Result<Option<TimeboxReached>> {
+        let mut active = match &self.session {
+            Some(active)
+                if active.session.day == day
+                    && now_millis - active.session.last_answer_at < SESSION_IDLE_MILLIS =>
+            {
+                active.clone()
+            }
+            _ => ActiveSession::new(now_millis, day),
+        };
+
+        let session = &mut active.session;
+        session.last_answer_at = now_millis;
+        session.reps += 1;
+        session.millis += taken_millis;
+        let deck = session.decks.entry(did).or_default();
+        deck.reps += 1;
+        deck.millis += taken_millis;
+        self.storage.save_study_session(session)?;
+        self.storage
+            .remove_study_sessions_before((day + 1).saturating_sub(SESSION_DAYS_KEPT))?;
+
+        let mut config = self.storage.get_config()?;
+        config.study_streak = Some(match config.study_streak {
+            Some(streak) => streak.add_day(day),
+            None => StudyStreak {
+                last_day: day,
+                days: 1,
+            },
+        });
+        self.storage.set_config(&config)?;
+
+        // the session in memory only changes once it has been saved
+        active.timebox_reps += 1;
+        let elapsed_secs = ((now_millis - active.timebox_started_at) / 1000) as u32;
+        let reached = if config.time_limit_secs > 0 && elapsed_secs >= config.time_limit_secs {
+            let reached = TimeboxReached {
+                reps: active.timebox_reps,
+                secs: elapsed_secs,
+            };
+            active.timebox_started_at = now_millis;
+            active.timebox_reps = 0;
+            Some(reached)
+        } else {
+            None
+        };
+        self.session = Some(active);
+        Ok(reached)
+    }
+
+    /// The study done today, including the current session.
+    pub fn study_today(&self) -> Result<StudyToday> {
+        let day = self.timing_today()?.days_elapsed;
+        let mut today = StudyToday::default();
+        for session in self.storage.get_study_sessions_on(day)? {
+            today.sessions += 1;
+            today.reps += session.reps;
+            today.millis += session.millis;
+            for (did, study) in session.decks {
+                let deck = today.decks.entry(did).or_default();
+                deck.reps += study.reps;
+                deck.millis += study.millis;
+            }
+        }
+        Ok(today)
+    }
+
+    /// The latest `limit` sessions with answers, latest first. Only the
+    /// sessions of the last `SESSION_DAYS_KEPT` days are kept.
+    pub fn study_sessions(&self, limit: u32) -> Result<Vec<StudySession>> {
+        self.storage.get_latest_study_sessions(limit)
+    }
+
+    /// The number of consecutive days, up to today, with a session. If
+    /// nothing has been studied yet today, the streak up to yesterday
+    /// continues.
+    pub fn study_streak(&self) -> Result<u32> {
+        let today = self.timing_today()?.days_elapsed;
+        Ok(match self.storage.get_config()?.study_streak {
+            Some(streak) if streak.last_day + 1 >= today => streak.days,
+            _ => 0,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{DeckStudy, StudyStreak, TimeboxReached, SESSION_DAYS_KEPT, SESSION_IDLE_MILLIS};
+    use crate::collection::{open_test_collection, Collection};
+
+    #[test]
+    fn test_sessions() {
+        let mut col = open_test_collection();
+        let today = col.timing_today().unwrap().days_elapsed;
+        let mut config = col.storage.get_config().unwrap();
+        config.time_limit_secs = 60;
+        col.storage.set_config(&config).unwrap();
+
+        let start = 1_000_000;
+        col.record_study(1, 5000, start, today).unwrap();
+        col.record_study(2, 3000, start + 10_000, today).unwrap();
+        assert_eq!(
+            col.record_study(1, 2000, start + 60_000, today).unwrap(),
+            Some(TimeboxReached { reps: 3, secs: 60 })
+        );
+        // the timebox restarts
+        assert_eq!(
+            col.record_study(1, 2000, start + 70_000, today).unwrap(),
+            None
+        );
+
+        let today_so_far = col.study_today().unwrap();
+        assert_eq!(
+            (
+                today_so_far.sessions,
+                today_so_far.reps,
+                today_so_far.millis
+            ),
+            (1, 4, 12_000)
+        );
+        assert_eq!(
+            today_so_far.decks[&1],
+            DeckStudy {
+                reps: 3,
+                millis: 9000
+            }
+        );
+
+        // an idle session ends
+        let later = start + 70_000 + SESSION_IDLE_MILLIS;
+        col.record_study(1, 1000, later, today).unwrap();
+        let sessions = col.study_sessions(10).unwrap();
+        assert_eq!(sessions.len(), 2);
+        assert_eq!((sessions[0].started_at, sessions[0].reps), (later, 1));
+        assert_eq!((sessions[1].started_at, sessions[1].reps), (start, 4));
+        assert_eq!(col.study_today().unwrap().sessions, 2);
+        // the sessions are kept out of the collection config
+        let config = col.storage.get_config().unwrap();
+        assert!(!config.other.contains_key("studySessions"));
+        assert_eq!(
+            col.storage.study_session_days().unwrap(),
+            vec![today, today]
+        );
+
+        let ended = col.end_study_session().unwrap();
+        assert_eq!(ended.started_at, later);
+        assert_eq!(col.end_study_session(), None);
+        // an empty session is not kept
+        col.start_study_session().unwrap();
+        assert_eq!(col.end_study_session(), None);
+        assert_eq!(col.study_sessions(10).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_failed_save() {
+        let mut col = open_test_collection();
+        let today = col.timing_today().unwrap().days_elapsed;
+        col.record_study(1, 1000, 1_000_000, today).unwrap();
+        col.storage
+            .db
+            .execute_batch("drop table study_sessions")
+            .unwrap();
+        assert!(col.record_study(1, 1000, 1_010_000, today).is_err());
+        // the session is left as it was saved
+        let session = col.end_study_session().unwrap();
+        assert_eq!((session.reps, session.last_answer_at), (1, 1_000_000));
+    }
+
+    #[test]
+    fn test_streak() {
+        let mut col = open_test_collection();
+        // move the collection's creation back so there are days before today
+        col.storage
+            .db
+            .execute_batch("update col set crt = crt - 864000")
+            .unwrap();
+        let today = col.timing_today().unwrap().days_elapsed;
+        assert!(today >= 10);
+        assert_eq!(col.study_streak().unwrap(), 0);
+
+        let mut at = 0;
+        let mut study_on = |col: &mut Collection, day: u32| {
+            at += SESSION_IDLE_MILLIS * 2;
+            col.record_study(1, 1000, at, day).unwrap();
+        };
+        for day in &[today - 5, today - 3, today - 2, today - 1] {
+            study_on(&mut col, *day);
+        }
+        // the streak continues until the end of today
+        assert_eq!(col.study_streak().unwrap(), 3);
+        study_on(&mut col, today);
+        study_on(&mut col, today);
+        assert_eq!(col.study_streak().unwrap(), 4);
+
+        // old sessions are dropped, but the streak outlasts them
+        let later = today + SESSION_DAYS_KEPT - 1;
+        study_on(&mut col, later);
+        assert_eq!(
+            col.storage.study_session_days().unwrap(),
+            vec![today, today, later]
+        );
+        let config = col.storage.get_config().unwrap();
+        assert_eq!(
+            config.study_streak,
+            Some(StudyStreak {
+                last_day: later,
+                days: 1
+            })
+        );
+        assert_eq!(
+            StudyStreak {
+                last_day: 10,
+                days: 4
+            }
+            .add_day(11)
+            .days,
+            5
+        );
+    }
+}
@@ -0,0 +1,198 @@
+use crate::card::{Card, CardQueue};
+use crate::collection::Collection;
+use crate::deckconf::DeckConf;
+use crate::err::Result;
+use crate::time::i64_unix_secs;
+use crate::undo::UndoableOp;
+
+/// Which siblings are buried when a card is answered, from the options of
+/// the card's home deck.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct BuryRules {
+    pub new: bool,
+    pub review: bool,
+    pub interday_learning: bool,
+    /// Only bury siblings with the same home deck.
+    pub same_deck_only: bool,
+}
+
+impl BuryRules {
+    pub(crate) fn from_conf(conf: &DeckConf) -> BuryRules {
+        BuryRules {
+            new: conf.new.bury,
+            review: conf.rev.bury,
+            interday_learning: conf.bury_interday_learning,
+            same_deck_only: conf.bury_same_deck_only,
+        }
+    }
+
+    /// True if a sibling in `queue` with home deck `sibling_did` is buried
+    /// when a card with home deck `did` is answered.
+    pub(crate) fn buries(self, did: i64, sibling_did: i64, queue: CardQueue) -> bool {
+        if self.same_deck_only && did != sibling_did {
+            return false;
+        }
+        match queue {
+            CardQueue::New => self.new,
+            CardQueue::Review => self.review,
+            CardQueue::DayLearn => self.interday_learning,
+            _ => false,
+        }
+    }
+}
+
+/// Which buried cards to restore.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnburyKind {
+    All,
+    /// Cards buried by the user.
+    User,
+    /// Cards buried because a sibling was answered.
+    Siblings,
+}
+
+impl UnburyKind {
+    fn queues(self) -> &'static [CardQueue] {
+        match self {
+            UnburyKind::All => &[CardQueue::SiblingBuried, CardQueue::UserBuried],
+            UnburyKind::User => &[CardQueue::UserBuried],
+            UnburyKind::Siblings => &[CardQueue::SiblingBuried],
+        }
+    }
+}
+
+impl Collection {
+    /// Bury the siblings of a card that is being answered that are new,
+    /// or due for review or interday learning, as the options call for.
+    /// Returns the ids of the buried cards.
+    pub(crate) fn bury_siblings(
+        &mut self,
+        card: &Card,
+        conf: &DeckConf,
+        today: u32,
+        mtime: i64,
+        usn: i32,
+    ) -> Result<Vec<i64>> {
+        let rules = BuryRules::from_conf(conf);
+        let did = card.home_deck_id();
+        let to_bury: Vec<_> = self
+            .storage
+            .due_siblings(card.nid, card.id, today)?
+            .into_iter()
+            .filter(|(_, queue, sibling_did)| rules.buries(did, *sibling_did, *queue))
+            .map(|(cid, _, _)| cid)
+            .collect();
+        self.storage
+            .set_cards_queue(&to_bury, CardQueue::SiblingBuried, mtime, usn)?;
+        Ok(to_bury)
+    }
+
+    /// Restore buried cards in the currently selected deck and its
+    /// children to their normal queues. Returns the number of cards
+    /// restored.
+    pub fn unbury_cards_in_current_deck(&mut self, kind: UnburyKind) -> Result<u32> {
+        self.transact(Some(UndoableOp::Unbury), |col| {
+            let usn = col.usn()?;
+            let dids = col.storage.get_config()?.active_decks;
+            col.storage
+                .restore_buried_cards(&dids, kind.queues(), i64_unix_secs(), usn)
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{BuryRules, UnburyKind};
+    use crate::card::{Card, CardQueue, CardType};
+    use crate::collection::open_test_collection;
+    use crate::sched::Answer;
+
+    #[test]
+    fn test_bury_rules() {
+        let rules = BuryRules {
+            new: true,
+            review: false,
+            interday_learning: true,
+            same_deck_only: true,
+        };
+        assert!(rules.buries(1, 1, CardQueue::New));
+        assert!(!rules.buries(1, 2, CardQueue::New));
+        assert!(!rules.buries(1, 1, CardQueue::Review));
+        assert!(rules.buries(1, 1, CardQueue::DayLearn));
+        assert!(!rules.buries(1, 1, CardQueue::Learn));
+    }
+
+    #[test]
+    fn test_bury_siblings() {
+        let mut col = open_test_collection();
+        let today = col.timing_today().unwrap().days_elapsed as i32;
+        let mut decks = col.storage.get_all_decks().unwrap();
+        let mut other = decks[&1].clone();
+        other.id = 2;
+        other.name = "Other".into();
+        decks.insert(2, other);
+        col.storage.set_all_decks(&decks).unwrap();
+        let mut confs = col.storage.get_all_deck_conf().unwrap();
+        let conf = confs.get_mut(&1).unwrap();
+        conf.new.bury = true;
+        conf.rev.bury = true;
+        conf.bury_interday_learning = true;
+        col.storage.set_all_deck_conf(&confs).unwrap();
+
+        col.storage.add_test_note(1, "").unwrap();
+        let cards = vec![
+            (1, 1, CardType::Review, CardQueue::Review, today),
+            (2, 1, CardType::New, CardQueue::New, 1),
+            (3, 1, CardType::Learn, CardQueue::DayLearn, today),
+            (4, 1, CardType::Review, CardQueue::Review, today + 1),
+            (5, 2, CardType::New, CardQueue::New, 2),
+        ];
+        for (id, did, ctype, queue, due) in cards {
+            col.storage
+                .add_card(&Card {
+                    id,
+                    nid: 1,
+                    did,
+                    ctype,
+                    queue,
+                    due,
+                    ivl: 1,
+                    ..Default::default()
+                })
+                .unwrap();
+        }
+
+        let outcome = col.answer_card(1, Answer::Good, 1000).unwrap();
+        assert_eq!(outcome.buried_sibling_ids, vec![2, 3, 5]);
+
+        // unburying restores each card's queue
+        assert_eq!(
+            col.unbury_cards_in_current_deck(UnburyKind::User).unwrap(),
+            0
+        );
+        assert_eq!(
+            col.unbury_cards_in_current_deck(UnburyKind::Siblings)
+                .unwrap(),
+            2
+        );
+        let queue = |id| col.storage.get_card(id).unwrap().unwrap().queue;
+        assert_eq!(queue(2), CardQueue::New);
+        assert_eq!(queue(3), CardQueue::DayLearn);
+        // the card in the other deck is outside the current deck
+        assert_eq!(queue(5), CardQueue::SiblingBuried);
+
+        // only siblings in the same deck
+        let mut confs = col.storage.get_all_deck_conf().unwrap();
+        confs.get_mut(&1).unwrap().bury_same_deck_only = true;
+        col.storage.set_all_deck_conf(&confs).unwrap();
+        col.storage
+            .set_cards_queue(&[5], CardQueue::New, 0, 0)
+            .unwrap();
+        col.storage
+            .db
+            .execute("update cards set due = 0 where id = 1", rusqlite::NO_PARAMS)
+            .unwrap();
+        let outcome = col.answer_card(1, Answer::Good, 1000).unwrap();
+        assert_eq!(outcome.buried_sibling_ids, vec![2, 3]);
+    }
+}
@@ -0,0 +1,440 @@
+//! Simulating future study with a set of deck options, to estimate the
+//! workload and retention they lead to.
+
+use crate::card::{Card, CardQueue, CardType};
+use crate::collection::Collection;
+use crate::deckconf::{CardConf, DeckConf};
+use crate::decks::deck_and_child_ids;
+use crate::err::{AnkiError, Result};
+use crate::sched::algorithm::{algorithm_for_conf, ReviewInput, SchedulingAlgorithm};
+use crate::sched::fuzz::fuzz_sample;
+use crate::sched::learning::{answer_learning_card, start_relearning, LearningContext};
+use crate::sched::Answer;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+/// The longest simulation that can be requested, in days.
+pub const MAX_SIMULATED_DAYS: u32 = 3650;
+
+/// How likely the simulated user is to remember a review card.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RecallModel {
+    /// Every review is passed with the same probability.
+    Constant(f32),
+    /// Memory decays exponentially, so a card reviewed on its due date is
+    /// remembered with the given probability, and overdue cards less often.
+    Exponential { retention_at_due: f32 },
+}
+
+impl RecallModel {
+    /// The chance of remembering a card with interval `ivl` after `elapsed`
+    /// days.
+    pub fn recall_probability(self, ivl: u32, elapsed: u32) -> f32 {
+        match self {
+            RecallModel::Constant(prob) => prob,
+            RecallModel::Exponential { retention_at_due } => {
+                retention_at_due.powf(elapsed as f32 / ivl.max(1) as f32)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SimulatorOptions {
+    pub days: u32,
+    pub recall: RecallModel,
+    /// The chance of passing a learning or relearning step.
+    pub learning_recall: f32,
+    /// The time taken to answer a review card, in seconds.
+    pub review_secs: u32,
+    /// The time taken to answer a new or learning card, in seconds.
+    pub learning_secs: u32,
+    /// Seeds the random number generator, so a run can be repeated.
+    pub seed: u64,
+}
+
+/// The study done on one simulated day.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct SimulatedDay {
+    /// Review cards answered, including lapses.
+    pub review_count: u32,
+    /// Answers to new, learning and relearning cards.
+    pub learn_count: u32,
+    /// New cards introduced.
+    pub new_count: u32,
+    pub seconds: u32,
+    /// The average chance of remembering a card in the review queue at the
+    /// end of the day, or 0 if there are none.
+    pub expected_retention: f32,
+}
+
+/// `count` new cards, as if they had just been added to a deck.
+pub fn synthetic_cards(count: u32) -> Vec<Card> {
+    (1..=count)
+        .map(|n| Card {
+            id: i64::from(n),
+            nid: i64::from(n),
+            due: n as i32,
+            ..Default::default()
+        })
+        .collect()
+}
+
+/// Simulate studying `cards` with `conf` for `opts.days` days, starting on
+/// day `today`, which ends at `next_day_at`. Suspended cards are ignored,
+/// and buried cards and cards in filtered decks are studied as if they
+/// were in their normal queues.
+///
+/// Intervals and learning steps are calculated the same way as when
+/// answering, apart from leech handling, which is not simulated. Passed
+/// cards are answered with Good, and failed ones with Again.
+pub fn simulate(
+    conf: &DeckConf,
+    mut cards: Vec<Card>,
+    today: u32,
+    next_day_at: i64,
+    opts: &SimulatorOptions,
+) -> Result<Vec<SimulatedDay>> {
+    let algorithm = algorithm_for_conf(conf)?;
+    for card in &mut cards {
+        restore_card(card, today);
+    }
+    cards.retain(|card| card.queue != CardQueue::Suspended);
+    let mut rng = StdRng::seed_from_u64(opts.seed);
+
+    Ok((0..opts.days)
+        .map(|offset| {
+            let mut day = DaySimulation {
+                conf,
+                algorithm: algorithm.as_ref(),
+                opts,
+                today: today + offset,
+                next_day_at: next_day_at + 86_400 * i64::from(offset),
+                now: next_day_at + 86_400 * (i64::from(offset) - 1),
+                stats: SimulatedDay::default(),
+            };
+            day.study(&mut cards, &mut rng);
+            day.stats.expected_retention = expected_retention(&cards, day.today, opts.recall);
+            day.stats
+        })
+        .collect())
+}
+
+/// Return a card to the queue it would be studied from on a normal day.
+fn restore_card(card: &mut Card, today: u32) {
+    if card.odid != 0 {
+        if card.odue != 0 {
+            card.due = card.odue;
+        }
+        card.remove_from_filtered_deck();
+        card.odue = 0;
+    }
+    match card.queue {
+        CardQueue::SiblingBuried | CardQueue::UserBuried | CardQueue::PreviewRepeat => {
+            card.queue = match card.ctype {
+                CardType::New => CardQueue::New,
+                CardType::Review => CardQueue::Review,
+                CardType::Learn | CardType::Relearn => {
+                    card.due = today as i32;
+                    CardQueue::DayLearn
+                }
+            }
+        }
+        _ => (),
+    }
+}
+
+/// The average chance of remembering the cards in the review queue.
+fn expected_retention(cards: &[Card], today: u32, recall: RecallModel) -> f32 {
+    let (total, count) = cards
+        .iter()
+        .filter(|card| card.queue == CardQueue::Review)
+        .fold((0.0, 0), |(total, count), card| {
+            let last_review = card.due - card.ivl as i32;
+            let elapsed = (today as i32 - last_review).max(0) as u32;
+            (
+                total + recall.recall_probability(card.ivl, elapsed),
+                count + 1,
+            )
+        });
+    if count > 0 {
+        total / count as f32
+    } else {
+        0.0
+    }
+}
+
+struct DaySimulation<'a> {
+    conf: &'a DeckConf,
+    algorithm: &'a dyn SchedulingAlgorithm,
+    opts: &'a SimulatorOptions,
+    today: u32,
+    next_day_at: i64,
+    /// The simulated time, in seconds.
+    now: i64,
+    stats: SimulatedDay,
+}
+
+impl<'a> DaySimulation<'a> {
+    /// Study reviews, then day learning and new cards, then intraday
+    /// learning cards until none are left before the day rolls over.
+    fn study(&mut self, cards: &mut [Card], rng: &mut StdRng) {
+        let today = self.today as i32;
+        let mut reviews: Vec<usize> =
+            indices(cards, |c| c.queue == CardQueue::Review && c.due <= today);
+        reviews.sort_by_key(|idx| (cards[*idx].due, cards[*idx].id));
+        reviews.truncate(self.conf.rev.per_day as usize);
+        for idx in reviews {
+            self.answer_review(&mut cards[idx], rng);
+        }
+
+        let mut new: Vec<usize> = indices(cards, |c| c.queue == CardQueue::New);
+        new.sort_by_key(|idx| (cards[*idx].due, cards[*idx].id));
+        new.truncate(self.conf.new.per_day as usize);
+        self.stats.new_count = new.len() as u32;
+        let day_learning = indices(cards, |c| c.queue == CardQueue::DayLearn && c.due <= today);
+        for idx in day_learning.into_iter().chain(new) {
+            self.answer_learning(&mut cards[idx], rng);
+        }
+
+        let mut learning = indices(cards, |c| c.queue == CardQueue::Learn);
+        while let Some(pos) = (0..learning.len()).min_by_key(|pos| {
+            let card = &cards[learning[*pos]];
+            (card.due, card.id)
+        }) {
+            let card = &mut cards[learning[pos]];
+            self.now = self.now.max(i64::from(card.due));
+            self.answer_learning(card, rng);
+            if card.queue != CardQueue::Learn {
+                learning.swap_remove(pos);
+            }
+        }
+    }
+
+    fn context(&self, card: &Card) -> LearningContext<'a> {
+        LearningContext {
+            now_millis: self.now * 1000,
+            today: self.today,
+            next_day_at: self.next_day_at,
+            usn: 0,
+            new_conf: &self.conf.new,
+            lapse_conf: &self.conf.lapse,
+            fuzz: Some(fuzz_sample(card.id, card.reps)),
+        }
+    }
+
+    fn answer_review(&mut self, card: &mut Card, rng: &mut StdRng) {
+        let last_review = card.due - card.ivl as i32;
+        let elapsed = (self.today as i32 - last_review).max(0) as u32;
+        let passed = rng.gen::<f32>() < self.opts.recall.recall_probability(card.ivl, elapsed);
+
+        card.reps += 1;
+        let ctx = self.context(card);
+        let answer = if passed { Answer::Good } else { Answer::Again };
+        let input = ReviewInput::from_card(card, answer, self.today, self.conf, ctx.fuzz);
+        self.algorithm.answer_review(&input).apply_to(card);
+        if passed || self.conf.lapse.delays.is_empty() {
+            card.due = (self.today + card.ivl) as i32;
+        } else {
+            start_relearning(card, &ctx);
+        }
+        if !passed {
+            card.lapses += 1;
+        }
+
+        self.stats.review_count += 1;
+        self.stats.seconds += self.opts.review_secs;
+        self.now += i64::from(self.opts.review_secs);
+    }
+
+    fn answer_learning(&mut self, card: &mut Card, rng: &mut StdRng) {
+        let answer = if rng.gen::<f32>() < self.opts.learning_recall {
+            Answer::Good
+        } else {
+            Answer::Again
+        };
+
+        card.reps += 1;
+        let ctx = self.context(card);
+        // only new and learning cards are passed in, so this can't fail
+        let _ = answer_learning_card(card, answer, 0, &ctx);
+
+        self.stats.learn_count += 1;
+        self.stats.seconds += self.opts.learning_secs;
+        // time always moves on, so steps of zero minutes can't repeat forever
+        self.now += i64::from(self.opts.learning_secs.max(1));
+    }
+}
+
+/// The positions of the cards matching `pred`.
+fn indices<F: Fn(&Card) -> bool>(cards: &[Card], pred: F) -> Vec<usize> {
+    cards
+        .iter()
+        .enumerate()
+        .filter(|(_, card)| pred(card))
+        .map(|(idx, _)| idx)
+        .collect()
+}
+
+impl Collection {
+    /// Simulate studying deck `did` and its children for `opts.days` days.
+    /// `conf` replaces the deck's current options if provided, and if
+    /// `synthetic_new_cards` is non-zero, that many new cards are studied
+    /// instead of the deck's cards.
+    pub fn simulate_workload(
+        &self,
+        did: i64,
+        conf: Option<DeckConf>,
+        synthetic_new_cards: u32,
+        opts: &SimulatorOptions,
+    ) -> Result<Vec<SimulatedDay>> {
+        if opts.days > MAX_SIMULATED_DAYS {
+            return Err(AnkiError::invalid_input(format!(
+                "can't simulate more than {} days",
+                MAX_SIMULATED_DAYS
+            )));
+        }
+        let decks = self.storage.get_all_decks()?;
+        let deck = decks.get(&did).ok_or(AnkiError::NotFound)?;
+        if deck.is_filtered() {
+            return Err(AnkiError::invalid_input("filtered decks have no options"));
+        }
+        let conf = match conf {
+            Some(conf) => conf,
+            None => {
+                let confs = self.storage.get_all_deck_conf()?;
+                CardConf::for_card(did, 0, &decks, &confs).conf
+            }
+        };
+
+        let timing = self.timing_today()?;
+        let cards = if synthetic_new_cards > 0 {
+            synthetic_cards(synthetic_new_cards)
+        } else {
+            self.storage
+                .all_cards_in_decks(&deck_and_child_ids(&decks, did))?
+        };
+
+        simulate(&conf, cards, timing.days_elapsed, timing.next_day_at, opts)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{simulate, synthetic_cards, RecallModel, SimulatorOptions};
+    use crate::card::{Card, CardQueue, CardType};
+    use crate::collection::open_test_collection;
+    use crate::deckconf::DeckConf;
+
+    fn options(days: u32, recall: RecallModel) -> SimulatorOptions {
+        SimulatorOptions {
+            days,
+            recall,
+            learning_recall: 1.0,
+            review_secs: 10,
+            learning_secs: 20,
+            seed: 1,
+        }
+    }
+
+    #[test]
+    fn test_recall_model() {
+        let model = RecallModel::Exponential {
+            retention_at_due: 0.9,
+        };
+        assert_eq!(model.recall_probability(10, 0), 1.0);
+        assert!((model.recall_probability(10, 10) - 0.9).abs() < 0.0001);
+        assert!((model.recall_probability(10, 20) - 0.81).abs() < 0.0001);
+        assert_eq!(RecallModel::Constant(0.5).recall_probability(10, 20), 0.5);
+    }
+
+    #[test]
+    fn test_simulate() {
+        let conf = DeckConf::default();
+        let days = simulate(
+            &conf,
+            synthetic_cards(30),
+            0,
+            86_400,
+            &options(10, RecallModel::Constant(1.0)),
+        )
+        .unwrap();
+        assert_eq!(days.len(), 10);
+
+        // 20 new cards a day, each passing both steps and graduating
+        assert_eq!(days[0].new_count, 20);
+        assert_eq!(days[0].learn_count, 40);
+        assert_eq!(days[0].review_count, 0);
+        assert_eq!(days[0].seconds, 800);
+        assert_eq!(days[0].expected_retention, 1.0);
+        // the rest are introduced the next day, along with the first reviews
+        assert_eq!(days[1].new_count, 10);
+        assert_eq!(days[1].review_count, 20);
+        assert_eq!(days[1].seconds, 10 * 20 + 20 * 20);
+        assert_eq!(days[2].new_count, 0);
+        let reviews: u32 = days.iter().map(|d| d.review_count).sum();
+        assert!(reviews > 30);
+
+        // a run can be repeated with the same seed
+        let opts = SimulatorOptions {
+            learning_recall: 0.8,
+            ..options(
+                60,
+                RecallModel::Exponential {
+                    retention_at_due: 0.9,
+                },
+            )
+        };
+        let first = simulate(&conf, synthetic_cards(100), 0, 86_400, &opts).unwrap();
+        assert_eq!(
+            first,
+            simulate(&conf, synthetic_cards(100), 0, 86_400, &opts).unwrap()
+        );
+        let last = first.last().unwrap();
+        assert!(last.expected_retention > 0.8 && last.expected_retention < 1.0);
+    }
+
+    #[test]
+    fn test_simulate_deck() {
+        let col = open_test_collection();
+        let today = col.timing_today().unwrap().days_elapsed;
+        let cards = vec![
+            (1, CardType::Review, CardQueue::Review, today as i32),
+            (2, CardType::Review, CardQueue::Suspended, today as i32),
+            (3, CardType::Review, CardQueue::UserBuried, today as i32 + 1),
+            (4, CardType::New, CardQueue::New, 1),
+        ];
+        for (id, ctype, queue, due) in cards {
+            col.storage
+                .add_card(&Card {
+                    id,
+                    nid: id,
+                    ctype,
+                    queue,
+                    due,
+                    ivl: 5,
+                    factor: 2500,
+                    ..Default::default()
+                })
+                .unwrap();
+        }
+
+        let opts = options(2, RecallModel::Constant(1.0));
+        let days = col.simulate_workload(1, None, 0, &opts).unwrap();
+        assert_eq!((days[0].review_count, days[0].new_count), (1, 1));
+        // the buried card comes back the next day, along with the new card
+        // that graduated
+        assert_eq!(days[1].review_count, 2);
+
+        // options and cards can be replaced
+        let mut conf = DeckConf::default();
+        conf.new.per_day = 5;
+        let days = col.simulate_workload(1, Some(conf), 50, &opts).unwrap();
+        assert_eq!((days[0].review_count, days[0].new_count), (0, 5));
+
+        assert!(col
+            .simulate_workload(1, None, 0, &options(5000, RecallModel::Constant(1.0)))
+            .is_err());
+        assert!(col.simulate_workload(5, None, 0, &opts).is_err());
+    }
+}
@@ -0,0 +1,318 @@
+//! Describing lengths of time, such as the intervals shown on the answer
+//! buttons.
+
+use crate::collection::Collection;
+use crate::err::Result;
+use crate::i18n::I18n;
+
+/// A unit lengths of time are described in. Months are 30 days and years
+/// are 365 days.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum TimespanUnit {
+    Seconds,
+    Minutes,
+    Hours,
+    Days,
+    Months,
+    Years,
+}
+
+impl TimespanUnit {
+    fn secs(self) -> f64 {
+        match self {
+            TimespanUnit::Seconds => 1.0,
+            TimespanUnit::Minutes => 60.0,
+            TimespanUnit::Hours => 3_600.0,
+            TimespanUnit::Days => 86_400.0,
+            TimespanUnit::Months => 2_592_000.0,
+            TimespanUnit::Years => 31_536_000.0,
+        }
+    }
+
+    /// The largest unit that a length of `secs` seconds fills at least one
+    /// of, up to `max`.
+    fn natural(secs: f64, max: TimespanUnit) -> TimespanUnit {
+        let secs = secs.abs();
+        let units = [
+            TimespanUnit::Seconds,
+            TimespanUnit::Minutes,
+            TimespanUnit::Hours,
+            TimespanUnit::Days,
+            TimespanUnit::Months,
+        ];
+        for pair in units.windows(2) {
+            if secs < pair[1].secs() || max <= pair[0] {
+                return pair[0];
+            }
+        }
+        if max <= TimespanUnit::Months || secs < TimespanUnit::Years.secs() {
+            TimespanUnit::Months
+        } else {
+            TimespanUnit::Years
+        }
+    }
+
+    /// The message ids of a length in this unit, as the Python code
+    /// translates them, with `%s` standing for the amount: short,
+    /// singular, plural, and singular and plural from now.
+    fn msgids(self) -> [&'static str; 5] {
+        match self {
+            TimespanUnit::Seconds => [
+                "%ss",
+                "%s second",
+                "%s seconds",
+                "in %s second",
+                "in %s seconds",
+            ],
+            TimespanUnit::Minutes => [
+                "%sm",
+                "%s minute",
+                "%s minutes",
+                "in %s minute",
+                "in %s minutes",
+            ],
+            TimespanUnit::Hours => ["%sh", "%s hour", "%s hours", "in %s hour", "in %s hours"],
+            TimespanUnit::Days => ["%sd", "%s day", "%s days", "in %s day", "in %s days"],
+            TimespanUnit::Months => [
+                "%smo",
+                "%s month",
+                "%s months",
+                "in %s month",
+                "in %s months",
+            ],
+            TimespanUnit::Years => ["%sy", "%s year", "%s years", "in %s year", "in %s years"],
+        }
+    }
+}
+
+/// How a length of time is described.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimespanStyle {
+    /// For example, "3d".
+    Short,
+    /// For example, "3 days".
+    Long,
+    /// For example, "in 3 days".
+    FromNow,
+}
+
+/// Options for describing lengths of time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimespanFormat {
+    pub style: TimespanStyle,
+    /// The number of decimal places shown. Seconds are shown with one
+    /// fewer, and months and years with one more.
+    pub precision: u32,
+    /// The largest unit used.
+    pub max_unit: TimespanUnit,
+}
+
+impl Default for TimespanFormat {
+    fn default() -> Self {
+        TimespanFormat {
+            style: TimespanStyle::Long,
+            precision: 0,
+            max_unit: TimespanUnit::Years,
+        }
+    }
+}
+
+impl TimespanFormat {
+    /// Describe a length of `secs` seconds in the largest unit it fills.
+    pub fn format_secs(self, secs: f64, i18n: &I18n) -> String {
+        let unit = TimespanUnit::natural(secs, self.max_unit);
+        let precision = match unit {
+            TimespanUnit::Seconds => self.precision.saturating_sub(1),
+            TimespanUnit::Months | TimespanUnit::Years => self.precision + 1,
+            _ => self.precision,
+        } as usize;
+        let mut amount = secs / unit.secs();
+        if precision == 0 {
+            amount = round_half_even(amount);
+        }
+        let [short, one, other, from_now_one, from_now_other] = unit.msgids();
+        // any decimal places make the amount plural
+        let count = if precision > 0 {
+            2
+        } else {
+            amount.abs() as u64
+        };
+        let template = match self.style {
+            TimespanStyle::Short => i18n.tr(short),
+            TimespanStyle::Long => i18n.ntr(one, other, count),
+            TimespanStyle::FromNow => i18n.ntr(from_now_one, from_now_other, count),
+        };
+        let number = format!("{:.*}", precision, amount).replace('.', i18n.decimal_point());
+        template.replace("%s", &number)
+    }
+
+    /// Describe a length of `days` days in the largest unit it fills.
+    pub fn format_days(self, days: f64, i18n: &I18n) -> String {
+        self.format_secs(days * TimespanUnit::Days.secs(), i18n)
+    }
+}
+
+/// Round to the nearest whole number, and halves to the nearest even
+/// number.
+fn round_half_even(num: f64) -> f64 {
+    if (num - num.trunc()).abs() == 0.5 {
+        (num / 2.0).round() * 2.0
+    } else {
+        num.round()
+    }
+}
+
+/// The text shown on an answer button for a card that will be shown again
+/// in `secs` seconds, or 0 if it will leave a filtered deck. Intervals
+/// shorter than `collapse_secs` are shown with a `<`.
+pub fn answer_button_text(secs: u32, collapse_secs: u32, i18n: &I18n) -> String {
+    if secs == 0 {
+        return i18n.tr("(end)").to_string();
+    }
+    let format = TimespanFormat {
+        style: TimespanStyle::Short,
+        ..Default::default()
+    };
+    let text = format.format_secs(f64::from(secs), i18n);
+    if secs < collapse_secs {
+        format!("<{}", text)
+    } else {
+        text
+    }
+}
+
+impl Collection {
+    /// The text of the Again, Hard, Good and Easy buttons for a card,
+    /// describing when the card would be shown again.
+    pub fn answer_button_labels(&mut self, cid: i64, i18n: &I18n) -> Result<Vec<String>> {
+        let collapse_secs = self.storage.get_config()?.collapse_time;
+        Ok(self
+            .next_interval_secs(cid)?
+            .into_iter()
+            .map(|secs| answer_button_text(secs, collapse_secs, i18n))
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{answer_button_text, TimespanFormat, TimespanStyle, TimespanUnit};
+    use crate::card::{Card, CardQueue, CardType};
+    use crate::collection::open_test_collection;
+    use crate::i18n::test::mo_file;
+    use crate::i18n::I18n;
+    use chrono::{Local, Timelike};
+
+    #[test]
+    fn test_format_timespan() {
+        let en = &I18n::english();
+        let long = TimespanFormat::default();
+        assert_eq!(long.format_secs(1.0, en), "1 second");
+        assert_eq!(long.format_secs(59.0, en), "59 seconds");
+        assert_eq!(long.format_secs(60.0, en), "1 minute");
+        // halves are rounded to even numbers
+        assert_eq!(long.format_secs(150.0, en), "2 minutes");
+        assert_eq!(long.format_secs(210.0, en), "4 minutes");
+        assert_eq!(long.format_secs(7200.0, en), "2 hours");
+        assert_eq!(long.format_days(1.0, en), "1 day");
+        assert_eq!(long.format_days(36.0, en), "1.2 months");
+        assert_eq!(long.format_days(30.0, en), "1.0 months");
+        assert_eq!(long.format_days(730.0, en), "2.0 years");
+
+        let short = TimespanFormat {
+            style: TimespanStyle::Short,
+            ..Default::default()
+        };
+        assert_eq!(short.format_secs(600.0, en), "10m");
+        assert_eq!(short.format_days(3.0, en), "3d");
+        assert_eq!(short.format_days(36.0, en), "1.2mo");
+
+        let from_now = TimespanFormat {
+            style: TimespanStyle::FromNow,
+            precision: 1,
+            max_unit: TimespanUnit::Days,
+        };
+        assert_eq!(from_now.format_secs(5400.0, en), "in 1.5 hours");
+        assert_eq!(from_now.format_days(400.0, en), "in 400.0 days");
+        assert_eq!(from_now.format_secs(30.0, en), "in 30 seconds");
+
+        // translations come from the catalog, with its plural rule
+        let data = mo_file(
+            "(n%10==1 && n%100!=11 ? 0 : n%10>=2 && n%10<=4 && (n%100<10 || n%100>=20) ? 1 : 2)",
+            &[
+                ("%s day", "%s days", vec!["%s день", "%s дня", "%s дней"]),
+                (
+                    "in %s day",
+                    "in %s days",
+                    vec!["через %s день", "через %s дня", "через %s дней"],
+                ),
+                ("%smo", "", vec!["%sмес"]),
+            ],
+        );
+        let ru = &I18n::from_mo(&data).unwrap().with_decimal_point(",");
+        assert_eq!(long.format_days(21.0, ru), "21 день");
+        assert_eq!(long.format_days(3.0, ru), "3 дня");
+        assert_eq!(long.format_days(11.0, ru), "11 дней");
+        // any decimal places make the amount plural, as for 2
+        assert_eq!(from_now.format_days(2.0, ru), "через 2,0 дня");
+        assert_eq!(short.format_days(36.0, ru), "1,2мес");
+        // and untranslated messages are shown in English
+        assert_eq!(long.format_secs(7200.0, ru), "2 hours");
+    }
+
+    #[test]
+    fn test_answer_button_labels() {
+        let en = &I18n::english();
+        assert_eq!(answer_button_text(0, 1200, en), "(end)");
+        assert_eq!(answer_button_text(600, 1200, en), "<10m");
+        assert_eq!(answer_button_text(86_400, 1200, en), "1d");
+        let de = &I18n::from_mo(&mo_file("(n != 1)", &[("(end)", "", vec!["(Ende)"])])).unwrap();
+        assert_eq!(answer_button_text(0, 1200, de), "(Ende)");
+
+        let mut col = open_test_collection();
+        let today = col.timing_today().unwrap().days_elapsed as i32;
+        col.storage.add_test_note(1, "").unwrap();
+        for (id, ctype, queue, due) in &[
+            (1, CardType::New, CardQueue::New, 1),
+            (2, CardType::Review, CardQueue::Review, today),
+        ] {
+            col.storage
+                .add_card(&Card {
+                    id: *id,
+                    nid: 1,
+                    ctype: *ctype,
+                    queue: *queue,
+                    due: *due,
+                    ivl: 10,
+                    factor: 2500,
+                    ..Default::default()
+                })
+                .unwrap();
+        }
+
+        assert_eq!(
+            col.answer_button_labels(1, en).unwrap(),
+            vec!["<1m", "<6m", "<10m", "4d"]
+        );
+        // the intervals shown are not fuzzed
+        assert_eq!(
+            col.answer_button_labels(2, en).unwrap(),
+            vec!["<10m", "12d", "25d", "1.1mo"]
+        );
+        // a step past the day cutoff shows its delay, not the days until due
+        let mut config = col.storage.get_config().unwrap();
+        config.rollover = Some(((Local::now().hour() + 1) % 24) as i8);
+        col.storage.set_config(&config).unwrap();
+        let mut confs = col.storage.get_all_deck_conf().unwrap();
+        confs.get_mut(&1).unwrap().new.delays = vec![1.0, 120.0];
+        col.storage.set_all_deck_conf(&confs).unwrap();
+        assert_eq!(
+            col.answer_button_labels(1, en).unwrap(),
+            vec!["<1m", "1h", "2h", "4d"]
+        );
+        // nothing is changed
+        let card = col.storage.get_card(1).unwrap().unwrap();
+        assert_eq!((card.queue, card.reps), (CardQueue::New, 0));
+        assert_eq!(col.undo_status().undo, None);
+    }
+}
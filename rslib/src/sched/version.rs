@@ -0,0 +1,219 @@
+//! Converting a collection between the v1 and v2 schedulers.
+
+use crate::card::CardQueue;
+use crate::collection::Collection;
+use crate::err::{AnkiError, Result};
+use crate::time::{i64_unix_millis, i64_unix_secs};
+
+/// What changing the scheduler version changed, or would change.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct SchedulerChange {
+    /// Cards returned from filtered decks to their home decks.
+    pub filtered_cards_returned: u32,
+    /// Review cards taken out of relearning and returned to review.
+    pub relearning_cards_restored: u32,
+    /// New cards taken out of learning and moved to the end of the new
+    /// queue.
+    pub learning_cards_forgotten: u32,
+    /// Suspended and buried learning cards turned back into new or review
+    /// cards, which v1 does not support.
+    pub suspended_learning_reset: u32,
+    /// Cards buried by the user moved to the sibling buried queue, which is
+    /// the only buried queue in v1.
+    pub buried_cards_moved: u32,
+    /// Review log entries with their learning answer buttons renumbered.
+    pub revlog_entries_remapped: u32,
+}
+
+impl Collection {
+    /// Convert the collection to scheduler version 1 or 2, which requires a
+    /// full sync. All cards leave filtered decks and learning, and learning
+    /// answers in the review log are renumbered for the other scheduler's
+    /// buttons.
+    ///
+    /// If `dry_run` is set, the changes are made and then rolled back, so
+    /// the returned counts show what a real change would do. Changing to
+    /// the current version does nothing.
+    pub fn change_scheduler_version(
+        &mut self,
+        version: u8,
+        dry_run: bool,
+    ) -> Result<SchedulerChange> {
+        if version != 1 && version != 2 {
+            return Err(AnkiError::invalid_input("unsupported scheduler version"));
+        }
+        if dry_run {
+            self.storage.begin()?;
+            let res = self.change_scheduler_version_inner(version);
+            self.storage.rollback()?;
+            res
+        } else {
+            self.transact(None, |col| col.change_scheduler_version_inner(version))
+        }
+    }
+
+    fn change_scheduler_version_inner(&mut self, version: u8) -> Result<SchedulerChange> {
+        let mut config = self.storage.get_config()?;
+        let mut change = SchedulerChange::default();
+        if config.sched_ver == version {
+            return Ok(change);
+        }
+        let today = self.timing_today()?.days_elapsed;
+        let mtime = i64_unix_secs();
+        let usn = self.usn()?;
+
+        for deck in self.storage.get_all_decks()?.values() {
+            if deck.is_filtered() {
+                change.filtered_cards_returned += self.storage.empty_filtered_deck(deck.id, usn)?;
+            }
+        }
+        // v1 records the due date of relearning cards in the original due
+        // number, but v2 does not
+        change.relearning_cards_restored =
+            self.storage
+                .end_relearning(today, version == 2, mtime, usn)?;
+        let learning = self.storage.learning_card_ids()?;
+        change.learning_cards_forgotten = self.forget_cards_inner(&learning)?;
+
+        if version == 1 {
+            change.buried_cards_moved = self.storage.move_queue(
+                CardQueue::UserBuried,
+                CardQueue::SiblingBuried,
+                mtime,
+                usn,
+            )?;
+            change.suspended_learning_reset = self.storage.reset_suspended_learning(mtime, usn)?;
+            // v2 adds a hard button to learning cards
+            change.revlog_entries_remapped = self.storage.remap_learning_eases(&[3, 4], -1)?;
+        } else {
+            change.revlog_entries_remapped = self.storage.remap_learning_eases(&[2, 3], 1)?;
+        }
+
+        config.sched_ver = version;
+        self.storage.set_config(&config)?;
+        self.storage.mark_schema_modified(i64_unix_millis())?;
+        Ok(change)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::SchedulerChange;
+    use crate::card::{Card, CardQueue, CardType};
+    use crate::collection::{open_test_collection, Collection};
+    use crate::revlog::{RevlogEntry, RevlogReviewKind};
+    use rusqlite::NO_PARAMS;
+
+    fn summary(col: &Collection, cid: i64) -> (CardType, CardQueue, i32) {
+        let card = col.storage.get_card(cid).unwrap().unwrap();
+        (card.ctype, card.queue, card.due)
+    }
+
+    fn eases(col: &Collection) -> Vec<u8> {
+        col.storage
+            .db
+            .prepare("select ease from revlog order by id")
+            .unwrap()
+            .query_map(NO_PARAMS, |row| row.get(0))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_change_scheduler_version() {
+        let mut col = open_test_collection();
+        let today = col.timing_today().unwrap().days_elapsed as i32;
+        let mut config = col.storage.get_config().unwrap();
+        config.sched_ver = 2;
+        col.storage.set_config(&config).unwrap();
+
+        let cards = vec![
+            (1, CardType::New, CardQueue::New, 3),
+            (2, CardType::Learn, CardQueue::Learn, 1_500_000_000),
+            (3, CardType::Relearn, CardQueue::Learn, 1_500_000_000),
+            (4, CardType::Review, CardQueue::UserBuried, today),
+            (5, CardType::Learn, CardQueue::Suspended, 1_500_000_000),
+        ];
+        for (id, ctype, queue, due) in cards {
+            col.storage
+                .add_card(&Card {
+                    id,
+                    nid: id,
+                    ctype,
+                    queue,
+                    due,
+                    ivl: 5,
+                    ..Default::default()
+                })
+                .unwrap();
+        }
+        for (id, ease, kind) in &[
+            (1, 3, RevlogReviewKind::Learning),
+            (2, 4, RevlogReviewKind::Relearning),
+            (3, 2, RevlogReviewKind::Learning),
+            (4, 3, RevlogReviewKind::Review),
+        ] {
+            col.storage
+                .add_revlog_entry(&RevlogEntry {
+                    id: *id,
+                    cid: 1,
+                    usn: 0,
+                    ease: *ease,
+                    interval: 1,
+                    last_interval: 1,
+                    factor: 2500,
+                    taken_millis: 1000,
+                    kind: *kind,
+                })
+                .unwrap();
+        }
+
+        let expected = SchedulerChange {
+            filtered_cards_returned: 0,
+            relearning_cards_restored: 1,
+            learning_cards_forgotten: 1,
+            suspended_learning_reset: 1,
+            buried_cards_moved: 1,
+            revlog_entries_remapped: 2,
+        };
+        // a dry run changes nothing
+        assert_eq!(col.change_scheduler_version(1, true).unwrap(), expected);
+        assert_eq!(col.storage.get_config().unwrap().sched_ver, 2);
+        assert_eq!(
+            summary(&col, 4),
+            (CardType::Review, CardQueue::UserBuried, today)
+        );
+        assert_eq!(eases(&col), vec![3, 4, 2, 3]);
+
+        assert_eq!(col.change_scheduler_version(1, false).unwrap(), expected);
+        assert_eq!(col.storage.get_config().unwrap().sched_ver, 1);
+        assert_eq!(summary(&col, 1), (CardType::New, CardQueue::New, 3));
+        assert_eq!(summary(&col, 2), (CardType::New, CardQueue::New, 4));
+        assert_eq!(
+            summary(&col, 3),
+            (CardType::Review, CardQueue::Review, today + 5)
+        );
+        assert_eq!(
+            summary(&col, 4),
+            (CardType::Review, CardQueue::SiblingBuried, today)
+        );
+        assert_eq!(summary(&col, 5).0, CardType::New);
+        assert_eq!(eases(&col), vec![2, 3, 2, 3]);
+
+        // changing to the current version does nothing
+        assert_eq!(
+            col.change_scheduler_version(1, false).unwrap(),
+            SchedulerChange::default()
+        );
+
+        assert_eq!(
+            col.change_scheduler_version(2, false)
+                .unwrap()
+                .revlog_entries_remapped,
+            3
+        );
+        assert_eq!(eases(&col), vec![3, 4, 3, 3]);
+        assert!(col.change_scheduler_version(3, false).is_err());
+    }
+}
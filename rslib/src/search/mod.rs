@@ -0,0 +1,120 @@
+//! Searching for cards with the syntax used in the browser.
+
+mod parser;
+mod sqlwriter;
+
+use crate::collection::Collection;
+use crate::err::Result;
+use parser::parse;
+use sqlwriter::SqlWriter;
+
+impl Collection {
+    /// The ids of cards matching `search`. Invalid searches return a
+    /// search error describing the problem.
+    ///
+    /// `order` is an SQL order by clause, which may refer to the cards
+    /// table as `c` and the notes table as `n`, and may include a limit.
+    pub(crate) fn search_cards(&self, search: &str, order: Option<&str>) -> Result<Vec<i64>> {
+        let nodes = parse(search)?;
+        let (where_clause, args) = SqlWriter::new(self)?.build_where_clause(&nodes)?;
+
+        let mut sql = format!(
+            "select c.id from cards c, notes n where c.nid = n.id and ({})",
+            where_clause
+        );
+        if let Some(order) = order {
+            sql.push_str(" order by ");
+            sql.push_str(order);
+        }
+
+        let mut stmt = self.storage.db.prepare(&sql)?;
+        let ids = stmt.query_map(&args, |row| row.get(0))?;
+        Ok(ids.collect::<rusqlite::Result<_>>()?)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::card::{Card, CardQueue, CardType};
+    use crate::collection::{open_test_collection, Collection};
+    use crate::err::AnkiError;
+    use crate::notetypes::NoteType;
+    use rusqlite::params;
+    use std::collections::HashMap;
+
+    fn search(col: &Collection, text: &str) -> Vec<i64> {
+        col.search_cards(text, Some("c.id")).unwrap()
+    }
+
+    #[test]
+    fn test_search() {
+        let col = open_test_collection();
+        let note_type: NoteType = serde_json::from_str(
+            r#"{"id": 5, "name": "Basic", "type": 0,
+            "flds": [{"name": "Front", "ord": 0}, {"name": "Back", "ord": 1}],
+            "tmpls": [{"name": "Card 1", "ord": 0}, {"name": "Card 2", "ord": 1}]}"#,
+        )
+        .unwrap();
+        let mut note_types = HashMap::new();
+        note_types.insert(5, note_type);
+        col.storage.set_all_note_types(&note_types).unwrap();
+
+        let notes = vec![
+            (1, "dog\x1fa big animal", " animal "),
+            (2, "cat\x1fsmall dog", " animal pet "),
+            (3, "fish\x1fswims", ""),
+        ];
+        for (nid, fields, tags) in notes {
+            col.storage
+                .db
+                .execute(
+                    "insert into notes values (?, '', 5, 0, 0, ?, ?, '', 0, 0, '')",
+                    params![nid, tags, fields],
+                )
+                .unwrap();
+        }
+        let cards = vec![
+            (1, 1, 0, CardType::New, CardQueue::New),
+            (2, 1, 1, CardType::Review, CardQueue::Review),
+            (3, 2, 0, CardType::Review, CardQueue::Suspended),
+            (4, 3, 0, CardType::Learn, CardQueue::Learn),
+        ];
+        for (id, nid, ord, ctype, queue) in cards {
+            col.storage
+                .add_card(&Card {
+                    id,
+                    nid,
+                    did: 1,
+                    ord,
+                    ctype,
+                    queue,
+                    ..Default::default()
+                })
+                .unwrap();
+        }
+
+        assert_eq!(search(&col, ""), vec![1, 2, 3, 4]);
+        assert_eq!(search(&col, "dog"), vec![1, 2, 3]);
+        assert_eq!(search(&col, "front:dog"), vec![1, 2]);
+        assert_eq!(search(&col, "back:*dog"), vec![3]);
+        assert_eq!(search(&col, "front:d_g"), vec![1, 2]);
+        assert_eq!(search(&col, "dog -tag:pet"), vec![1, 2]);
+        assert_eq!(search(&col, "tag:anim*"), vec![1, 2, 3]);
+        assert_eq!(search(&col, "tag:none"), vec![4]);
+        assert_eq!(search(&col, "fish or is:suspended"), vec![3, 4]);
+        assert_eq!(search(&col, "(fish or cat) is:learn"), vec![4]);
+        assert_eq!(search(&col, "is:new or is:review"), vec![1, 2, 3]);
+        assert_eq!(search(&col, "card:2"), vec![2]);
+        assert_eq!(search(&col, "\"card:card 2\""), vec![2]);
+        assert_eq!(search(&col, "note:basic nid:1,3"), vec![1, 2, 4]);
+        assert_eq!(search(&col, "deck:default"), vec![1, 2, 3, 4]);
+        assert_eq!(search(&col, "deck:def* -deck:filtered"), vec![1, 2, 3, 4]);
+
+        for bad in &["(dog", "foo:bar", "deck:missing", "card:missing", "is:foo"] {
+            match col.search_cards(bad, None) {
+                Err(AnkiError::SearchError { .. }) => (),
+                other => panic!("{}: unexpected {:?}", bad, other),
+            }
+        }
+    }
+}
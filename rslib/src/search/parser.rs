@@ -0,0 +1,412 @@
+//! Splitting a search string into a tree of search terms.
+
+use crate::err::{AnkiError, Result};
+
+#[derive(Debug, PartialEq)]
+pub(super) enum Node {
+    And,
+    Or,
+    Not(Box<Node>),
+    Group(Vec<Node>),
+    Search(SearchNode),
+}
+
+#[derive(Debug, PartialEq)]
+pub(super) enum SearchNode {
+    /// Text in any field.
+    UnqualifiedText(String),
+    /// Text that must match the whole of a named field.
+    SingleField {
+        field: String,
+        text: String,
+    },
+    AddedInDays(u32),
+    CardTemplate(TemplateKind),
+    Deck(String),
+    NoteTypeId(i64),
+    NoteType(String),
+    Rated {
+        days: u32,
+        ease: Option<u8>,
+    },
+    Tag(String),
+    State(StateKind),
+    Flag(u8),
+    /// A comma-separated list of ids.
+    NoteIds(String),
+    /// A comma-separated list of ids.
+    CardIds(String),
+    Property {
+        operator: String,
+        kind: PropertyKind,
+    },
+    WholeCollection,
+}
+
+#[derive(Debug, PartialEq)]
+pub(super) enum TemplateKind {
+    /// 1-based, as shown to the user.
+    Ordinal(u16),
+    Name(String),
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub(super) enum StateKind {
+    New,
+    Review,
+    Learning,
+    Due,
+    Suspended,
+    Buried,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub(super) enum PropertyKind {
+    /// Days relative to today.
+    Due(i32),
+    Interval(u32),
+    Reps(u32),
+    Lapses(u32),
+    Ease(f32),
+}
+
+// Lexing
+//----------------------------------------
+
+#[derive(Debug, PartialEq)]
+enum Token {
+    Open,
+    Close,
+    Not,
+    Text(String),
+}
+
+/// Split a search into tokens. Text may be quoted with single or double
+/// quotes to include spaces and brackets, and quotes may start directly
+/// after a colon, as in `field:"some text"`.
+fn tokenize(search: &str) -> Vec<Token> {
+    let mut tokens = vec![];
+    let mut token = String::new();
+    let mut in_quote: Option<char> = None;
+
+    for c in search.chars() {
+        match c {
+            '"' | '\'' => {
+                if let Some(quote) = in_quote {
+                    if c == quote {
+                        in_quote = None;
+                    } else {
+                        token.push(c);
+                    }
+                } else if !token.is_empty() {
+                    if token.ends_with(':') {
+                        in_quote = Some(c);
+                    } else {
+                        token.push(c);
+                    }
+                } else {
+                    in_quote = Some(c);
+                }
+            }
+            ' ' | '\u{3000}' => {
+                if in_quote.is_some() {
+                    token.push(c);
+                } else if !token.is_empty() {
+                    tokens.push(Token::Text(std::mem::take(&mut token)));
+                }
+            }
+            '(' | ')' => {
+                if in_quote.is_some() {
+                    token.push(c);
+                } else {
+                    if c == ')' && !token.is_empty() {
+                        tokens.push(Token::Text(std::mem::take(&mut token)));
+                    }
+                    tokens.push(if c == '(' { Token::Open } else { Token::Close });
+                }
+            }
+            '-' => {
+                if !token.is_empty() {
+                    token.push(c);
+                } else if tokens.last() != Some(&Token::Not) {
+                    tokens.push(Token::Not);
+                }
+            }
+            _ => token.push(c),
+        }
+    }
+    if !token.is_empty() {
+        tokens.push(Token::Text(token));
+    }
+
+    tokens
+}
+
+// Parsing
+//----------------------------------------
+
+/// Parse a search into a list of nodes. Terms that are not separated by
+/// `or` are joined with an implicit `and`. An empty search returns an
+/// empty list.
+pub(super) fn parse(search: &str) -> Result<Vec<Node>> {
+    let mut tokens = tokenize(search).into_iter();
+    parse_group(&mut tokens, false)
+}
+
+fn parse_group<I: Iterator<Item = Token>>(tokens: &mut I, nested: bool) -> Result<Vec<Node>> {
+    let mut nodes = vec![];
+    loop {
+        let node = match tokens.next() {
+            None if nested => return Err(AnkiError::search_error("missing )")),
+            None => break,
+            Some(Token::Close) if nested => break,
+            Some(Token::Close) => return Err(AnkiError::search_error("unexpected )")),
+            Some(Token::Text(ref text)) if text.eq_ignore_ascii_case("or") => {
+                match nodes.last() {
+                    None | Some(Node::Or) | Some(Node::And) => {
+                        return Err(AnkiError::search_error("misplaced or"))
+                    }
+                    _ => (),
+                }
+                nodes.push(Node::Or);
+                continue;
+            }
+            Some(Token::Not) => match tokens.next() {
+                Some(token) => Node::Not(Box::new(parse_term(token, tokens)?)),
+                None => return Err(AnkiError::search_error("nothing to negate")),
+            },
+            Some(token) => parse_term(token, tokens)?,
+        };
+        match nodes.last() {
+            None | Some(Node::Or) => (),
+            _ => nodes.push(Node::And),
+        }
+        nodes.push(node);
+    }
+
+    if let Some(Node::Or) = nodes.last() {
+        return Err(AnkiError::search_error("misplaced or"));
+    }
+    Ok(nodes)
+}
+
+/// A bracketed group or a single search term.
+fn parse_term<I: Iterator<Item = Token>>(token: Token, tokens: &mut I) -> Result<Node> {
+    match token {
+        Token::Open => {
+            let group = parse_group(tokens, true)?;
+            if group.is_empty() {
+                Err(AnkiError::search_error("empty brackets"))
+            } else {
+                Ok(Node::Group(group))
+            }
+        }
+        Token::Text(text) => {
+            if text.eq_ignore_ascii_case("or") {
+                Err(AnkiError::search_error("misplaced or"))
+            } else {
+                Ok(Node::Search(parse_search_term(&text)?))
+            }
+        }
+        Token::Close => Err(AnkiError::search_error("unexpected )")),
+        Token::Not => Err(AnkiError::search_error("nothing to negate")),
+    }
+}
+
+fn parse_search_term(text: &str) -> Result<SearchNode> {
+    let colon = match text.find(':') {
+        Some(idx) => idx,
+        None => return Ok(SearchNode::UnqualifiedText(text.into())),
+    };
+    let key = text[..colon].to_lowercase();
+    let val = &text[colon + 1..];
+    let invalid = || AnkiError::search_error(format!("invalid {}: '{}'", key, val));
+
+    Ok(match key.as_str() {
+        "added" => SearchNode::AddedInDays(val.parse().map_err(|_| invalid())?),
+        "card" => SearchNode::CardTemplate(match val.parse() {
+            Ok(ord) => TemplateKind::Ordinal(ord),
+            Err(_) => TemplateKind::Name(val.into()),
+        }),
+        "deck" => {
+            if val == "*" {
+                SearchNode::WholeCollection
+            } else {
+                SearchNode::Deck(val.into())
+            }
+        }
+        "mid" => SearchNode::NoteTypeId(val.parse().map_err(|_| invalid())?),
+        "nid" | "cid" => {
+            if val.is_empty() || !val.chars().all(|c| c.is_ascii_digit() || c == ',') {
+                return Err(invalid());
+            }
+            if key == "nid" {
+                SearchNode::NoteIds(val.into())
+            } else {
+                SearchNode::CardIds(val.into())
+            }
+        }
+        "note" => SearchNode::NoteType(val.into()),
+        "prop" => parse_property(val).ok_or_else(invalid)?,
+        "rated" => {
+            let mut parts = val.splitn(2, ':');
+            let days: u32 = parts
+                .next()
+                .unwrap_or_default()
+                .parse()
+                .map_err(|_| invalid())?;
+            let ease = match parts.next() {
+                None => None,
+                Some(ease) => match ease.parse() {
+                    Ok(ease) if (1..=4).contains(&ease) => Some(ease),
+                    _ => return Err(invalid()),
+                },
+            };
+            SearchNode::Rated {
+                days: days.min(31),
+                ease,
+            }
+        }
+        "tag" => SearchNode::Tag(val.into()),
+        "dupe" => {
+            return Err(AnkiError::search_error(
+                "dupe: is not supported in this search",
+            ))
+        }
+        "flag" => match val.parse() {
+            Ok(flag) if flag <= 4 => SearchNode::Flag(flag),
+            _ => return Err(invalid()),
+        },
+        "is" => SearchNode::State(match val {
+            "new" => StateKind::New,
+            "review" => StateKind::Review,
+            "learn" => StateKind::Learning,
+            "due" => StateKind::Due,
+            "suspended" => StateKind::Suspended,
+            "buried" => StateKind::Buried,
+            _ => return Err(invalid()),
+        }),
+        _ => SearchNode::SingleField {
+            field: key.clone(),
+            text: val.into(),
+        },
+    })
+}
+
+/// Parse a property comparison such as `ivl>=10`.
+fn parse_property(val: &str) -> Option<SearchNode> {
+    let start = val.find(&['<', '>', '=', '!'][..])?;
+    let (prop, rest) = val.split_at(start);
+    let operator = ["<=", ">=", "!=", "=", "<", ">"]
+        .iter()
+        .find(|op| rest.starts_with(*op))?;
+    let num = &rest[operator.len()..];
+
+    let kind = match prop.to_lowercase().as_str() {
+        "due" => PropertyKind::Due(num.parse().ok()?),
+        "ivl" => PropertyKind::Interval(num.parse().ok()?),
+        "reps" => PropertyKind::Reps(num.parse().ok()?),
+        "lapses" => PropertyKind::Lapses(num.parse().ok()?),
+        "ease" => PropertyKind::Ease(num.parse().ok()?),
+        _ => return None,
+    };
+    Some(SearchNode::Property {
+        operator: operator.to_string(),
+        kind,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::{parse, tokenize, Node, PropertyKind, SearchNode, StateKind, TemplateKind, Token};
+    use crate::err::Result;
+
+    fn text(s: &str) -> Node {
+        Node::Search(SearchNode::UnqualifiedText(s.into()))
+    }
+
+    #[test]
+    fn test_tokenize() {
+        assert_eq!(
+            tokenize(r#"dog -"a cat" front:"b (c)" (x-y)"#),
+            vec![
+                Token::Text("dog".into()),
+                Token::Not,
+                Token::Text("a cat".into()),
+                Token::Text("front:b (c)".into()),
+                Token::Open,
+                Token::Text("x-y".into()),
+                Token::Close,
+            ]
+        );
+        assert_eq!(tokenize("--a"), vec![Token::Not, Token::Text("a".into())]);
+    }
+
+    #[test]
+    fn test_parse() -> Result<()> {
+        assert_eq!(parse("")?, vec![]);
+        assert_eq!(
+            parse("a b or -(c d)")?,
+            vec![
+                text("a"),
+                Node::And,
+                text("b"),
+                Node::Or,
+                Node::Not(Box::new(Node::Group(vec![text("c"), Node::And, text("d")]))),
+            ]
+        );
+        assert_eq!(
+            parse("Front:*dog* tag:foo is:due deck:*")?,
+            vec![
+                Node::Search(SearchNode::SingleField {
+                    field: "front".into(),
+                    text: "*dog*".into()
+                }),
+                Node::And,
+                Node::Search(SearchNode::Tag("foo".into())),
+                Node::And,
+                Node::Search(SearchNode::State(StateKind::Due)),
+                Node::And,
+                Node::Search(SearchNode::WholeCollection),
+            ]
+        );
+        assert_eq!(
+            parse("rated:40:2 prop:ivl>=10 card:2")?,
+            vec![
+                Node::Search(SearchNode::Rated {
+                    days: 31,
+                    ease: Some(2)
+                }),
+                Node::And,
+                Node::Search(SearchNode::Property {
+                    operator: ">=".into(),
+                    kind: PropertyKind::Interval(10)
+                }),
+                Node::And,
+                Node::Search(SearchNode::CardTemplate(TemplateKind::Ordinal(2))),
+            ]
+        );
+
+        for bad in &[
+            "(a",
+            "a)",
+            "()",
+            "or a",
+            "a or",
+            "a or or b",
+            "a -",
+            "is:foo",
+            "flag:5",
+            "rated:x",
+            "rated:1:5",
+            "prop:foo>1",
+            "prop:ivl",
+            "nid:1a",
+            "added:-1",
+        ] {
+            assert!(parse(bad).is_err(), "{} should fail", bad);
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,344 @@
+//! Converting a parsed search into an SQL where clause.
+
+use super::parser::{Node, PropertyKind, SearchNode, StateKind, TemplateKind};
+use crate::card::{CardQueue, CardType};
+use crate::collection::Collection;
+use crate::decks::{deck_and_child_ids, Deck};
+use crate::err::{AnkiError, Result};
+use crate::notetypes::NoteType;
+use crate::storage::ids_to_string;
+use regex::Regex;
+use std::collections::HashMap;
+use std::fmt::Write;
+
+/// The field separator in the notes table.
+const FIELD_SEPARATOR: char = '\x1f';
+
+pub(super) struct SqlWriter<'a> {
+    col: &'a Collection,
+    sql: String,
+    args: Vec<String>,
+    today: u32,
+    next_day_at: i64,
+    current_deck: i64,
+    decks: HashMap<i64, Deck>,
+    note_types: HashMap<i64, NoteType>,
+}
+
+impl SqlWriter<'_> {
+    pub(super) fn new(col: &Collection) -> Result<SqlWriter<'_>> {
+        let timing = col.timing_today()?;
+        Ok(SqlWriter {
+            col,
+            sql: String::new(),
+            args: vec![],
+            today: timing.days_elapsed,
+            next_day_at: timing.next_day_at,
+            current_deck: col.storage.get_config()?.current_deck,
+            decks: col.storage.get_all_decks()?,
+            note_types: col.storage.get_all_note_types()?,
+        })
+    }
+
+    /// Build a where clause matching `nodes`, returning the clause and
+    /// the arguments it refers to. An empty search matches all cards.
+    pub(super) fn build_where_clause(mut self, nodes: &[Node]) -> Result<(String, Vec<String>)> {
+        if nodes.is_empty() {
+            self.sql.push('1');
+        } else {
+            self.write_nodes(nodes)?;
+        }
+        Ok((self.sql, self.args))
+    }
+
+    fn write_nodes(&mut self, nodes: &[Node]) -> Result<()> {
+        for node in nodes {
+            self.write_node(node)?;
+        }
+        Ok(())
+    }
+
+    fn write_node(&mut self, node: &Node) -> Result<()> {
+        match node {
+            Node::And => self.sql.push_str(" and "),
+            Node::Or => self.sql.push_str(" or "),
+            Node::Not(node) => {
+                self.sql.push_str("not ");
+                self.write_node(node)?;
+            }
+            Node::Group(nodes) => {
+                self.sql.push('(');
+                self.write_nodes(nodes)?;
+                self.sql.push(')');
+            }
+            Node::Search(search) => {
+                self.sql.push('(');
+                self.write_search_node(search)?;
+                self.sql.push(')');
+            }
+        }
+        Ok(())
+    }
+
+    fn write_search_node(&mut self, node: &SearchNode) -> Result<()> {
+        match node {
+            SearchNode::UnqualifiedText(text) => self.write_unqualified(text),
+            SearchNode::SingleField { field, text } => self.write_single_field(field, text)?,
+            SearchNode::AddedInDays(days) => {
+                write!(self.sql, "c.id > {}", self.days_ago_millis(*days)).unwrap()
+            }
+            SearchNode::CardTemplate(template) => self.write_template(template)?,
+            SearchNode::Deck(deck) => self.write_deck(deck)?,
+            SearchNode::NoteTypeId(mid) => write!(self.sql, "n.mid = {}", mid).unwrap(),
+            SearchNode::NoteType(name) => self.write_note_type(name),
+            SearchNode::Rated { days, ease } => {
+                write!(
+                    self.sql,
+                    "c.id in (select cid from revlog where id > {}",
+                    self.days_ago_millis(*days)
+                )
+                .unwrap();
+                if let Some(ease) = ease {
+                    write!(self.sql, " and ease = {}", ease).unwrap();
+                }
+                self.sql.push(')');
+            }
+            SearchNode::Tag(tag) => self.write_tag(tag),
+            SearchNode::State(state) => self.write_state(*state),
+            SearchNode::Flag(flag) => write!(self.sql, "(c.flags & 7) == {}", flag).unwrap(),
+            SearchNode::NoteIds(nids) => write!(self.sql, "n.id in ({})", nids).unwrap(),
+            SearchNode::CardIds(cids) => write!(self.sql, "c.id in ({})", cids).unwrap(),
+            SearchNode::Property { operator, kind } => self.write_property(operator, *kind),
+            SearchNode::WholeCollection => self.sql.push('1'),
+        }
+        Ok(())
+    }
+
+    /// The id of a revlog entry or card created `days` days before the
+    /// start of today.
+    fn days_ago_millis(&self, days: u32) -> i64 {
+        (self.next_day_at - 86_400 * i64::from(days)) * 1000
+    }
+
+    fn write_unqualified(&mut self, text: &str) {
+        self.args.push(format!("%{}%", text.replace('*', "%")));
+        write!(
+            self.sql,
+            "n.sfld like ?{n} escape '\\' or n.flds like ?{n} escape '\\'",
+            n = self.args.len()
+        )
+        .unwrap();
+    }
+
+    fn write_tag(&mut self, tag: &str) {
+        if tag == "none" {
+            self.sql.push_str("n.tags = ''");
+            return;
+        }
+        let mut tag = tag.replace('*', "%");
+        if !tag.starts_with('%') {
+            tag.insert_str(0, "% ");
+        }
+        if !tag.ends_with('%') || tag.ends_with("\\%") {
+            tag.push_str(" %");
+        }
+        self.args.push(tag);
+        write!(self.sql, "n.tags like ?{} escape '\\'", self.args.len()).unwrap();
+    }
+
+    fn write_state(&mut self, state: StateKind) {
+        match state {
+            StateKind::New => write!(self.sql, "c.type = {}", CardType::New as u8).unwrap(),
+            StateKind::Review => write!(self.sql, "c.type = {}", CardType::Review as u8).unwrap(),
+            StateKind::Learning => write!(
+                self.sql,
+                "c.queue in ({}, {})",
+                CardQueue::Learn as i8,
+                CardQueue::DayLearn as i8
+            )
+            .unwrap(),
+            StateKind::Due => write!(
+                self.sql,
+                "(c.queue in ({rev}, {daylrn}) and c.due <= {today}) or \
+                 (c.queue = {lrn} and c.due <= {cutoff})",
+                rev = CardQueue::Review as i8,
+                daylrn = CardQueue::DayLearn as i8,
+                lrn = CardQueue::Learn as i8,
+                today = self.today,
+                cutoff = self.next_day_at,
+            )
+            .unwrap(),
+            StateKind::Suspended => {
+                write!(self.sql, "c.queue = {}", CardQueue::Suspended as i8).unwrap()
+            }
+            StateKind::Buried => write!(
+                self.sql,
+                "c.queue in ({}, {})",
+                CardQueue::SiblingBuried as i8,
+                CardQueue::UserBuried as i8
+            )
+            .unwrap(),
+        }
+    }
+
+    fn write_property(&mut self, operator: &str, kind: PropertyKind) {
+        match kind {
+            PropertyKind::Due(days) => write!(
+                self.sql,
+                "c.queue in ({}, {}) and c.due {} {}",
+                CardQueue::Review as i8,
+                CardQueue::DayLearn as i8,
+                operator,
+                i64::from(self.today) + i64::from(days)
+            ),
+            PropertyKind::Interval(ivl) => write!(self.sql, "c.ivl {} {}", operator, ivl),
+            PropertyKind::Reps(reps) => write!(self.sql, "c.reps {} {}", operator, reps),
+            PropertyKind::Lapses(lapses) => write!(self.sql, "c.lapses {} {}", operator, lapses),
+            PropertyKind::Ease(ease) => {
+                write!(self.sql, "c.factor {} {}", operator, (ease * 1000.0) as i32)
+            }
+        }
+        .unwrap();
+    }
+
+    fn write_note_type(&mut self, name: &str) {
+        let name = name.to_lowercase();
+        let mut ids: Vec<i64> = self
+            .note_types
+            .values()
+            .filter(|nt| nt.name.to_lowercase() == name)
+            .map(|nt| nt.id)
+            .collect();
+        ids.sort();
+        write!(self.sql, "n.mid in {}", ids_to_string(&ids)).unwrap();
+    }
+
+    fn write_deck(&mut self, deck: &str) -> Result<()> {
+        if deck == "filtered" {
+            self.sql.push_str("c.odid != 0");
+            return Ok(());
+        }
+
+        let mut dids: Vec<i64> = if deck.eq_ignore_ascii_case("current") {
+            deck_and_child_ids(&self.decks, self.current_deck)
+        } else if !deck.contains('*') {
+            let name = deck.replace('"', "").to_lowercase();
+            match self.decks.values().find(|d| d.name.to_lowercase() == name) {
+                Some(deck) => deck_and_child_ids(&self.decks, deck.id),
+                None => vec![],
+            }
+        } else {
+            let re = Regex::new(&format!(
+                "(?i)^{}",
+                regex::escape(deck).replace(r"\*", ".*")
+            ))
+            .map_err(|_| AnkiError::search_error(format!("invalid deck: '{}'", deck)))?;
+            self.decks
+                .values()
+                .filter(|d| re.is_match(&d.name))
+                .flat_map(|d| deck_and_child_ids(&self.decks, d.id))
+                .collect()
+        };
+        if dids.is_empty() {
+            return Err(AnkiError::search_error(format!("no such deck: '{}'", deck)));
+        }
+        dids.sort();
+        dids.dedup();
+
+        let dids = ids_to_string(&dids);
+        write!(self.sql, "c.did in {} or c.odid in {}", dids, dids).unwrap();
+        Ok(())
+    }
+
+    fn write_template(&mut self, template: &TemplateKind) -> Result<()> {
+        match template {
+            TemplateKind::Ordinal(ord) => {
+                write!(self.sql, "c.ord = {}", i32::from(*ord) - 1).unwrap();
+            }
+            TemplateKind::Name(name) => {
+                let name = name.to_lowercase();
+                let mut note_types: Vec<_> = self.note_types.values().collect();
+                note_types.sort_by_key(|nt| nt.id);
+                let mut clauses = vec![];
+                for nt in note_types {
+                    for template in &nt.templates {
+                        if template.name.to_lowercase() == name {
+                            if nt.is_cloze() {
+                                // cloze cards all share one template
+                                clauses.push(format!("n.mid = {}", nt.id));
+                            } else {
+                                clauses.push(format!(
+                                    "(n.mid = {} and c.ord = {})",
+                                    nt.id, template.ord
+                                ));
+                            }
+                        }
+                    }
+                }
+                if clauses.is_empty() {
+                    return Err(AnkiError::search_error(format!(
+                        "no such card template: '{}'",
+                        name
+                    )));
+                }
+                self.sql.push_str(&clauses.join(" or "));
+            }
+        }
+        Ok(())
+    }
+
+    /// Match notes whose `field` matches `text` in full. `*` matches any
+    /// sequence of characters, and `_` matches a single character.
+    fn write_single_field(&mut self, field: &str, text: &str) -> Result<()> {
+        let field = field.to_lowercase();
+        let field_ords: HashMap<i64, u16> = self
+            .note_types
+            .values()
+            .filter_map(|nt| {
+                nt.fields
+                    .iter()
+                    .find(|f| f.name.to_lowercase() == field)
+                    .map(|f| (nt.id, f.ord))
+            })
+            .collect();
+        if field_ords.is_empty() {
+            return Err(AnkiError::search_error(format!(
+                "no such field: '{}'",
+                field
+            )));
+        }
+
+        let text = text.replace('*', "%");
+        let re = Regex::new(&format!(
+            "(?si)^{}$",
+            regex::escape(&text).replace('_', ".").replace('%', ".*")
+        ))
+        .map_err(|_| AnkiError::search_error(format!("invalid search: '{}'", text)))?;
+
+        let mids: Vec<i64> = field_ords.keys().cloned().collect();
+        let mut stmt = self.col.storage.db.prepare(&format!(
+            "select id, mid, flds from notes where mid in {} and flds like ? escape '\\'",
+            ids_to_string(&mids)
+        ))?;
+        let mut rows = stmt.query(&[format!("%{}%", text)])?;
+        let mut nids = vec![];
+        while let Some(row) = rows.next()? {
+            let nid: i64 = row.get(0)?;
+            let mid: i64 = row.get(1)?;
+            let fields: String = row.get(2)?;
+            let ord = field_ords[&mid] as usize;
+            if let Some(value) = fields.split(FIELD_SEPARATOR).nth(ord) {
+                if re.is_match(value) {
+                    nids.push(nid);
+                }
+            }
+        }
+
+        if nids.is_empty() {
+            self.sql.push('0');
+        } else {
+            write!(self.sql, "n.id in {}", ids_to_string(&nids)).unwrap();
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,531 @@
+//! The series shown in the collection statistics graphs.
+//!
+//! Series over time are split into buckets of one or more days, counted
+//! from the start of the current scheduler day.
+
+use crate::card::{Card, CardQueue};
+use crate::collection::Collection;
+use crate::decks::deck_and_child_ids;
+use crate::err::Result;
+use crate::revlog::{RevlogEntry, RevlogReviewKind};
+use crate::stats::MATURE_DAYS;
+use std::collections::HashMap;
+
+/// The span of time the graphs cover.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StatsPeriod {
+    /// 31 days, a day at a time.
+    Month,
+    /// 52 weeks, a week at a time.
+    Year,
+    /// The whole history, a day, week or month at a time depending on how
+    /// long ago the first review was.
+    Life,
+}
+
+impl StatsPeriod {
+    /// The number of buckets shown, or None for no limit, and the days in
+    /// each. `age` is the number of days since the first review.
+    fn buckets(self, age: u32) -> (Option<u32>, u32) {
+        match self {
+            StatsPeriod::Month => (Some(31), 1),
+            StatsPeriod::Year => (Some(52), 7),
+            StatsPeriod::Life if age <= 100 => (None, 1),
+            StatsPeriod::Life if age <= 700 => (None, 7),
+            StatsPeriod::Life => (None, 31),
+        }
+    }
+}
+
+/// The answers given in a bucket, and the time taken in milliseconds.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ReviewBucket {
+    pub learn_count: u32,
+    pub young_count: u32,
+    pub mature_count: u32,
+    pub relearn_count: u32,
+    /// Reviews answered early in a filtered deck.
+    pub filtered_count: u32,
+    pub learn_millis: u64,
+    pub young_millis: u64,
+    pub mature_millis: u64,
+    pub relearn_millis: u64,
+    pub filtered_millis: u64,
+}
+
+impl ReviewBucket {
+    fn add_entry(&mut self, entry: &RevlogEntry) {
+        let millis = u64::from(entry.taken_millis);
+        let (count, total) = match entry.kind {
+            RevlogReviewKind::Learning => (&mut self.learn_count, &mut self.learn_millis),
+            RevlogReviewKind::Review if entry.last_interval < MATURE_DAYS as i32 => {
+                (&mut self.young_count, &mut self.young_millis)
+            }
+            RevlogReviewKind::Review => (&mut self.mature_count, &mut self.mature_millis),
+            RevlogReviewKind::Relearning => (&mut self.relearn_count, &mut self.relearn_millis),
+            RevlogReviewKind::EarlyReview => (&mut self.filtered_count, &mut self.filtered_millis),
+            RevlogReviewKind::Manual => return,
+        };
+        *count += 1;
+        *total += millis;
+    }
+
+    fn merge(&mut self, other: &ReviewBucket) {
+        self.learn_count += other.learn_count;
+        self.young_count += other.young_count;
+        self.mature_count += other.mature_count;
+        self.relearn_count += other.relearn_count;
+        self.filtered_count += other.filtered_count;
+        self.learn_millis += other.learn_millis;
+        self.young_millis += other.young_millis;
+        self.mature_millis += other.mature_millis;
+        self.relearn_millis += other.relearn_millis;
+        self.filtered_millis += other.filtered_millis;
+    }
+}
+
+/// Reviews due in a bucket, split by whether the card is mature.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DueBucket {
+    pub young: u32,
+    pub mature: u32,
+}
+
+/// Answers by button, from Again to Easy.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ButtonCounts {
+    /// Learning and relearning cards.
+    pub learning: [u32; 4],
+    pub young: [u32; 4],
+    pub mature: [u32; 4],
+}
+
+/// Answers given in an hour of the day, and how many were not Again.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct HourBucket {
+    pub count: u32,
+    pub correct: u32,
+}
+
+/// The number of cards in each state.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CardCounts {
+    pub new: u32,
+    /// Learning cards and reviews with an interval under 21 days.
+    pub young_or_learning: u32,
+    pub mature: u32,
+    pub suspended_or_buried: u32,
+}
+
+/// The intervals and ease factors of the cards in the review queue.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ReviewCardSummary {
+    pub count: u32,
+    pub average_interval: f32,
+    pub max_interval: u32,
+    /// Ease factors, in permille.
+    pub min_factor: u32,
+    pub average_factor: f32,
+    pub max_factor: u32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CollectionStats {
+    /// The number of days in each bucket of the series over time.
+    pub bucket_days: u32,
+    /// Answers given, starting with the bucket that ends today.
+    pub reviews: Vec<ReviewBucket>,
+    /// Cards added, starting with the bucket that ends today.
+    pub added: Vec<u32>,
+    /// Reviews due, starting with the bucket that starts today. Overdue
+    /// reviews are not included.
+    pub due: Vec<DueBucket>,
+    pub due_tomorrow: u32,
+    /// The number of reviews by interval, starting with the bucket that
+    /// starts at 0 days.
+    pub intervals: Vec<u32>,
+    pub buttons: ButtonCounts,
+    /// Answers by the local hour they were given in, starting at midnight.
+    pub hours: Vec<HourBucket>,
+    pub cards: CardCounts,
+    pub review_cards: ReviewCardSummary,
+}
+
+/// Group values kept by day into buckets of `days` days. With a `limit`,
+/// there are exactly that many buckets; otherwise there are as many as
+/// needed.
+fn into_buckets<T, F>(by_day: HashMap<u32, T>, days: u32, limit: Option<u32>, merge: F) -> Vec<T>
+where
+    T: Default + Clone,
+    F: Fn(&mut T, &T),
+{
+    let len = match limit {
+        Some(limit) => limit,
+        None => by_day.keys().max().map(|day| day / days + 1).unwrap_or(0),
+    };
+    let mut buckets = vec![T::default(); len as usize];
+    for (day, value) in &by_day {
+        if let Some(bucket) = buckets.get_mut((day / days) as usize) {
+            merge(bucket, value);
+        }
+    }
+    buckets
+}
+
+impl Collection {
+    /// Gather the data of the statistics graphs for a deck and its
+    /// children, or for the whole collection if `deck_id` is None.
+    pub fn collection_stats(
+        &self,
+        deck_id: Option<i64>,
+        period: StatsPeriod,
+    ) -> Result<CollectionStats> {
+        let timing = self.timing_today()?;
+        let today = timing.days_elapsed;
+        let config = self.storage.get_config()?;
+        let dids = match deck_id {
+            Some(did) => Some(deck_and_child_ids(&self.storage.get_all_decks()?, did)),
+            None => None,
+        };
+        let dids = dids.as_deref();
+
+        let cutoff_millis = timing.next_day_at * 1000;
+        // today is day 0, starting at the start of the scheduler day
+        let days_ago = |millis: i64| ((cutoff_millis - 1 - millis).max(0) / 86_400_000) as u32;
+        let period_days = match period {
+            StatsPeriod::Month => Some(31),
+            StatsPeriod::Year => Some(52 * 7),
+            StatsPeriod::Life => None,
+        };
+        let since_millis = period_days
+            .map(|days| cutoff_millis - i64::from(days) * 86_400_000)
+            .unwrap_or(0);
+        let local_midnight = timing.next_day_at - i64::from(config.rollover_hour()) * 3600;
+
+        let mut oldest_review = None;
+        let mut reviews: HashMap<u32, ReviewBucket> = HashMap::new();
+        let mut buttons = ButtonCounts::default();
+        let mut hours = vec![HourBucket::default(); 24];
+        self.storage
+            .for_each_revlog_entry_since(since_millis, dids, |entry| {
+                if entry.kind == RevlogReviewKind::Manual || entry.ease == 0 {
+                    return;
+                }
+                let day = days_ago(entry.id);
+                oldest_review = oldest_review.or(Some(day));
+                reviews.entry(day).or_default().add_entry(entry);
+
+                let learning = entry.kind == RevlogReviewKind::Learning
+                    || entry.kind == RevlogReviewKind::Relearning;
+                let (row, ease) = if learning {
+                    // the v1 scheduler has no fourth learning button
+                    let ease = if config.sched_ver == 1 {
+                        entry.ease.min(3)
+                    } else {
+                        entry.ease
+                    };
+                    (&mut buttons.learning, ease)
+                } else if entry.last_interval < MATURE_DAYS as i32 {
+                    (&mut buttons.young, entry.ease)
+                } else {
+                    (&mut buttons.mature, entry.ease)
+                };
+                if let Some(count) = row.get_mut(usize::from(ease) - 1) {
+                    *count += 1;
+                }
+
+                if entry.kind != RevlogReviewKind::EarlyReview {
+                    let secs = (entry.id / 1000 - local_midnight).rem_euclid(86_400);
+                    let hour = &mut hours[(secs / 3600) as usize];
+                    hour.count += 1;
+                    if entry.ease > 1 {
+                        hour.correct += 1;
+                    }
+                }
+            })?;
+        let age = oldest_review.map(|day| day + 1).unwrap_or(1);
+        let (limit, bucket_days) = period.buckets(age);
+
+        let mut added: HashMap<u32, u32> = HashMap::new();
+        let mut due: HashMap<u32, DueBucket> = HashMap::new();
+        let mut due_tomorrow = 0;
+        let mut intervals: HashMap<u32, u32> = HashMap::new();
+        let mut cards = CardCounts::default();
+        let mut review_cards = ReviewCardSummary::default();
+        let mut interval_total = 0u64;
+        let mut factor_total = 0u64;
+        self.storage.for_each_card_in_decks(dids, |card: &Card| {
+            if card.id >= since_millis {
+                *added.entry(days_ago(card.id)).or_default() += 1;
+            }
+            let mature = card.ivl >= MATURE_DAYS;
+            match card.queue {
+                CardQueue::New => cards.new += 1,
+                CardQueue::Learn | CardQueue::DayLearn => cards.young_or_learning += 1,
+                CardQueue::Review if mature => cards.mature += 1,
+                CardQueue::Review => cards.young_or_learning += 1,
+                CardQueue::Suspended | CardQueue::SiblingBuried | CardQueue::UserBuried => {
+                    cards.suspended_or_buried += 1
+                }
+                CardQueue::PreviewRepeat => (),
+            }
+            if card.queue == CardQueue::Review || card.queue == CardQueue::DayLearn {
+                let days_ahead = i64::from(card.due) - i64::from(today);
+                if days_ahead >= 0 {
+                    let bucket = due.entry(days_ahead as u32).or_default();
+                    if mature {
+                        bucket.mature += 1;
+                    } else {
+                        bucket.young += 1;
+                    }
+                }
+                if days_ahead == 1 {
+                    due_tomorrow += 1;
+                }
+            }
+            if card.queue == CardQueue::Review {
+                *intervals.entry(card.ivl).or_default() += 1;
+                let factor = u32::from(card.factor);
+                if review_cards.count == 0 {
+                    review_cards.min_factor = factor;
+                }
+                review_cards.count += 1;
+                review_cards.max_interval = review_cards.max_interval.max(card.ivl);
+                review_cards.min_factor = review_cards.min_factor.min(factor);
+                review_cards.max_factor = review_cards.max_factor.max(factor);
+                interval_total += u64::from(card.ivl);
+                factor_total += u64::from(factor);
+            }
+        })?;
+        if review_cards.count > 0 {
+            let count = review_cards.count as f64;
+            review_cards.average_interval = (interval_total as f64 / count) as f32;
+            review_cards.average_factor = (factor_total as f64 / count) as f32;
+        }
+
+        let add = |total: &mut u32, count: &u32| *total += *count;
+        Ok(CollectionStats {
+            bucket_days,
+            reviews: into_buckets(reviews, bucket_days, limit, ReviewBucket::merge),
+            added: into_buckets(added, bucket_days, limit, add),
+            due: into_buckets(due, bucket_days, limit, |total, bucket| {
+                total.young += bucket.young;
+                total.mature += bucket.mature;
+            }),
+            due_tomorrow,
+            // the bucket at the limit is included, as in the older code
+            intervals: into_buckets(intervals, bucket_days, limit.map(|n| n + 1), add),
+            buttons,
+            hours,
+            cards,
+            review_cards,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{into_buckets, ButtonCounts, DueBucket, HourBucket, StatsPeriod};
+    use crate::card::{Card, CardQueue, CardType};
+    use crate::collection::open_test_collection;
+    use crate::revlog::{RevlogEntry, RevlogReviewKind};
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_buckets() {
+        let by_day: HashMap<u32, u32> = vec![(0, 1), (6, 2), (7, 4), (20, 8)].into_iter().collect();
+        let add = |total: &mut u32, count: &u32| *total += *count;
+        assert_eq!(into_buckets(by_day.clone(), 7, None, add), vec![3, 4, 8]);
+        assert_eq!(into_buckets(by_day.clone(), 7, Some(2), add), vec![3, 4]);
+        assert_eq!(
+            into_buckets(by_day, 1, Some(8), add),
+            vec![1, 0, 0, 0, 0, 0, 2, 4]
+        );
+        assert_eq!(StatsPeriod::Life.buckets(101), (None, 7));
+    }
+
+    #[test]
+    fn test_collection_stats() {
+        let col = open_test_collection();
+        let timing = col.timing_today().unwrap();
+        let today = timing.days_elapsed as i32;
+        let day_start = (timing.next_day_at - 86_400) * 1000;
+        let mut config = col.storage.get_config().unwrap();
+        config.rollover = Some(4);
+        config.sched_ver = 2;
+        col.storage.set_config(&config).unwrap();
+        col.storage.add_test_note(1, "").unwrap();
+        for (id, did, queue, due, ivl, factor) in &[
+            (day_start, 1, CardQueue::New, 1, 0, 0),
+            (
+                day_start - 86_400_000,
+                1,
+                CardQueue::Review,
+                today,
+                30,
+                2500,
+            ),
+            (1, 1, CardQueue::Review, today + 1, 3, 2100),
+            (2, 1, CardQueue::Review, today - 2, 5, 2300),
+            (3, 1, CardQueue::DayLearn, today + 9, 0, 0),
+            (4, 1, CardQueue::Suspended, today, 10, 2500),
+            // outside the deck
+            (5, 2, CardQueue::Review, today, 3, 2500),
+        ] {
+            col.storage
+                .add_card(&Card {
+                    id: *id,
+                    nid: 1,
+                    did: *did,
+                    ctype: CardType::Review,
+                    queue: *queue,
+                    due: *due,
+                    ivl: *ivl,
+                    factor: *factor,
+                    ..Default::default()
+                })
+                .unwrap();
+        }
+        let hour = 3_600_000;
+        for (id, ease, last_interval, kind) in &[
+            // 4am, at the rollover
+            (day_start, 1, -600, RevlogReviewKind::Learning),
+            (day_start + hour, 3, 25, RevlogReviewKind::Review),
+            (day_start + hour * 2, 3, 3, RevlogReviewKind::Review),
+            (day_start - hour, 2, 3, RevlogReviewKind::EarlyReview),
+            (
+                day_start - 3 * 86_400_000,
+                4,
+                -600,
+                RevlogReviewKind::Relearning,
+            ),
+            (day_start - 40 * 86_400_000, 1, 25, RevlogReviewKind::Review),
+            (day_start + hour * 3, 0, 3, RevlogReviewKind::Manual),
+        ] {
+            col.storage
+                .add_revlog_entry(&RevlogEntry {
+                    id: *id,
+                    cid: 1,
+                    usn: 0,
+                    ease: *ease,
+                    interval: 1,
+                    last_interval: *last_interval,
+                    factor: 2500,
+                    taken_millis: 1000,
+                    kind: *kind,
+                })
+                .unwrap();
+        }
+
+        let stats = col.collection_stats(Some(1), StatsPeriod::Month).unwrap();
+        assert_eq!(stats.bucket_days, 1);
+        assert_eq!(stats.reviews.len(), 31);
+        let today_reviews = stats.reviews[0];
+        assert_eq!(
+            (
+                today_reviews.learn_count,
+                today_reviews.young_count,
+                today_reviews.mature_count
+            ),
+            (1, 1, 1)
+        );
+        assert_eq!(today_reviews.mature_millis, 1000);
+        assert_eq!(stats.reviews[1].filtered_count, 1);
+        assert_eq!(stats.reviews[3].relearn_count, 1);
+        // the review 40 days ago is outside the period
+        assert_eq!(
+            stats
+                .reviews
+                .iter()
+                .map(|bucket| bucket.mature_count)
+                .sum::<u32>(),
+            1
+        );
+        assert_eq!((stats.added[0], stats.added[1]), (1, 1));
+
+        assert_eq!(stats.due.len(), 31);
+        assert_eq!(
+            stats.due[0],
+            DueBucket {
+                young: 0,
+                mature: 1
+            }
+        );
+        assert_eq!(
+            stats.due[1],
+            DueBucket {
+                young: 1,
+                mature: 0
+            }
+        );
+        assert_eq!(
+            stats.due[9],
+            DueBucket {
+                young: 1,
+                mature: 0
+            }
+        );
+        assert_eq!(stats.due_tomorrow, 1);
+        assert_eq!(stats.intervals.len(), 32);
+        assert_eq!(
+            (stats.intervals[3], stats.intervals[5], stats.intervals[30]),
+            (1, 1, 1)
+        );
+
+        assert_eq!(
+            stats.buttons,
+            ButtonCounts {
+                learning: [1, 0, 0, 1],
+                young: [0, 1, 1, 0],
+                mature: [0, 0, 1, 0],
+            }
+        );
+        // filtered reviews are left out of the hours
+        assert_eq!(stats.hours[3], HourBucket::default());
+        assert_eq!(
+            stats.hours[4],
+            HourBucket {
+                count: 2,
+                correct: 1
+            }
+        );
+        assert_eq!(
+            stats.hours[5],
+            HourBucket {
+                count: 1,
+                correct: 1
+            }
+        );
+
+        assert_eq!(
+            (
+                stats.cards.new,
+                stats.cards.young_or_learning,
+                stats.cards.mature,
+                stats.cards.suspended_or_buried
+            ),
+            (1, 3, 1, 1)
+        );
+        assert_eq!(stats.review_cards.count, 3);
+        assert_eq!(stats.review_cards.max_interval, 30);
+        assert_eq!(
+            (stats.review_cards.min_factor, stats.review_cards.max_factor),
+            (2100, 2500)
+        );
+        assert!((stats.review_cards.average_factor - 2300.0).abs() < 0.01);
+
+        // the whole history, including the other deck
+        let stats = col.collection_stats(None, StatsPeriod::Life).unwrap();
+        assert_eq!(stats.bucket_days, 1);
+        assert_eq!(stats.reviews.len(), 41);
+        assert_eq!(stats.reviews[40].mature_count, 1);
+        assert_eq!(stats.review_cards.count, 4);
+        assert_eq!(stats.due.len(), 10);
+        assert_eq!(stats.intervals.len(), 31);
+
+        let stats = col.collection_stats(Some(1), StatsPeriod::Year).unwrap();
+        assert_eq!((stats.bucket_days, stats.reviews.len()), (7, 52));
+        assert_eq!(stats.reviews[0].relearn_count, 1);
+        assert_eq!(stats.reviews[5].mature_count, 1);
+    }
+}
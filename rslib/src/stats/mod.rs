@@ -0,0 +1,7 @@
+//! Statistics about the collection and the reviews done in it.
+
+pub mod graphs;
+pub mod today;
+
+/// Cards with an interval of at least this many days are mature.
+pub(crate) const MATURE_DAYS: u32 = 21;
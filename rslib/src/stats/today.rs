@@ -0,0 +1,154 @@
+//! The reviews done since the start of the current scheduler day.
+
+use crate::collection::Collection;
+use crate::err::Result;
+use crate::revlog::{RevlogEntry, RevlogReviewKind};
+use crate::stats::MATURE_DAYS;
+
+/// A summary of today's answers. Cards rescheduled by the user are not
+/// counted.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TodayStats {
+    /// The number of answers; a card answered twice counts twice.
+    pub answer_count: u32,
+    /// The time taken to answer, in milliseconds.
+    pub answer_millis: u32,
+    /// Answers with the Again button.
+    pub again_count: u32,
+    pub learn_count: u32,
+    pub review_count: u32,
+    pub relearn_count: u32,
+    /// Reviews answered early in a filtered deck.
+    pub filtered_count: u32,
+    /// Answers to mature cards, and how many of them were not Again.
+    pub mature_count: u32,
+    pub mature_correct: u32,
+}
+
+impl TodayStats {
+    fn add(&mut self, entry: &RevlogEntry) {
+        match entry.kind {
+            RevlogReviewKind::Learning => self.learn_count += 1,
+            RevlogReviewKind::Review => self.review_count += 1,
+            RevlogReviewKind::Relearning => self.relearn_count += 1,
+            RevlogReviewKind::EarlyReview => self.filtered_count += 1,
+            RevlogReviewKind::Manual => return,
+        }
+        self.answer_count += 1;
+        self.answer_millis += entry.taken_millis;
+        let correct = entry.ease > 1;
+        if !correct {
+            self.again_count += 1;
+        }
+        if entry.last_interval >= MATURE_DAYS as i32 {
+            self.mature_count += 1;
+            if correct {
+                self.mature_correct += 1;
+            }
+        }
+    }
+
+    /// The share of answers to mature cards that were correct, or None if
+    /// no mature cards were answered.
+    pub fn mature_retention(&self) -> Option<f32> {
+        if self.mature_count == 0 {
+            None
+        } else {
+            Some(self.mature_correct as f32 / self.mature_count as f32)
+        }
+    }
+}
+
+impl Collection {
+    /// Summarize the answers given since the start of the scheduler day,
+    /// in the selected deck and its children, or in the whole collection.
+    pub fn today_stats(&self, whole_collection: bool) -> Result<TodayStats> {
+        let day_start = self.timing_today()?.next_day_at - 86_400;
+        let active;
+        let dids = if whole_collection {
+            None
+        } else {
+            active = self.storage.get_config()?.active_decks;
+            Some(active.as_slice())
+        };
+        let mut stats = TodayStats::default();
+        self.storage
+            .for_each_revlog_entry_since(day_start * 1000, dids, |entry| stats.add(entry))?;
+        Ok(stats)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::card::Card;
+    use crate::collection::open_test_collection;
+    use crate::revlog::{RevlogEntry, RevlogReviewKind};
+
+    #[test]
+    fn test_today_stats() {
+        let col = open_test_collection();
+        let day_start = (col.timing_today().unwrap().next_day_at - 86_400) * 1000;
+        col.storage.add_test_note(1, "").unwrap();
+        for (cid, did) in &[(1, 1), (2, 5)] {
+            col.storage
+                .add_card(&Card {
+                    id: *cid,
+                    nid: 1,
+                    did: *did,
+                    ..Default::default()
+                })
+                .unwrap();
+        }
+        let stats = col.today_stats(true).unwrap();
+        assert_eq!(stats.answer_count, 0);
+        assert_eq!(stats.mature_retention(), None);
+
+        for (id, cid, ease, last_interval, kind) in &[
+            // yesterday
+            (day_start - 1, 1, 3, 1, RevlogReviewKind::Review),
+            (day_start, 1, 1, -600, RevlogReviewKind::Learning),
+            (day_start + 1, 1, 3, 30, RevlogReviewKind::Review),
+            (day_start + 2, 1, 1, 25, RevlogReviewKind::Review),
+            (day_start + 3, 1, 3, -600, RevlogReviewKind::Relearning),
+            (day_start + 4, 1, 4, 2, RevlogReviewKind::EarlyReview),
+            (day_start + 5, 1, 0, 2, RevlogReviewKind::Manual),
+            // in another deck
+            (day_start + 6, 2, 3, 40, RevlogReviewKind::Review),
+        ] {
+            col.storage
+                .add_revlog_entry(&RevlogEntry {
+                    id: *id,
+                    cid: *cid,
+                    usn: 0,
+                    ease: *ease,
+                    interval: 1,
+                    last_interval: *last_interval,
+                    factor: 2500,
+                    taken_millis: 2000,
+                    kind: *kind,
+                })
+                .unwrap();
+        }
+
+        let stats = col.today_stats(false).unwrap();
+        assert_eq!(
+            (stats.answer_count, stats.answer_millis, stats.again_count),
+            (5, 10_000, 2)
+        );
+        assert_eq!(
+            (
+                stats.learn_count,
+                stats.review_count,
+                stats.relearn_count,
+                stats.filtered_count
+            ),
+            (1, 2, 1, 1)
+        );
+        assert_eq!((stats.mature_count, stats.mature_correct), (2, 1));
+        assert_eq!(stats.mature_retention(), Some(0.5));
+
+        let stats = col.today_stats(true).unwrap();
+        assert_eq!(stats.answer_count, 6);
+        assert_eq!(stats.mature_retention(), Some(2.0 / 3.0));
+    }
+}
@@ -0,0 +1,655 @@
+use crate::card::{Card, CardQueue, CardType};
+use crate::err::Result;
+use crate::storage::{ids_to_string, SqliteStorage};
+use rusqlite::types::{FromSql, FromSqlError, FromSqlResult, ToSql, ToSqlOutput, ValueRef};
+use rusqlite::{params, NO_PARAMS};
+use rusqlite::{OptionalExtension, Row};
+use std::collections::HashMap;
+
+const CARD_COLUMNS: &str = "id, nid, did, ord, mod, usn, type, queue, due, ivl, factor, \
+                            reps, lapses, left, odue, odid, flags, data";
+
+/// The queue a card should be in based on its type. Learning cards may
+/// be due on a timestamp or a day number, depending on their delay.
+const RESTORE_QUEUE_SQL: &str = "(case when type in (1, 3) then \
+     (case when (case when odue then odue else due end) > 1000000000 then 1 else 3 end) \
+     else type end)";
+
+impl FromSql for CardType {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        Ok(match value.as_i64()? {
+            0 => CardType::New,
+            1 => CardType::Learn,
+            2 => CardType::Review,
+            3 => CardType::Relearn,
+            other => return Err(FromSqlError::OutOfRange(other)),
+        })
+    }
+}
+
+impl ToSql for CardType {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::from(*self as u8))
+    }
+}
+
+impl FromSql for CardQueue {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        Ok(match value.as_i64()? {
+            0 => CardQueue::New,
+            1 => CardQueue::Learn,
+            2 => CardQueue::Review,
+            3 => CardQueue::DayLearn,
+            4 => CardQueue::PreviewRepeat,
+            -1 => CardQueue::Suspended,
+            -2 => CardQueue::SiblingBuried,
+            -3 => CardQueue::UserBuried,
+            other => return Err(FromSqlError::OutOfRange(other)),
+        })
+    }
+}
+
+impl ToSql for CardQueue {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::from(*self as i8))
+    }
+}
+
+pub(crate) fn row_to_card(row: &Row) -> rusqlite::Result<Card> {
+    Ok(Card {
+        id: row.get(0)?,
+        nid: row.get(1)?,
+        did: row.get(2)?,
+        ord: row.get(3)?,
+        mtime: row.get(4)?,
+        usn: row.get(5)?,
+        ctype: row.get(6)?,
+        queue: row.get(7)?,
+        due: row.get(8)?,
+        ivl: row.get(9)?,
+        factor: row.get(10)?,
+        reps: row.get(11)?,
+        lapses: row.get(12)?,
+        left: row.get(13)?,
+        odue: row.get(14)?,
+        odid: row.get(15)?,
+        flags: row.get(16)?,
+        data: row.get(17)?,
+    })
+}
+
+impl SqliteStorage {
+    pub(crate) fn get_card(&self, cid: i64) -> Result<Option<Card>> {
+        let mut stmt = self
+            .db
+            .prepare_cached(&format!("select {} from cards where id = ?", CARD_COLUMNS))?;
+        Ok(stmt.query_row(params![cid], row_to_card).optional()?)
+    }
+
+    pub(crate) fn update_card(&self, card: &Card) -> Result<()> {
+        let mut stmt = self.db.prepare_cached(
+            "update cards set nid = ?, did = ?, ord = ?, mod = ?, usn = ?, type = ?, \
+             queue = ?, due = ?, ivl = ?, factor = ?, reps = ?, lapses = ?, left = ?, \
+             odue = ?, odid = ?, flags = ?, data = ? where id = ?",
+        )?;
+        stmt.execute(params![
+            card.nid,
+            card.did,
+            card.ord,
+            card.mtime,
+            card.usn,
+            card.ctype,
+            card.queue,
+            card.due,
+            card.ivl,
+            card.factor,
+            card.reps,
+            card.lapses,
+            card.left,
+            card.odue,
+            card.odid,
+            card.flags,
+            card.data,
+            card.id,
+        ])?;
+        Ok(())
+    }
+
+    /// Add a card with the provided id.
+    #[cfg(test)]
+    pub(crate) fn add_card(&self, card: &Card) -> Result<()> {
+        self.db.execute(
+            &format!(
+                "insert into cards ({}) values (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+                CARD_COLUMNS
+            ),
+            params![
+                card.id,
+                card.nid,
+                card.did,
+                card.ord,
+                card.mtime,
+                card.usn,
+                card.ctype,
+                card.queue,
+                card.due,
+                card.ivl,
+                card.factor,
+                card.reps,
+                card.lapses,
+                card.left,
+                card.odue,
+                card.odid,
+                card.flags,
+                card.data,
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Siblings of a card that are new, or due for review or interday
+    /// learning on or before `today`, as (id, queue, home deck id).
+    pub(crate) fn due_siblings(
+        &self,
+        nid: i64,
+        cid: i64,
+        today: u32,
+    ) -> Result<Vec<(i64, CardQueue, i64)>> {
+        let mut stmt = self.db.prepare_cached(
+            "select id, queue, (case when odid != 0 then odid else did end) from cards \
+             where nid = ? and id != ? and (queue = ? or (queue in (?, ?) and due <= ?)) \
+             order by id",
+        )?;
+        let rows = stmt.query_map(
+            params![
+                nid,
+                cid,
+                CardQueue::New,
+                CardQueue::Review,
+                CardQueue::DayLearn,
+                today
+            ],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+        )?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    /// Move the provided cards into a different queue.
+    pub(crate) fn set_cards_queue(
+        &self,
+        cids: &[i64],
+        queue: CardQueue,
+        mtime: i64,
+        usn: i32,
+    ) -> Result<()> {
+        let mut stmt = self
+            .db
+            .prepare_cached("update cards set queue = ?, mod = ?, usn = ? where id = ?")?;
+        for cid in cids {
+            stmt.execute(params![queue, mtime, usn, cid])?;
+        }
+        Ok(())
+    }
+
+    // Queues
+    //////////////////////////////////////////
+
+    /// New cards in a deck in the order they should be shown, as
+    /// (id, nid, home deck id).
+    pub(crate) fn new_cards_in_deck(&self, did: i64, limit: u32) -> Result<Vec<(i64, i64, i64)>> {
+        let mut stmt = self.db.prepare_cached(
+            "select id, nid, (case when odid != 0 then odid else did end) from cards \
+             where did = ? and queue = ? order by due, ord limit ?",
+        )?;
+        let rows = stmt.query_map(params![did, CardQueue::New, limit], |row| {
+            Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+        })?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    /// Reviews due on or before `today` in the provided decks, as
+    /// (id, nid, home deck id). Cards due on the same day are shuffled.
+    pub(crate) fn due_reviews(
+        &self,
+        dids: &[i64],
+        today: u32,
+        limit: u32,
+    ) -> Result<Vec<(i64, i64, i64)>> {
+        let mut stmt = self.db.prepare(&format!(
+            "select id, nid, (case when odid != 0 then odid else did end) from cards \
+             where did in {} and queue = ? and due <= ? order by due, random() limit ?",
+            ids_to_string(dids)
+        ))?;
+        let rows = stmt.query_map(params![CardQueue::Review, today, limit], |row| {
+            Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+        })?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    /// Learning and preview cards due before the `cutoff` timestamp in the
+    /// provided decks, as (id, nid, home deck id, due), earliest first.
+    pub(crate) fn intraday_learning(
+        &self,
+        dids: &[i64],
+        cutoff: i64,
+        limit: u32,
+    ) -> Result<Vec<(i64, i64, i64, i64)>> {
+        let mut stmt = self.db.prepare(&format!(
+            "select id, nid, (case when odid != 0 then odid else did end), due from cards \
+             where did in {} and queue in (?, ?) and due < ? order by due limit ?",
+            ids_to_string(dids)
+        ))?;
+        let rows = stmt.query_map(
+            params![CardQueue::Learn, CardQueue::PreviewRepeat, cutoff, limit],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)),
+        )?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    /// Interday learning cards in a deck due on or before `today`, as
+    /// (id, nid, home deck id).
+    pub(crate) fn day_learning_in_deck(
+        &self,
+        did: i64,
+        today: u32,
+    ) -> Result<Vec<(i64, i64, i64)>> {
+        let mut stmt = self.db.prepare_cached(
+            "select id, nid, (case when odid != 0 then odid else did end) from cards \
+             where did = ? and queue = ? and due <= ? order by id",
+        )?;
+        let rows = stmt.query_map(params![did, CardQueue::DayLearn, today], |row| {
+            Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+        })?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    // Counts
+    //////////////////////////////////////////
+
+    /// The number of new cards in a deck, up to `limit`.
+    pub(crate) fn new_count_in_deck(&self, did: i64, limit: u32) -> Result<u32> {
+        let mut stmt = self.db.prepare_cached(
+            "select count() from (select 1 from cards where did = ? and queue = ? limit ?)",
+        )?;
+        Ok(stmt.query_row(params![did, CardQueue::New, limit], |row| row.get(0))?)
+    }
+
+    /// The number of learning cards in a deck: intraday cards due before
+    /// the `cutoff` timestamp, and interday cards due on or before `today`.
+    /// Each kind is counted up to `limit`.
+    pub(crate) fn learning_count_in_deck(
+        &self,
+        did: i64,
+        cutoff: i64,
+        today: u32,
+        limit: u32,
+    ) -> Result<u32> {
+        let mut stmt = self.db.prepare_cached(
+            "select count() from (select 1 from cards where did = ? and queue = ? \
+             and due < ? limit ?)",
+        )?;
+        let intraday: u32 = stmt
+            .query_row(params![did, CardQueue::Learn, cutoff, limit], |row| {
+                row.get(0)
+            })?;
+        let mut stmt = self.db.prepare_cached(
+            "select count() from (select 1 from cards where did = ? and queue = ? \
+             and due <= ? limit ?)",
+        )?;
+        let interday: u32 = stmt
+            .query_row(params![did, CardQueue::DayLearn, today, limit], |row| {
+                row.get(0)
+            })?;
+        Ok(intraday + interday)
+    }
+
+    /// The number of reviews due on or before `today` in the provided
+    /// decks, up to `limit`.
+    pub(crate) fn review_count_in_decks(
+        &self,
+        dids: &[i64],
+        today: u32,
+        limit: u32,
+    ) -> Result<u32> {
+        let mut stmt = self.db.prepare(&format!(
+            "select count() from (select 1 from cards where did in {} and queue = ? \
+             and due <= ? limit ?)",
+            ids_to_string(dids)
+        ))?;
+        Ok(stmt.query_row(params![CardQueue::Review, today, limit], |row| row.get(0))?)
+    }
+
+    /// When the first intraday learning or preview card in the provided
+    /// decks is due, or None if there are none.
+    pub(crate) fn next_learning_due(&self, dids: &[i64]) -> Result<Option<i64>> {
+        let mut stmt = self.db.prepare(&format!(
+            "select min(due) from cards where did in {} and queue in (?, ?)",
+            ids_to_string(dids)
+        ))?;
+        Ok(
+            stmt.query_row(params![CardQueue::Learn, CardQueue::PreviewRepeat], |row| {
+                row.get(0)
+            })?,
+        )
+    }
+
+    /// Whether any card in the provided decks is in `queue`.
+    pub(crate) fn have_cards_in_queue(&self, dids: &[i64], queue: CardQueue) -> Result<bool> {
+        let mut stmt = self.db.prepare(&format!(
+            "select 1 from cards where did in {} and queue = ? limit 1",
+            ids_to_string(dids)
+        ))?;
+        Ok(stmt
+            .query_row(params![queue], |row| row.get::<_, i64>(0))
+            .optional()?
+            .is_some())
+    }
+
+    /// The number of reviews and interday learning cards due on each of
+    /// the `days` days starting with `today`, as (did, queue, day, count).
+    /// Overdue cards are counted on day 0.
+    pub(crate) fn due_counts_by_day(
+        &self,
+        today: u32,
+        days: u32,
+    ) -> Result<Vec<(i64, CardQueue, u32, u32)>> {
+        let mut stmt = self.db.prepare_cached(
+            "select did, queue, max(due - ?1, 0) as day, count() from cards \
+             where queue in (?2, ?3) and due < ?1 + ?4 group by did, queue, day",
+        )?;
+        let rows = stmt.query_map(
+            params![today, CardQueue::Review, CardQueue::DayLearn, days],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)),
+        )?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    /// The number of reviews and interday learning cards due on each day
+    /// from `start` to `end` inclusive, keyed by day. Days with nothing due
+    /// are left out.
+    pub(crate) fn review_counts_between(&self, start: u32, end: u32) -> Result<HashMap<u32, u32>> {
+        let mut stmt = self.db.prepare_cached(
+            "select due, count() from cards where queue in (?, ?) \
+             and due between ? and ? group by due",
+        )?;
+        let rows = stmt.query_map(
+            params![CardQueue::Review, CardQueue::DayLearn, start, end],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    /// All cards in the given decks, including cards that have been moved
+    /// from them into filtered decks.
+    pub(crate) fn all_cards_in_decks(&self, dids: &[i64]) -> Result<Vec<Card>> {
+        let dids = ids_to_string(dids);
+        let mut stmt = self.db.prepare(&format!(
+            "select {} from cards where did in {} or odid in {}",
+            CARD_COLUMNS, dids, dids
+        ))?;
+        let cards = stmt.query_map(NO_PARAMS, row_to_card)?;
+        Ok(cards.collect::<rusqlite::Result<_>>()?)
+    }
+
+    /// Call `func` with each card in `dids`, or with every card if `dids`
+    /// is None.
+    pub(crate) fn for_each_card_in_decks<F>(&self, dids: Option<&[i64]>, mut func: F) -> Result<()>
+    where
+        F: FnMut(&Card),
+    {
+        let deck_limit = match dids {
+            Some(dids) => format!(" where did in {}", ids_to_string(dids)),
+            None => String::new(),
+        };
+        let mut stmt = self
+            .db
+            .prepare(&format!("select {} from cards{}", CARD_COLUMNS, deck_limit))?;
+        let mut rows = stmt.query(NO_PARAMS)?;
+        while let Some(row) = rows.next()? {
+            func(&row_to_card(row)?);
+        }
+        Ok(())
+    }
+
+    /// The number of intraday learning and preview cards in each deck.
+    pub(crate) fn intraday_learning_counts(&self) -> Result<HashMap<i64, u32>> {
+        let mut stmt = self
+            .db
+            .prepare_cached("select did, count() from cards where queue in (?, ?) group by did")?;
+        let rows = stmt.query_map(params![CardQueue::Learn, CardQueue::PreviewRepeat], |row| {
+            Ok((row.get(0)?, row.get(1)?))
+        })?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    /// The number of new cards in each deck.
+    pub(crate) fn new_counts(&self) -> Result<HashMap<i64, u32>> {
+        let mut stmt = self
+            .db
+            .prepare_cached("select did, count() from cards where queue = ? group by did")?;
+        let rows = stmt.query_map(params![CardQueue::New], |row| {
+            Ok((row.get(0)?, row.get(1)?))
+        })?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    /// The number of reviews due before `today` in each deck.
+    pub(crate) fn overdue_review_counts(&self, today: u32) -> Result<HashMap<i64, u32>> {
+        let mut stmt = self.db.prepare_cached(
+            "select did, count() from cards where queue = ? and due < ? group by did",
+        )?;
+        let rows = stmt.query_map(params![CardQueue::Review, today], |row| {
+            Ok((row.get(0)?, row.get(1)?))
+        })?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    // Filtered decks
+    //////////////////////////////////////////
+
+    /// Move cards into a filtered deck, remembering their home deck and
+    /// due number. Cards with a positive due number are given consecutive
+    /// numbers starting at `start_due`. In preview mode, cards are placed
+    /// in the review queue.
+    pub(crate) fn move_cards_to_filtered_deck(
+        &self,
+        cids: &[i64],
+        did: i64,
+        start_due: i32,
+        preview: bool,
+        usn: i32,
+    ) -> Result<()> {
+        let mut sql = String::from(
+            "update cards set odid = did, odue = due, did = ?, \
+             due = (case when due <= 0 then due else ? end), usn = ?",
+        );
+        if preview {
+            sql.push_str(&format!(", queue = {}", CardQueue::Review as i8));
+        }
+        sql.push_str(" where id = ?");
+        let mut stmt = self.db.prepare(&sql)?;
+        for (idx, cid) in cids.iter().enumerate() {
+            stmt.execute(params![did, start_due + idx as i32, usn, cid])?;
+        }
+        Ok(())
+    }
+
+    /// The ids of the cards of the provided notes.
+    pub(crate) fn card_ids_of_notes(&self, nids: &[i64]) -> Result<Vec<i64>> {
+        let mut stmt = self.db.prepare(&format!(
+            "select id from cards where nid in {} order by id",
+            ids_to_string(nids)
+        ))?;
+        let ids = stmt.query_map(NO_PARAMS, |row| row.get(0))?;
+        Ok(ids.collect::<rusqlite::Result<_>>()?)
+    }
+
+    /// The highest due number of a new card, or 0 if there are none.
+    pub(crate) fn max_new_position(&self) -> Result<i32> {
+        Ok(self
+            .db
+            .prepare_cached("select max(due) from cards where type = ?")?
+            .query_row(params![CardType::New], |row| row.get::<_, Option<i32>>(0))?
+            .unwrap_or(0))
+    }
+
+    /// Move new cards other than `excluding` at position `start` or later
+    /// `by` positions further back, returning the number of cards moved.
+    /// Cards in filtered decks have their original position moved.
+    pub(crate) fn shift_new_positions(
+        &self,
+        start: i32,
+        by: i32,
+        excluding: &[i64],
+        mtime: i64,
+        usn: i32,
+    ) -> Result<u32> {
+        let mut stmt = self.db.prepare(&format!(
+            "update cards set \
+             due = (case when odid = 0 then due + ?1 else due end), \
+             odue = (case when odid = 0 then odue else odue + ?1 end), \
+             mod = ?2, usn = ?3 \
+             where type = ?4 and id not in {} \
+             and (case when odid = 0 then due else odue end) >= ?5",
+            ids_to_string(excluding)
+        ))?;
+        Ok(stmt.execute(params![by, mtime, usn, CardType::New, start])? as u32)
+    }
+
+    /// The lowest position of a new card other than `excluding` at
+    /// position `start` or later.
+    pub(crate) fn min_new_position_from(
+        &self,
+        start: i32,
+        excluding: &[i64],
+    ) -> Result<Option<i32>> {
+        Ok(self
+            .db
+            .prepare(&format!(
+                "select min(case when odid = 0 then due else odue end) from cards \
+                 where type = ? and id not in {} \
+                 and (case when odid = 0 then due else odue end) >= ?",
+                ids_to_string(excluding)
+            ))?
+            .query_row(params![CardType::New, start], |row| row.get(0))?)
+    }
+
+    /// Move cards in the provided decks out of `queues` and back into the
+    /// queues their types call for. Returns the number of cards moved.
+    pub(crate) fn restore_buried_cards(
+        &self,
+        dids: &[i64],
+        queues: &[CardQueue],
+        mtime: i64,
+        usn: i32,
+    ) -> Result<u32> {
+        let queues: Vec<_> = queues.iter().map(|q| (*q as i8).to_string()).collect();
+        let count = self.db.execute(
+            &format!(
+                "update cards set queue = {}, mod = ?, usn = ? \
+                 where queue in ({}) and did in {}",
+                RESTORE_QUEUE_SQL,
+                queues.join(", "),
+                ids_to_string(dids)
+            ),
+            params![mtime, usn],
+        )?;
+        Ok(count as u32)
+    }
+
+    /// Return the cards in a filtered deck to their home decks, restoring
+    /// their original due numbers and queues. Returns the number of cards
+    /// returned.
+    pub(crate) fn empty_filtered_deck(&self, did: i64, usn: i32) -> Result<u32> {
+        Ok(self
+            .db
+            .prepare_cached(&format!(
+                "update cards set did = odid, queue = {}, \
+                 due = (case when odue > 0 then odue else due end), \
+                 odue = 0, odid = 0, usn = ? where did = ?",
+                RESTORE_QUEUE_SQL
+            ))?
+            .execute(params![usn, did])? as u32)
+    }
+
+    /// Move review cards out of relearning and back into the review queue,
+    /// due on their original due date if `keep_original_due` is set and
+    /// one was recorded, or an interval from `today` otherwise. Returns the
+    /// number of cards moved.
+    pub(crate) fn end_relearning(
+        &self,
+        today: u32,
+        keep_original_due: bool,
+        mtime: i64,
+        usn: i32,
+    ) -> Result<u32> {
+        let due = if keep_original_due {
+            "(case when odue > 0 then odue else ?1 + ivl end)"
+        } else {
+            "?1 + ivl"
+        };
+        Ok(self.db.execute(
+            &format!(
+                "update cards set due = {}, queue = ?2, type = ?3, odue = 0, \
+                 mod = ?4, usn = ?5 where queue in (?6, ?7) and type in (?3, ?8)",
+                due
+            ),
+            params![
+                today,
+                CardQueue::Review,
+                CardType::Review,
+                mtime,
+                usn,
+                CardQueue::Learn,
+                CardQueue::DayLearn,
+                CardType::Relearn
+            ],
+        )? as u32)
+    }
+
+    /// The ids of cards in the learning queues.
+    pub(crate) fn learning_card_ids(&self) -> Result<Vec<i64>> {
+        let mut stmt = self
+            .db
+            .prepare("select id from cards where queue in (?, ?) order by id")?;
+        let ids = stmt.query_map(params![CardQueue::Learn, CardQueue::DayLearn], |row| {
+            row.get(0)
+        })?;
+        Ok(ids.collect::<rusqlite::Result<_>>()?)
+    }
+
+    /// Turn suspended and buried learning cards into new cards, and
+    /// relearning cards into review cards, restoring their original due
+    /// number if they have one. Returns the number of cards changed.
+    pub(crate) fn reset_suspended_learning(&self, mtime: i64, usn: i32) -> Result<u32> {
+        Ok(self.db.execute(
+            "update cards set \
+             type = (case when type = ?1 then ?2 else ?3 end), \
+             due = (case when odue then odue else due end), odue = 0, \
+             mod = ?4, usn = ?5 where queue < 0 and type in (?1, ?6)",
+            params![
+                CardType::Learn,
+                CardType::New,
+                CardType::Review,
+                mtime,
+                usn,
+                CardType::Relearn
+            ],
+        )? as u32)
+    }
+
+    /// Move all cards in queue `from` to queue `to`, returning the number
+    /// of cards moved.
+    pub(crate) fn move_queue(
+        &self,
+        from: CardQueue,
+        to: CardQueue,
+        mtime: i64,
+        usn: i32,
+    ) -> Result<u32> {
+        Ok(self.db.execute(
+            "update cards set queue = ?, mod = ?, usn = ? where queue = ?",
+            params![to, mtime, usn, from],
+        )? as u32)
+    }
+}
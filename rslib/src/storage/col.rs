@@ -0,0 +1,73 @@
+//! The JSON values stored in the single row of the col table.
+
+use crate::config::Config;
+use crate::deckconf::DeckConf;
+use crate::decks::Deck;
+use crate::err::Result;
+use crate::notetypes::NoteType;
+use crate::storage::SqliteStorage;
+use rusqlite::{params, NO_PARAMS};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::collections::HashMap;
+
+impl SqliteStorage {
+    fn get_col_json<T: DeserializeOwned>(&self, column: &str) -> Result<T> {
+        let text: String =
+            self.db
+                .query_row(&format!("select {} from col", column), NO_PARAMS, |row| {
+                    row.get(0)
+                })?;
+        Ok(serde_json::from_str(&text)?)
+    }
+
+    fn set_col_json<T: Serialize>(&self, column: &str, value: &T) -> Result<()> {
+        let text = serde_json::to_string(value)?;
+        self.db
+            .execute(&format!("update col set {} = ?", column), params![text])?;
+        Ok(())
+    }
+
+    pub(crate) fn get_all_decks(&self) -> Result<HashMap<i64, Deck>> {
+        self.get_col_json("decks")
+    }
+
+    pub(crate) fn set_all_decks(&self, decks: &HashMap<i64, Deck>) -> Result<()> {
+        self.set_col_json("decks", decks)
+    }
+
+    pub(crate) fn get_all_deck_conf(&self) -> Result<HashMap<i64, DeckConf>> {
+        self.get_col_json("dconf")
+    }
+
+    #[cfg(test)]
+    pub(crate) fn set_all_deck_conf(&self, confs: &HashMap<i64, DeckConf>) -> Result<()> {
+        self.set_col_json("dconf", confs)
+    }
+
+    pub(crate) fn get_config(&self) -> Result<Config> {
+        self.get_col_json("conf")
+    }
+
+    pub(crate) fn set_config(&self, config: &Config) -> Result<()> {
+        self.set_col_json("conf", config)
+    }
+
+    pub(crate) fn get_all_note_types(&self) -> Result<HashMap<i64, NoteType>> {
+        self.get_col_json("models")
+    }
+
+    #[cfg(test)]
+    pub(crate) fn set_all_note_types(&self, note_types: &HashMap<i64, NoteType>) -> Result<()> {
+        self.set_col_json("models", note_types)
+    }
+
+    /// The tag registry, mapping each tag to the usn it was added with.
+    pub(crate) fn get_all_tags(&self) -> Result<HashMap<String, i32>> {
+        self.get_col_json("tags")
+    }
+
+    pub(crate) fn set_all_tags(&self, tags: &HashMap<String, i32>) -> Result<()> {
+        self.set_col_json("tags", tags)
+    }
+}
@@ -0,0 +1,35 @@
+mod card;
+mod col;
+mod note;
+mod revlog;
+mod session;
+mod sqlite;
+mod undo;
+
+pub(crate) use sqlite::SqliteStorage;
+pub(crate) use undo::UndoChanges;
+
+/// Write a list of ids as a parenthesized SQL list, eg '(1,2,3)'.
+pub(crate) fn ids_to_string(ids: &[i64]) -> String {
+    let mut buf = String::from("(");
+    for (idx, id) in ids.iter().enumerate() {
+        if idx > 0 {
+            buf.push(',');
+        }
+        buf.push_str(&id.to_string());
+    }
+    buf.push(')');
+    buf
+}
+
+#[cfg(test)]
+mod test {
+    use super::ids_to_string;
+
+    #[test]
+    fn test_ids_to_string() {
+        assert_eq!(ids_to_string(&[]), "()");
+        assert_eq!(ids_to_string(&[7]), "(7)");
+        assert_eq!(ids_to_string(&[7, 6]), "(7,6)");
+    }
+}
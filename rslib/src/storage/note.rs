@@ -0,0 +1,33 @@
+use crate::err::{AnkiError, Result};
+use crate::storage::SqliteStorage;
+use rusqlite::{params, OptionalExtension};
+
+impl SqliteStorage {
+    /// The space-separated tags of a note.
+    pub(crate) fn get_note_tags(&self, nid: i64) -> Result<String> {
+        self.db
+            .prepare_cached("select tags from notes where id = ?")?
+            .query_row(params![nid], |row| row.get(0))
+            .optional()?
+            .ok_or(AnkiError::NotFound)
+    }
+
+    pub(crate) fn set_note_tags(&self, nid: i64, tags: &str, mtime: i64, usn: i32) -> Result<()> {
+        self.db
+            .prepare_cached("update notes set tags = ?, mod = ?, usn = ? where id = ?")?
+            .execute(params![tags, mtime, usn, nid])?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+impl SqliteStorage {
+    /// Add a note with no fields, for testing.
+    pub(crate) fn add_test_note(&self, nid: i64, tags: &str) -> Result<()> {
+        self.db.execute(
+            "insert into notes values (?, '', 0, 0, 0, ?, '', '', 0, 0, '')",
+            params![nid, tags],
+        )?;
+        Ok(())
+    }
+}
@@ -0,0 +1,161 @@
+use crate::err::Result;
+use crate::revlog::{RevlogEntry, RevlogReviewKind};
+use crate::storage::{ids_to_string, SqliteStorage};
+use rusqlite::{params, OptionalExtension, NO_PARAMS};
+use std::collections::HashMap;
+
+impl SqliteStorage {
+    /// Add an entry to the review log. If an entry with the same id already
+    /// exists, the id is bumped until it is unique.
+    pub(crate) fn add_revlog_entry(&self, entry: &RevlogEntry) -> Result<i64> {
+        let mut id = entry.id;
+        let mut stmt = self
+            .db
+            .prepare_cached("select 1 from revlog where id = ?")?;
+        while stmt
+            .query_row(params![id], |row| row.get::<_, i64>(0))
+            .optional()?
+            .is_some()
+        {
+            id += 1;
+        }
+        self.insert_revlog_entry(id, entry)?;
+        Ok(id)
+    }
+
+    /// Add many entries to the review log. As with `add_revlog_entry`,
+    /// each keeps its id unless that is taken, so entries logged in the
+    /// same millisecond are given the ids after it.
+    pub(crate) fn add_revlog_entries(&self, entries: &[RevlogEntry]) -> Result<()> {
+        for entry in entries {
+            self.add_revlog_entry(entry)?;
+        }
+        Ok(())
+    }
+
+    fn insert_revlog_entry(&self, id: i64, entry: &RevlogEntry) -> Result<()> {
+        self.db
+            .prepare_cached("insert into revlog values (?, ?, ?, ?, ?, ?, ?, ?, ?)")?
+            .execute(params![
+                id,
+                entry.cid,
+                entry.usn,
+                entry.ease,
+                entry.interval,
+                entry.last_interval,
+                entry.factor,
+                entry.taken_millis,
+                entry.kind as u8,
+            ])?;
+        Ok(())
+    }
+
+    /// Call `func` with each entry logged at or after `since_millis`,
+    /// oldest first, for the cards in `dids` and the cards moved from them
+    /// into filtered decks, or for all cards if `dids` is None. Entries of
+    /// an unknown type are skipped.
+    pub(crate) fn for_each_revlog_entry_since<F>(
+        &self,
+        since_millis: i64,
+        dids: Option<&[i64]>,
+        mut func: F,
+    ) -> Result<()>
+    where
+        F: FnMut(&RevlogEntry),
+    {
+        let deck_limit = match dids {
+            Some(dids) => {
+                let dids = ids_to_string(dids);
+                format!(
+                    " and cid in (select id from cards where did in {} or odid in {})",
+                    dids, dids
+                )
+            }
+            None => String::new(),
+        };
+        let mut stmt = self.db.prepare(&format!(
+            "select id, cid, usn, ease, ivl, lastIvl, factor, time, type from revlog \
+             where id >= ?{} order by id",
+            deck_limit
+        ))?;
+        let mut rows = stmt.query(params![since_millis])?;
+        while let Some(row) = rows.next()? {
+            if let Some(kind) = RevlogReviewKind::from_u8(row.get(8)?) {
+                func(&RevlogEntry {
+                    id: row.get(0)?,
+                    cid: row.get(1)?,
+                    usn: row.get(2)?,
+                    ease: row.get(3)?,
+                    interval: row.get(4)?,
+                    last_interval: row.get(5)?,
+                    factor: row.get(6)?,
+                    taken_millis: row.get(7)?,
+                    kind,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Call `func` with the cid, id, ease, last interval and type of each
+    /// review of the cards in `dids`, ordered by card and then time. Cards
+    /// moved from the decks into filtered decks are included.
+    pub(crate) fn for_each_revlog_in_decks<F>(&self, dids: &[i64], mut func: F) -> Result<()>
+    where
+        F: FnMut(i64, i64, u8, i32, u8),
+    {
+        let dids = ids_to_string(dids);
+        let mut stmt = self.db.prepare(&format!(
+            "select cid, id, ease, lastIvl, type from revlog where cid in \
+             (select id from cards where did in {} or odid in {}) order by cid, id",
+            dids, dids
+        ))?;
+        let mut rows = stmt.query(NO_PARAMS)?;
+        while let Some(row) = rows.next()? {
+            func(
+                row.get(0)?,
+                row.get(1)?,
+                row.get(2)?,
+                row.get(3)?,
+                row.get(4)?,
+            );
+        }
+        Ok(())
+    }
+
+    /// The times of the lapses recorded for each of `cids`, in milliseconds,
+    /// oldest first. Cards without lapses are left out.
+    pub(crate) fn lapse_times(&self, cids: &[i64]) -> Result<HashMap<i64, Vec<i64>>> {
+        let mut stmt = self.db.prepare(&format!(
+            "select cid, id from revlog where cid in {} and ease = 1 and type in ({}, {}) \
+             order by id",
+            ids_to_string(cids),
+            RevlogReviewKind::Review as u8,
+            RevlogReviewKind::EarlyReview as u8
+        ))?;
+        let mut rows = stmt.query(NO_PARAMS)?;
+        let mut times: HashMap<i64, Vec<i64>> = HashMap::new();
+        while let Some(row) = rows.next()? {
+            times.entry(row.get(0)?).or_default().push(row.get(1)?);
+        }
+        Ok(times)
+    }
+
+    /// Add `by` to the answer buttons of learning and relearning entries
+    /// that used one of `eases`, returning the number of entries changed.
+    /// The schedulers have different numbers of learning buttons.
+    pub(crate) fn remap_learning_eases(&self, eases: &[u8], by: i8) -> Result<u32> {
+        let eases: Vec<String> = eases.iter().map(ToString::to_string).collect();
+        Ok(self.db.execute(
+            &format!(
+                "update revlog set ease = ease + ? where ease in ({}) and type in (?, ?)",
+                eases.join(", ")
+            ),
+            params![
+                by,
+                RevlogReviewKind::Learning as u8,
+                RevlogReviewKind::Relearning as u8
+            ],
+        )? as u32)
+    }
+}
@@ -0,0 +1,82 @@
+//! Study sessions, which are kept in their own table rather than the
+//! collection config, so they are not loaded and synced with it.
+
+use crate::err::Result;
+use crate::sched::session::StudySession;
+use crate::storage::SqliteStorage;
+use rusqlite::{params, Row};
+
+fn row_to_session(row: &Row) -> Result<StudySession> {
+    let text: String = row.get(0)?;
+    Ok(serde_json::from_str(&text)?)
+}
+
+impl SqliteStorage {
+    /// Create the sessions table if the collection does not have one yet.
+    pub(crate) fn ensure_study_sessions_table(&self) -> Result<()> {
+        self.db.execute_batch(
+            "create table if not exists study_sessions (
+              id integer primary key,
+              day integer not null,
+              data text not null
+            )",
+        )?;
+        Ok(())
+    }
+
+    /// Add a session, or replace it if it has been saved before.
+    pub(crate) fn save_study_session(&self, session: &StudySession) -> Result<()> {
+        self.db
+            .prepare_cached("insert or replace into study_sessions values (?, ?, ?)")?
+            .execute(params![
+                session.started_at,
+                session.day,
+                serde_json::to_string(session)?
+            ])?;
+        Ok(())
+    }
+
+    /// Remove the sessions that started before `day`.
+    pub(crate) fn remove_study_sessions_before(&self, day: u32) -> Result<()> {
+        self.db
+            .execute("delete from study_sessions where day < ?", params![day])?;
+        Ok(())
+    }
+
+    /// The sessions that started on `day`, oldest first.
+    pub(crate) fn get_study_sessions_on(&self, day: u32) -> Result<Vec<StudySession>> {
+        let mut stmt = self
+            .db
+            .prepare_cached("select data from study_sessions where day = ? order by id")?;
+        let mut rows = stmt.query(params![day])?;
+        let mut sessions = vec![];
+        while let Some(row) = rows.next()? {
+            sessions.push(row_to_session(row)?);
+        }
+        Ok(sessions)
+    }
+
+    /// The latest `limit` sessions, latest first.
+    pub(crate) fn get_latest_study_sessions(&self, limit: u32) -> Result<Vec<StudySession>> {
+        let mut stmt = self
+            .db
+            .prepare_cached("select data from study_sessions order by id desc limit ?")?;
+        let mut rows = stmt.query(params![limit])?;
+        let mut sessions = vec![];
+        while let Some(row) = rows.next()? {
+            sessions.push(row_to_session(row)?);
+        }
+        Ok(sessions)
+    }
+
+    #[cfg(test)]
+    pub(crate) fn study_session_days(&self) -> Result<Vec<u32>> {
+        let mut stmt = self
+            .db
+            .prepare("select day from study_sessions order by id")?;
+        let days = stmt
+            .query_map(rusqlite::NO_PARAMS, |row| row.get(0))?
+            .collect::<rusqlite::Result<_>>()?;
+        Ok(days)
+    }
+}
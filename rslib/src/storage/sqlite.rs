@@ -0,0 +1,111 @@
+use crate::err::{AnkiError, Result};
+use rusqlite::{params, Connection, OptionalExtension, NO_PARAMS};
+use std::path::Path;
+use std::time::Duration;
+
+const SCHEMA_VERSION: u8 = 11;
+
+/// A connection to a collection file.
+pub(crate) struct SqliteStorage {
+    pub(crate) db: Connection,
+}
+
+fn open_or_create_collection_db(path: &Path) -> Result<Connection> {
+    let db = Connection::open(path)?;
+
+    // the Python code may be holding a write lock on the same file
+    db.busy_timeout(Duration::from_secs(10))?;
+    db.pragma_update(None, "temp_store", &"memory")?;
+    db.pragma_update(None, "cache_size", &10_000)?;
+
+    Ok(db)
+}
+
+impl SqliteStorage {
+    /// Open the collection at `path`, creating an empty one if it does not
+    /// exist yet.
+    pub(crate) fn open_or_create(path: &Path) -> Result<Self> {
+        let db = open_or_create_collection_db(path)?;
+
+        let exists: bool = db
+            .query_row(
+                "select 1 from sqlite_master where type = 'table' and name = 'col'",
+                NO_PARAMS,
+                |row| row.get::<_, i64>(0),
+            )
+            .optional()?
+            .is_some();
+        if !exists {
+            db.execute_batch("begin exclusive")?;
+            db.execute_batch(include_str!("schema11.sql"))?;
+            db.execute_batch("commit")?;
+        }
+
+        let ver: u8 = db.query_row("select ver from col", NO_PARAMS, |row| row.get(0))?;
+        if ver != SCHEMA_VERSION {
+            return Err(AnkiError::db_error(format!(
+                "unsupported schema version {}",
+                ver
+            )));
+        }
+
+        let storage = SqliteStorage { db };
+        storage.ensure_study_sessions_table()?;
+        Ok(storage)
+    }
+
+    // Transactions
+    //////////////////////////////////////////
+
+    pub(crate) fn begin(&self) -> Result<()> {
+        self.db.execute_batch("begin immediate")?;
+        Ok(())
+    }
+
+    pub(crate) fn commit(&self) -> Result<()> {
+        if !self.db.is_autocommit() {
+            self.db.execute_batch("commit")?;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn rollback(&self) -> Result<()> {
+        if !self.db.is_autocommit() {
+            self.db.execute_batch("rollback")?;
+        }
+        Ok(())
+    }
+
+    // Collection-wide values
+    //////////////////////////////////////////
+
+    /// Timestamp the collection was created at.
+    pub(crate) fn creation_stamp(&self) -> Result<i64> {
+        Ok(self
+            .db
+            .query_row("select crt from col", NO_PARAMS, |row| row.get(0))?)
+    }
+
+    /// The usn of the collection, which is used for changes made on the
+    /// sync server.
+    pub(crate) fn server_usn(&self) -> Result<i32> {
+        Ok(self
+            .db
+            .query_row("select usn from col", NO_PARAMS, |row| row.get(0))?)
+    }
+
+    /// Bump the collection's modification time, in milliseconds.
+    pub(crate) fn mark_modified(&self, mtime_millis: i64) -> Result<()> {
+        self.db
+            .execute("update col set mod = ?", params![mtime_millis])?;
+        Ok(())
+    }
+
+    /// Bump the collection's schema modification time, in milliseconds,
+    /// so that the next sync is a full sync.
+    pub(crate) fn mark_schema_modified(&self, mtime_millis: i64) -> Result<()> {
+        self.db
+            .execute("update col set scm = ?", params![mtime_millis])?;
+        Ok(())
+    }
+}
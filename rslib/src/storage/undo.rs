@@ -0,0 +1,194 @@
+//! Recording changes to rows so they can be reverted.
+//!
+//! Temporary triggers on the tables holding cards, notes, the review log
+//! and graves write a statement that reverses each insert, update and
+//! delete to the `undo_log` table. Changes to the decks, which hold the
+//! counts of cards studied today, are recorded the same way. As the
+//! triggers see every change made through this connection, replaying the
+//! statements of an undo step records the statements that redo it.
+//!
+//! The statements restore whole rows, so the state each changed row was
+//! left in is kept too. If a row has since been changed elsewhere, such as
+//! by the Python code, the step can't be replayed without losing that
+//! change.
+
+use crate::err::Result;
+use crate::storage::SqliteStorage;
+use rusqlite::{params, OptionalExtension, NO_PARAMS};
+use std::collections::HashMap;
+
+/// The tables whose rows are recorded, and the columns recorded, or None
+/// for all of them. Only updates are recorded for tables with a column
+/// list.
+const TRACKED_TABLES: &[(&str, Option<&[&str]>)] = &[
+    ("cards", None),
+    ("notes", None),
+    ("revlog", None),
+    ("graves", None),
+    ("col", Some(&["decks"])),
+];
+
+/// The statements that revert an operation, and the rows it changed.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct UndoChanges {
+    statements: Vec<String>,
+    /// Each changed row as (table, rowid, the quoted values of its recorded
+    /// columns, or None if it was deleted).
+    rows: Vec<(String, i64, Option<String>)>,
+}
+
+impl UndoChanges {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.statements.is_empty()
+    }
+}
+
+/// An SQL expression listing the values of the columns of `row`, such as
+/// `old` in a trigger or a table in a query, quoted for use in a
+/// statement, as `name=value` if `with_names` is set.
+fn quoted_columns(columns: &[String], row: &str, with_names: bool) -> String {
+    columns
+        .iter()
+        .map(|column| {
+            let name = if with_names {
+                format!("{}=", column)
+            } else {
+                String::new()
+            };
+            format!("'{}' || quote({}.{})", name, row, column)
+        })
+        .collect::<Vec<_>>()
+        .join(" || ',' || ")
+}
+
+fn update_trigger(table: &str, columns: &[String], only_columns: bool) -> String {
+    format!(
+        "create temp trigger {table}_undo_update after update {of} on main.{table} begin \
+         insert into undo_log (sql, tbl, rid) values ('update {table} set ' || {assignments} \
+         || ' where rowid=' || new.rowid, '{table}', new.rowid); end;",
+        table = table,
+        of = if only_columns {
+            format!("of {}", columns.join(","))
+        } else {
+            String::new()
+        },
+        assignments = quoted_columns(columns, "old", true),
+    )
+}
+
+fn insert_and_delete_triggers(table: &str, columns: &[String]) -> String {
+    format!(
+        "create temp trigger {table}_undo_insert after insert on main.{table} begin \
+         insert into undo_log (sql, tbl, rid) values \
+         ('delete from {table} where rowid=' || new.rowid, '{table}', new.rowid); end;
+         create temp trigger {table}_undo_delete before delete on main.{table} begin \
+         insert into undo_log (sql, tbl, rid) values ('insert into {table} (rowid,{names}) \
+         values (' || old.rowid || ',' || {values} || ')', '{table}', old.rowid); end;",
+        table = table,
+        names = columns.join(","),
+        values = quoted_columns(columns, "old", false),
+    )
+}
+
+impl SqliteStorage {
+    /// Start recording changes to the tracked tables.
+    pub(crate) fn begin_undo_tracking(&self) -> Result<()> {
+        self.db.execute_batch(
+            "create temp table if not exists undo_log (seq integer primary key autoincrement, \
+             sql text not null, tbl text not null, rid integer not null)",
+        )?;
+        for (table, only_columns) in TRACKED_TABLES {
+            let columns = self.tracked_columns(table)?;
+            self.db
+                .execute_batch(&update_trigger(table, &columns, only_columns.is_some()))?;
+            if only_columns.is_none() {
+                self.db
+                    .execute_batch(&insert_and_delete_triggers(table, &columns))?;
+            }
+        }
+        Ok(())
+    }
+
+    fn tracked_columns(&self, table: &str) -> Result<Vec<String>> {
+        let only_columns = TRACKED_TABLES
+            .iter()
+            .find(|(name, _)| *name == table)
+            .and_then(|(_, columns)| *columns);
+        Ok(match only_columns {
+            Some(columns) => columns.iter().map(|column| column.to_string()).collect(),
+            None => self
+                .db
+                .prepare(&format!("pragma main.table_info({})", table))?
+                .query_map(NO_PARAMS, |row| row.get(1))?
+                .collect::<rusqlite::Result<_>>()?,
+        })
+    }
+
+    /// The quoted values of the recorded columns of a row, or None if there
+    /// is no such row. The query for each table is kept in `queries`.
+    fn row_state(
+        &self,
+        queries: &mut HashMap<String, String>,
+        table: &str,
+        rowid: i64,
+    ) -> Result<Option<String>> {
+        if !queries.contains_key(table) {
+            let columns = self.tracked_columns(table)?;
+            let sql = format!(
+                "select {} from {} where rowid = ?",
+                quoted_columns(&columns, table, false),
+                table
+            );
+            queries.insert(table.to_string(), sql);
+        }
+        Ok(self
+            .db
+            .prepare_cached(&queries[table])?
+            .query_row(params![rowid], |row| row.get(0))
+            .optional()?)
+    }
+
+    /// Remove and return the statements that reverse the changes recorded
+    /// since the log was last taken, in the order they were recorded, with
+    /// the current state of the rows they change.
+    pub(crate) fn take_undo_log(&self) -> Result<UndoChanges> {
+        let statements = self
+            .db
+            .prepare("select sql from undo_log order by seq")?
+            .query_map(NO_PARAMS, |row| row.get(0))?
+            .collect::<rusqlite::Result<_>>()?;
+        let changed: Vec<(String, i64)> = self
+            .db
+            .prepare("select distinct tbl, rid from undo_log")?
+            .query_map(NO_PARAMS, |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<rusqlite::Result<_>>()?;
+        let mut queries = HashMap::new();
+        let mut rows = Vec::with_capacity(changed.len());
+        for (table, rowid) in changed {
+            let state = self.row_state(&mut queries, &table, rowid)?;
+            rows.push((table, rowid, state));
+        }
+        self.db.execute("delete from undo_log", NO_PARAMS)?;
+        Ok(UndoChanges { statements, rows })
+    }
+
+    /// True if the rows changed by `changes` are still in the state the
+    /// changes were taken in.
+    pub(crate) fn undo_rows_unchanged(&self, changes: &UndoChanges) -> Result<bool> {
+        let mut queries = HashMap::new();
+        for (table, rowid, state) in &changes.rows {
+            if self.row_state(&mut queries, table, *rowid)? != *state {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    /// Run statements taken from the undo log, latest first.
+    pub(crate) fn replay_undo_log(&self, changes: &UndoChanges) -> Result<()> {
+        for sql in changes.statements.iter().rev() {
+            self.db.execute(sql, NO_PARAMS)?;
+        }
+        Ok(())
+    }
+}
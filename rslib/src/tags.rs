@@ -0,0 +1,111 @@
+use crate::collection::Collection;
+use crate::err::Result;
+use std::collections::{BTreeSet, HashMap};
+
+/// Split a space-separated tag string.
+pub fn split_tags(tags: &str) -> impl Iterator<Item = &str> {
+    tags.split(&[' ', '\u{3000}'][..])
+        .filter(|tag| !tag.is_empty())
+}
+
+/// Join tags into a string with leading and trailing spaces, as stored in
+/// the notes table.
+pub fn join_tags<S: AsRef<str>>(tags: &[S]) -> String {
+    if tags.is_empty() {
+        return "".to_string();
+    }
+    let joined: Vec<_> = tags.iter().map(AsRef::as_ref).collect();
+    format!(" {} ", joined.join(" "))
+}
+
+/// Strip quotes and duplicates, adjust case to match already registered
+/// tags, and sort.
+pub fn canonify_tags<'a, I>(tags: I, registry: &HashMap<String, i32>) -> Vec<String>
+where
+    I: IntoIterator<Item = &'a str>,
+{
+    tags.into_iter()
+        .map(|tag| {
+            let stripped = tag.replace(&['"', '\''][..], "");
+            let lower = stripped.to_lowercase();
+            registry
+                .keys()
+                .find(|existing| existing.to_lowercase() == lower)
+                .cloned()
+                .unwrap_or(stripped)
+        })
+        .filter(|tag| !tag.is_empty())
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect()
+}
+
+/// Add `tag` to a tag string if it is not already present, ignoring case.
+/// Returns None if the tag was already there.
+pub fn add_tag_to_str(tags: &str, tag: &str, registry: &HashMap<String, i32>) -> Option<String> {
+    let lower = tag.to_lowercase();
+    if split_tags(tags).any(|t| t.to_lowercase() == lower) {
+        return None;
+    }
+    let canonified = canonify_tags(split_tags(tags).chain(std::iter::once(tag)), registry);
+    Some(join_tags(&canonified))
+}
+
+impl Collection {
+    /// Add a tag to a note, registering it if it is new.
+    /// Returns false if the note already had the tag.
+    pub(crate) fn add_tag_to_note(
+        &mut self,
+        nid: i64,
+        tag: &str,
+        mtime: i64,
+        usn: i32,
+    ) -> Result<bool> {
+        let mut registry = self.storage.get_all_tags()?;
+        let tags = self.storage.get_note_tags(nid)?;
+        let updated = match add_tag_to_str(&tags, tag, &registry) {
+            Some(updated) => updated,
+            None => return Ok(false),
+        };
+        self.storage.set_note_tags(nid, &updated, mtime, usn)?;
+
+        let lower = tag.to_lowercase();
+        if !registry.keys().any(|t| t.to_lowercase() == lower) {
+            registry.insert(tag.to_string(), usn);
+            self.storage.set_all_tags(&registry)?;
+        }
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{add_tag_to_str, split_tags};
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_tags() {
+        let mut registry = HashMap::new();
+        assert_eq!(
+            split_tags(" one  two\u{3000}three ").collect::<Vec<_>>(),
+            vec!["one", "two", "three"]
+        );
+
+        assert_eq!(
+            add_tag_to_str("", "leech", &registry),
+            Some(" leech ".into())
+        );
+        assert_eq!(add_tag_to_str(" Leech ", "leech", &registry), None);
+        assert_eq!(
+            add_tag_to_str(" zed b b ", "leech", &registry),
+            Some(" b leech zed ".into())
+        );
+
+        // case follows the registry
+        registry.insert("Leech".to_string(), 0);
+        assert_eq!(
+            add_tag_to_str(" a ", "leech", &registry),
+            Some(" Leech a ".into())
+        );
+    }
+}
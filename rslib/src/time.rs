@@ -0,0 +1,12 @@
+use std::time;
+
+pub(crate) fn i64_unix_secs() -> i64 {
+    i64_unix_millis() / 1000
+}
+
+pub(crate) fn i64_unix_millis() -> i64 {
+    time::SystemTime::now()
+        .duration_since(time::SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as i64
+}
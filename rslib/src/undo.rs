@@ -0,0 +1,286 @@
+//! Undoing and redoing operations.
+//!
+//! Each operation run with `Collection::transact` records the statements
+//! that revert its changes to cards, notes, the review log, graves and the
+//! decks' counts of cards studied today. Changes made outside the backend,
+//! such as by the Python code holding its own connection, are not
+//! recorded; if they touch a row an operation changed, that operation and
+//! the ones before it can no longer be undone.
+
+use crate::collection::Collection;
+use crate::err::{AnkiError, Result};
+use crate::storage::UndoChanges;
+use crate::time::i64_unix_millis;
+use std::collections::VecDeque;
+
+/// The number of operations that can be undone.
+const UNDO_LIMIT: usize = 30;
+
+/// An operation that can be undone.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UndoableOp {
+    AnswerCard,
+    Bury,
+    Unbury,
+    Suspend,
+    Unsuspend,
+    Forget,
+    Reschedule,
+    ResetCards,
+    SetDueDate,
+    Reposition,
+    RebuildFilteredDeck,
+    EmptyFilteredDeck,
+}
+
+impl UndoableOp {
+    /// The name of the operation, as shown to the user.
+    pub fn name(self) -> &'static str {
+        match self {
+            UndoableOp::AnswerCard => "Review",
+            UndoableOp::Bury => "Bury",
+            UndoableOp::Unbury => "Unbury",
+            UndoableOp::Suspend => "Suspend",
+            UndoableOp::Unsuspend => "Unsuspend",
+            UndoableOp::Forget => "Forget",
+            UndoableOp::Reschedule => "Reschedule",
+            UndoableOp::ResetCards => "Reset Cards",
+            UndoableOp::SetDueDate => "Set Due Date",
+            UndoableOp::Reposition => "Reposition",
+            UndoableOp::RebuildFilteredDeck => "Rebuild",
+            UndoableOp::EmptyFilteredDeck => "Empty",
+        }
+    }
+}
+
+/// An operation and the statements that revert it.
+#[derive(Debug)]
+struct UndoStep {
+    op: UndoableOp,
+    changes: UndoChanges,
+}
+
+/// The operations that can be undone and redone, latest last.
+#[derive(Debug, Default)]
+pub(crate) struct UndoManager {
+    undo_steps: VecDeque<UndoStep>,
+    redo_steps: Vec<UndoStep>,
+}
+
+impl UndoManager {
+    /// Record the changes of an operation that has been committed. An
+    /// operation that changed nothing is not recorded, and one that can't
+    /// be undone clears the history.
+    pub(crate) fn record(&mut self, op: Option<UndoableOp>, changes: UndoChanges) {
+        let op = match op {
+            Some(op) => op,
+            None => {
+                self.clear();
+                return;
+            }
+        };
+        if changes.is_empty() {
+            return;
+        }
+        self.redo_steps.clear();
+        self.undo_steps.push_back(UndoStep { op, changes });
+        if self.undo_steps.len() > UNDO_LIMIT {
+            self.undo_steps.pop_front();
+        }
+    }
+
+    fn clear(&mut self) {
+        self.undo_steps.clear();
+        self.redo_steps.clear();
+    }
+}
+
+/// The operations that would be undone and redone next.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UndoStatus {
+    pub undo: Option<UndoableOp>,
+    pub redo: Option<UndoableOp>,
+}
+
+impl Collection {
+    pub fn undo_status(&self) -> UndoStatus {
+        UndoStatus {
+            undo: self.undo.undo_steps.back().map(|step| step.op),
+            redo: self.undo.redo_steps.last().map(|step| step.op),
+        }
+    }
+
+    /// Revert the latest operation, returning it, or None if there is
+    /// nothing to undo.
+    pub fn undo(&mut self) -> Result<Option<UndoableOp>> {
+        let step = match self.undo.undo_steps.pop_back() {
+            Some(step) => step,
+            None => return Ok(None),
+        };
+        match self.replay_changes(&step.changes) {
+            Ok(Some(changes)) => {
+                let op = step.op;
+                self.undo.redo_steps.push(UndoStep { op, changes });
+                Ok(Some(op))
+            }
+            Ok(None) => {
+                self.undo.clear();
+                Err(modified_elsewhere())
+            }
+            Err(err) => {
+                self.undo.undo_steps.push_back(step);
+                Err(err)
+            }
+        }
+    }
+
+    /// Make the latest undone operation again, returning it, or None if
+    /// there is nothing to redo.
+    pub fn redo(&mut self) -> Result<Option<UndoableOp>> {
+        let step = match self.undo.redo_steps.pop() {
+            Some(step) => step,
+            None => return Ok(None),
+        };
+        match self.replay_changes(&step.changes) {
+            Ok(Some(changes)) => {
+                let op = step.op;
+                self.undo.undo_steps.push_back(UndoStep { op, changes });
+                Ok(Some(op))
+            }
+            Ok(None) => {
+                self.undo.clear();
+                Err(modified_elsewhere())
+            }
+            Err(err) => {
+                self.undo.redo_steps.push(step);
+                Err(err)
+            }
+        }
+    }
+
+    /// Run recorded statements in a transaction, returning the statements
+    /// that revert them. If the rows they change have been changed since,
+    /// nothing is run and None is returned.
+    fn replay_changes(&mut self, changes: &UndoChanges) -> Result<Option<UndoChanges>> {
+        self.storage.begin()?;
+        let res = self.storage.take_undo_log().and_then(|_| {
+            if !self.storage.undo_rows_unchanged(changes)? {
+                return Ok(None);
+            }
+            self.storage.replay_undo_log(changes)?;
+            let changes = self.storage.take_undo_log()?;
+            self.storage.mark_modified(i64_unix_millis())?;
+            self.storage.commit()?;
+            Ok(Some(changes))
+        });
+        match res {
+            Ok(Some(_)) => (),
+            _ => self.storage.rollback()?,
+        }
+        res
+    }
+}
+
+fn modified_elsewhere() -> AnkiError {
+    AnkiError::invalid_input("the changes have been modified elsewhere and can't be undone")
+}
+
+#[cfg(test)]
+mod test {
+    use super::{UndoableOp, UNDO_LIMIT};
+    use crate::card::{Card, CardQueue, CardType};
+    use crate::collection::{open_test_collection, Collection};
+    use crate::sched::Answer;
+    use rusqlite::NO_PARAMS;
+
+    fn revlog_count(col: &Collection) -> u32 {
+        col.storage
+            .db
+            .query_row("select count() from revlog", NO_PARAMS, |row| row.get(0))
+            .unwrap()
+    }
+
+    #[test]
+    fn test_undo_redo() {
+        let mut col = open_test_collection();
+        let today = col.timing_today().unwrap().days_elapsed as i32;
+        col.storage.add_test_note(1, "").unwrap();
+        let card = Card {
+            id: 1,
+            nid: 1,
+            ctype: CardType::Review,
+            queue: CardQueue::Review,
+            due: today,
+            ivl: 10,
+            factor: 2500,
+            ..Default::default()
+        };
+        col.storage.add_card(&card).unwrap();
+        let status = col.undo_status();
+        assert_eq!((status.undo, status.redo), (None, None));
+        let decks = col.storage.get_all_decks().unwrap();
+
+        col.answer_card(1, Answer::Good, 1000).unwrap();
+        let answered_decks = col.storage.get_all_decks().unwrap();
+        assert_eq!(answered_decks[&1].rev_today.1, 1);
+        col.suspend_cards(&[1]).unwrap();
+        // operations that change nothing are not recorded
+        col.suspend_cards(&[1]).unwrap();
+        let answered = col.storage.get_card(1).unwrap().unwrap();
+        assert_eq!(answered.queue, CardQueue::Suspended);
+        assert_eq!(col.undo_status().undo, Some(UndoableOp::Suspend));
+
+        assert_eq!(col.undo().unwrap(), Some(UndoableOp::Suspend));
+        assert_eq!(col.undo().unwrap(), Some(UndoableOp::AnswerCard));
+        assert_eq!(col.undo().unwrap(), None);
+        assert_eq!(col.storage.get_card(1).unwrap().unwrap(), card);
+        assert_eq!(revlog_count(&col), 0);
+        // the review no longer counts towards today's limits
+        assert_eq!(col.storage.get_all_decks().unwrap(), decks);
+        let status = col.undo_status();
+        assert_eq!(
+            (status.undo, status.redo),
+            (None, Some(UndoableOp::AnswerCard))
+        );
+
+        assert_eq!(col.redo().unwrap(), Some(UndoableOp::AnswerCard));
+        assert_eq!(col.redo().unwrap(), Some(UndoableOp::Suspend));
+        assert_eq!(col.redo().unwrap(), None);
+        assert_eq!(col.storage.get_card(1).unwrap().unwrap(), answered);
+        assert_eq!(revlog_count(&col), 1);
+        assert_eq!(col.storage.get_all_decks().unwrap(), answered_decks);
+
+        // a new operation clears the redo steps
+        col.undo().unwrap();
+        col.bury_cards(&[1], true).unwrap();
+        assert_eq!(col.undo_status().redo, None);
+
+        // only the latest operations are kept
+        for _ in 0..UNDO_LIMIT {
+            col.suspend_cards(&[1]).unwrap();
+            col.unsuspend_cards(&[1]).unwrap();
+        }
+        let mut count = 0;
+        while col.undo().unwrap().is_some() {
+            count += 1;
+        }
+        assert_eq!(count, UNDO_LIMIT);
+
+        // changes made elsewhere to the same rows are not reverted
+        col.suspend_cards(&[1]).unwrap();
+        col.unsuspend_cards(&[1]).unwrap();
+        col.storage
+            .db
+            .execute("update cards set flags = 1 where id = 1", NO_PARAMS)
+            .unwrap();
+        assert!(col.undo().is_err());
+        assert_eq!(col.storage.get_card(1).unwrap().unwrap().flags, 1);
+        let status = col.undo_status();
+        assert_eq!((status.undo, status.redo), (None, None));
+
+        // changing the scheduler clears the history
+        col.suspend_cards(&[1]).unwrap();
+        col.change_scheduler_version(2, false).unwrap();
+        assert_eq!(col.undo_status().undo, None);
+    }
+}